@@ -1,77 +1,121 @@
+use crate::github_issues::COMMENTS_INDEX;
+use crate::metrics;
+use crate::reading::ReadingStats;
+use crate::toc::TocEntry;
+use crate::{sanitize, schedule};
 use anyhow::{anyhow, Result};
 use elasticsearch::{Elasticsearch, SearchParts};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
 
-pub async fn get_by_number(id: &u64, index: &str, es_client: &Elasticsearch) -> Result<Post> {
-    let r = es_client
-        .search(SearchParts::Index(&[index]))
-        .body(json!({
-            "query": {
-                "match": {
-                    "number": id
-                }
-            }
-        }))
-        .send()
-        .await
-        .unwrap()
-        .json::<Value>()
-        .await
-        .unwrap();
-    match r["hits"]["hits"].as_array().unwrap().get(0) {
-        Some(v) => Ok(serde_json::from_value::<Post>(v["_source"].clone()).unwrap()),
-        None => Err(anyhow!("没有找到")),
-    }
+/// 不出现在列表、搜索、相关文章里，只能通过链接访问
+pub const UNLISTED_LABEL: &str = "Unlisted";
+/// 需要输入密码才能看正文
+pub const PROTECTED_LABEL: &str = "Protected";
+
+// 面向读者的查询都要过滤掉还没到发布时间的文章。now 由最外层的调用方传入，
+// 同一个页面的几次查询用同一个时间，测试时也可以指定
+fn visible(query: Value, now: OffsetDateTime) -> Value {
+    json!({
+        "bool": {
+            "must": query,
+            "filter": schedule::published_filter(now)
+        }
+    })
+}
+
+// 列表类的查询还要去掉 Unlisted 的文章
+fn listed(query: Value, now: OffsetDateTime) -> Value {
+    json!({
+        "bool": {
+            "must": query,
+            "filter": schedule::published_filter(now),
+            "must_not": has_label(UNLISTED_LABEL)
+        }
+    })
+}
+
+pub async fn get_by_number(
+    id: &u64,
+    index: &str,
+    es_client: &Elasticsearch,
+    now: OffsetDateTime,
+) -> Result<Post> {
+    metrics::es_query("get_post", async {
+        let r = es_client
+            .search(SearchParts::Index(&[index]))
+            .body(json!({
+                "query": visible(json!({ "match": { "number": id } }), now)
+            }))
+            .send()
+            .await?
+            .error_for_status_code()?
+            .json::<Value>()
+            .await?;
+        match r["hits"]["hits"].as_array().and_then(|hits| hits.first()) {
+            Some(v) => Ok(serde_json::from_value::<Post>(v["_source"].clone())?),
+            None => Err(anyhow!("没有找到")),
+        }
+    })
+    .await
 }
 pub async fn get_latest_with_filter(
     index: &str,
     es_client: &Elasticsearch,
     filter: Option<String>,
+    now: OffsetDateTime,
 ) -> Result<Vec<Post>> {
-    let body = match filter {
+    metrics::es_query("search", async {
+        let body = latest_query(filter, now);
+        let r = es_client
+            .search(SearchParts::Index(&[index]))
+            .body(body)
+            .send()
+            .await?
+            .error_for_status_code()?
+            .json::<Value>()
+            .await?;
+        let mut posts = Vec::new();
+        for v in r["hits"]["hits"].as_array().into_iter().flatten() {
+            posts.push(serde_json::from_value::<Post>(v["_source"].clone())?);
+        }
+        Ok(posts)
+    })
+    .await
+}
+
+fn latest_query(filter: Option<String>, now: OffsetDateTime) -> Value {
+    // Protected 的文章只按标题搜索，不然可以用搜索结果一点点试出正文
+    let query = match filter {
         Some(f) => json!({
-            "size": 30,
-            "query": {
-                "multi_match": {
-                "query": f,
-                "fields" : [ "body_text", "title" ],
-                "type": "phrase"
-                }
-            },
-            "sort": [
-                {
-                    "updated_at": {
-                        "order": "desc"
-                    }
-                }
-            ]
-        }),
-        None => json!({
-            "size": 30,
-            "sort": [
-                {
-                    "updated_at": {
-                        "order": "desc"
+            "bool": {
+                "should": [
+                    { "match_phrase": { "title": f } },
+                    {
+                        "bool": {
+                            "must": { "match_phrase": { "body_text": f } },
+                            "must_not": has_label(PROTECTED_LABEL)
+                        }
                     }
-                }
-            ]
+                ],
+                "minimum_should_match": 1
+            }
         }),
+        None => json!({ "match_all": {} }),
     };
-    let r = es_client
-        .search(SearchParts::Index(&[index]))
-        .body(body)
-        .send()
-        .await
-        .unwrap()
-        .json::<Value>()
-        .await
-        .unwrap();
-    let mut posts = Vec::new();
-    for v in r["hits"]["hits"].as_array().unwrap() {
-        posts.push(serde_json::from_value::<Post>(v["_source"].clone()).unwrap());
-    }
-    Ok(posts)
+    json!({
+        "size": 30,
+        "query": listed(query, now),
+        "sort": [
+            {
+                "updated_at": {
+                    "order": "desc"
+                }
+            }
+        ]
+    })
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -91,6 +135,342 @@ pub struct Post {
     #[serde(with = "time::serde::iso8601")]
     pub updated_at: time::OffsetDateTime, //String,//"2018-05-23T16:30:12Z",
     pub body_html: String, // "## 如
+    #[serde(default)]
+    pub rendered_html: String,
+    #[serde(default)]
+    pub toc: Vec<TocEntry>,
+    #[serde(default)]
+    pub html_url: String,
+    // 旧数据里没有作者和表情，展示时按站长和零处理
+    #[serde(default)]
+    pub user: Option<PostUser>,
+    #[serde(default)]
+    pub reactions: PostReactions,
+    #[serde(default)]
+    pub reading: ReadingStats,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub publish_at: Option<OffsetDateTime>,
+    #[serde(default)]
+    pub password_hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostUser {
+    pub login: String,
+    pub avatar_url: String,
+    #[serde(default)]
+    pub html_url: String,
+}
+
+impl PostUser {
+    pub fn profile_url(&self) -> String {
+        if self.html_url.is_empty() {
+            format!("https://github.com/{}", self.login)
+        } else {
+            self.html_url.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PostReactions {
+    #[serde(default)]
+    pub total_count: u64,
+    #[serde(rename = "+1", default)]
+    pub plus_one: u64,
+    #[serde(rename = "-1", default)]
+    pub minus_one: u64,
+    #[serde(default)]
+    pub laugh: u64,
+    #[serde(default)]
+    pub hooray: u64,
+    #[serde(default)]
+    pub confused: u64,
+    #[serde(default)]
+    pub heart: u64,
+    #[serde(default)]
+    pub rocket: u64,
+    #[serde(default)]
+    pub eyes: u64,
+}
+
+impl PostReactions {
+    /// 按 GitHub 的顺序列出数量不为零的表情
+    pub fn summary(&self) -> Vec<(&'static str, u64)> {
+        [
+            ("👍", self.plus_one),
+            ("👎", self.minus_one),
+            ("😄", self.laugh),
+            ("🎉", self.hooray),
+            ("😕", self.confused),
+            ("❤️", self.heart),
+            ("🚀", self.rocket),
+            ("👀", self.eyes),
+        ]
+        .into_iter()
+        .filter(|(_, n)| *n > 0)
+        .collect()
+    }
+}
+
+impl Post {
+    /// 优先使用同步时生成并清理过的 HTML，旧数据回退到 GitHub 的 body_html，同样要先清理
+    pub fn html(&self) -> String {
+        if self.rendered_html.is_empty() {
+            sanitize::clean(&self.body_html)
+        } else {
+            self.rendered_html.clone()
+        }
+    }
+
+    pub fn has_label(&self, name: &str) -> bool {
+        self.labels.iter().any(|l| l.name == name)
+    }
+
+    /// 带 Protected 标签的文章一律需要密码，没设置密码的也打不开
+    pub fn is_protected(&self) -> bool {
+        self.has_label(PROTECTED_LABEL)
+    }
+
+    /// `Series: <name>` 标签里的系列名
+    pub fn series(&self) -> Option<String> {
+        series_name(&self.labels)
+    }
+}
+
+const SERIES_PREFIX: &str = "Series:";
+const PART_PREFIX: &str = "part:";
+
+fn series_name(labels: &[PostLabel]) -> Option<String> {
+    labels.iter().find_map(|l| {
+        l.name
+            .strip_prefix(SERIES_PREFIX)
+            .map(|n| n.trim().to_string())
+            .filter(|n| !n.is_empty())
+    })
+}
+
+// part:3 / part: 3
+fn series_part(labels: &[PostLabel]) -> Option<u32> {
+    labels.iter().find_map(|l| {
+        l.name
+            .strip_prefix(PART_PREFIX)
+            .and_then(|n| n.trim().parse().ok())
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PostLink {
+    pub number: u64,
+    pub title: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Series {
+    pub name: String,
+    pub parts: Vec<PostLink>,
+}
+
+/// 文章底部的上一篇、下一篇（按发布时间），以及所属系列的全部文章
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PostNav {
+    pub prev: Option<PostLink>,
+    pub next: Option<PostLink>,
+    pub series: Option<Series>,
+}
+
+// 导航只需要这几个字段，查询时用 _source 过滤掉正文
+#[derive(Debug, Clone, Deserialize)]
+struct LinkSource {
+    number: u64,
+    title: String,
+    #[serde(default)]
+    labels: Vec<PostLabel>,
+    #[serde(with = "time::serde::iso8601")]
+    created_at: time::OffsetDateTime,
+}
+
+const LINK_FIELDS: [&str; 4] = ["number", "title", "labels", "created_at"];
+
+impl From<LinkSource> for PostLink {
+    fn from(s: LinkSource) -> Self {
+        PostLink {
+            number: s.number,
+            title: s.title,
+        }
+    }
+}
+
+pub async fn get_post_nav(
+    es_client: &Elasticsearch,
+    post: &Post,
+    now: OffsetDateTime,
+) -> Result<PostNav> {
+    metrics::es_query("post_nav", async {
+        let prev = search_links(es_client, adjacent_query(post, false, now)?).await?;
+        let next = search_links(es_client, adjacent_query(post, true, now)?).await?;
+        // 同一个系列的文章打的是同一个标签，直接按原始标签名查
+        let series_label = post
+            .labels
+            .iter()
+            .find(|l| series_name(std::slice::from_ref(l)).is_some());
+        let series = match (post.series(), series_label) {
+            (Some(name), Some(label)) => {
+                let parts = search_links(
+                    es_client,
+                    json!({
+                        "size": 200,
+                        "_source": LINK_FIELDS,
+                        "query": listed(has_label(&label.name), now)
+                    }),
+                )
+                .await?;
+                Some(Series {
+                    name,
+                    parts: order_series(parts),
+                })
+            }
+            _ => None,
+        };
+        Ok(PostNav {
+            prev: prev.into_iter().next().map(PostLink::from),
+            next: next.into_iter().next().map(PostLink::from),
+            series,
+        })
+    })
+    .await
+}
+
+fn adjacent_query(post: &Post, next: bool, now: OffsetDateTime) -> Result<Value> {
+    let created_at = post.created_at.format(&Rfc3339)?;
+    let (range, order) = if next { ("gt", "asc") } else { ("lt", "desc") };
+    Ok(json!({
+        "size": 1,
+        "_source": LINK_FIELDS,
+        "query": listed(json!({ "range": { "created_at": { (range): created_at } } }), now),
+        "sort": [ { "created_at": { "order": order } } ]
+    }))
+}
+
+async fn search_links(es_client: &Elasticsearch, body: Value) -> Result<Vec<LinkSource>> {
+    let r = es_client
+        .search(SearchParts::Index(&["blog"]))
+        .body(body)
+        .send()
+        .await?
+        .json::<Value>()
+        .await?;
+    let mut links = Vec::new();
+    for v in r["hits"]["hits"].as_array().into_iter().flatten() {
+        links.push(serde_json::from_value::<LinkSource>(v["_source"].clone())?);
+    }
+    Ok(links)
+}
+
+const RELATED_SIZE: usize = 5;
+
+/// 和这篇文章内容相近的文章，标签相同的排在前面
+pub async fn related_posts(
+    es_client: &Elasticsearch,
+    number: u64,
+    now: OffsetDateTime,
+) -> Result<Vec<PostLink>> {
+    metrics::es_query("related", async {
+        let post = get_by_number(&number, "blog", es_client, now).await?;
+        let links = search_links(es_client, related_query(&post, now)).await?;
+        Ok(links.into_iter().map(PostLink::from).collect())
+    })
+    .await
+}
+
+fn related_query(post: &Post, now: OffsetDateTime) -> Value {
+    let labels: Vec<&str> = post.labels.iter().map(|l| l.name.as_str()).collect();
+    json!({
+        "size": RELATED_SIZE,
+        "_source": LINK_FIELDS,
+        "query": {
+            "bool": {
+                "must": {
+                    "more_like_this": {
+                        "fields": ["title", "body_text"],
+                        "like": [ { "_index": "blog", "_id": post.id.to_string() } ],
+                        "min_term_freq": 1,
+                        "min_doc_freq": 1,
+                        "max_query_terms": 25
+                    }
+                },
+                "should": {
+                    "nested": {
+                        "path": "labels",
+                        "query": { "terms": { "labels.name": labels } },
+                        "boost": 2.0
+                    }
+                },
+                "must_not": [
+                    { "term": { "number": post.number } },
+                    has_label(UNLISTED_LABEL)
+                ],
+                "filter": schedule::published_filter(now)
+            }
+        }
+    })
+}
+
+// 有 part:N 的按序号排，没有的排在后面按发布时间排
+fn order_series(mut parts: Vec<LinkSource>) -> Vec<PostLink> {
+    parts.sort_by_key(|p| {
+        (
+            series_part(&p.labels).unwrap_or(u32::MAX),
+            p.created_at,
+            p.number,
+        )
+    });
+    parts.into_iter().map(PostLink::from).collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Comment {
+    pub id: u64,
+    pub issue_number: u64,
+    pub html_url: String,
+    pub user: PostUser,
+    #[serde(with = "time::serde::iso8601")]
+    pub created_at: time::OffsetDateTime,
+    pub author_association: String,
+    pub rendered_html: String,
+}
+
+/// 文章下的评论，按时间正序；评论索引还没建好时返回空
+pub async fn get_comments(es_client: &Elasticsearch, number: u64) -> Result<Vec<Comment>> {
+    metrics::es_query("comments", async {
+        let r = es_client
+            .search(SearchParts::Index(&[COMMENTS_INDEX]))
+            .body(json!({
+                "size": 500,
+                "query": {
+                    "term": {
+                        "issue_number": number
+                    }
+                },
+                "sort": [
+                    {
+                        "created_at": {
+                            "order": "asc"
+                        }
+                    }
+                ]
+            }))
+            .send()
+            .await?
+            .json::<Value>()
+            .await?;
+        let mut comments = Vec::new();
+        for v in r["hits"]["hits"].as_array().into_iter().flatten() {
+            comments.push(serde_json::from_value::<Comment>(v["_source"].clone())?);
+        }
+        Ok(comments)
+    })
+    .await
 }
 
 // pub async fn redirect_to_blog(Path(id): Path<u64>) -> impl IntoResponse {
@@ -102,8 +482,8 @@ pub struct Post {
 //     }
 // }
 
-pub async fn get_one_blog(es_client: &Elasticsearch, id: u64) -> Result<Post> {
-    let p = get_by_number(&id, "blog", es_client).await?;
+pub async fn get_one_blog(es_client: &Elasticsearch, id: u64, now: OffsetDateTime) -> Result<Post> {
+    let p = get_by_number(&id, "blog", es_client, now).await?;
 
     Ok(p)
 }
@@ -113,23 +493,373 @@ pub async fn get_one_blog(es_client: &Elasticsearch, id: u64) -> Result<Post> {
 //     pub outdated_info: String,
 // }
 
-pub async fn get_all_blog(es_client: Elasticsearch) -> Result<String> {
-    let posts = get_latest_with_filter("blog", &es_client, None).await?;
+pub async fn get_all_blog(es_client: Elasticsearch, now: OffsetDateTime) -> Result<String> {
+    let posts = get_latest_with_filter("blog", &es_client, None, now).await?;
     Ok(json!({
         "posts": posts,
     })
     .to_string())
 }
+/// 所有已发布文章的编号，包括 Unlisted，导出静态站点时用
+pub async fn published_numbers(es_client: &Elasticsearch, now: OffsetDateTime) -> Result<Vec<u64>> {
+    metrics::es_query("published_numbers", async {
+        let r = es_client
+            .search(SearchParts::Index(&["blog"]))
+            .body(numbers_query(now))
+            .send()
+            .await?
+            .json::<Value>()
+            .await?;
+        let mut numbers = Vec::new();
+        for v in r["hits"]["hits"].as_array().into_iter().flatten() {
+            if let Some(n) = v["_source"]["number"].as_u64() {
+                numbers.push(n);
+            }
+        }
+        Ok(numbers)
+    })
+    .await
+}
+
+/// 列表里能看到的所有文章，不含 Unlisted，按更新时间倒序，生成 sitemap 和 feed 用
+pub async fn listed_posts(es_client: &Elasticsearch, now: OffsetDateTime) -> Result<Vec<Post>> {
+    metrics::es_query("listed_posts", async {
+        let body = json!({
+            "size": 10000,
+            "query": listed(json!({ "match_all": {} }), now),
+            "sort": [ { "updated_at": { "order": "desc" } } ]
+        });
+        search_posts(es_client, body).await
+    })
+    .await
+}
+
+/// 索引里的所有文章，包括 Unlisted 和还没到发布时间的，按编号排序，保存快照时用；
+/// 快照在读取时再按当时的时间过滤，定时发布的文章到点就能看到
+pub async fn all_posts(es_client: &Elasticsearch) -> Result<Vec<Post>> {
+    metrics::es_query("all_posts", async {
+        let body = json!({
+            "size": 10000,
+            "query": { "match_all": {} },
+            "sort": [ { "number": { "order": "asc" } } ]
+        });
+        search_posts(es_client, body).await
+    })
+    .await
+}
+
+fn numbers_query(now: OffsetDateTime) -> Value {
+    json!({
+        "size": 10000,
+        "_source": ["number"],
+        "query": visible(json!({ "match_all": {} }), now),
+        "sort": [ { "number": { "order": "asc" } } ]
+    })
+}
+
+pub const PINNED_LABEL: &str = "Pinned";
+pub const FEATURED_LABEL: &str = "Featured";
+pub(crate) const HOME_SIZE: usize = 30;
+
+/// 首页列表的排序字段，编辑旧文章会刷新 updated_at，不想让它顶到前面时用 created_at
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HomeSort {
+    #[default]
+    UpdatedAt,
+    CreatedAt,
+}
+
+impl HomeSort {
+    fn field(&self) -> &'static str {
+        match self {
+            HomeSort::UpdatedAt => "updated_at",
+            HomeSort::CreatedAt => "created_at",
+        }
+    }
+
+    /// 不查 ES 时按同样的字段排序
+    pub(crate) fn key(&self, post: &Post) -> OffsetDateTime {
+        match self {
+            HomeSort::UpdatedAt => post.updated_at,
+            HomeSort::CreatedAt => post.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Home {
+    /// 带 Pinned 标签的文章，固定在列表最前面
+    pub pinned: Vec<Post>,
+    /// 带 Featured 标签的文章，单独展示，同时也会出现在列表里
+    pub featured: Vec<Post>,
+    pub posts: Vec<Post>,
+}
+
+pub async fn get_home(
+    es_client: &Elasticsearch,
+    sort: HomeSort,
+    now: OffsetDateTime,
+) -> Result<Home> {
+    metrics::es_query("home", async {
+        let pinned =
+            search_posts(es_client, home_query(sort, Some(PINNED_LABEL), None, now)).await?;
+        let featured =
+            search_posts(es_client, home_query(sort, Some(FEATURED_LABEL), None, now)).await?;
+        let posts =
+            search_posts(es_client, home_query(sort, None, Some(PINNED_LABEL), now)).await?;
+        Ok(Home {
+            pinned,
+            featured,
+            posts,
+        })
+    })
+    .await
+}
+
+fn has_label(label: &str) -> Value {
+    json!({
+        "nested": {
+            "path": "labels",
+            "query": { "term": { "labels.name": label } }
+        }
+    })
+}
+
+fn home_query(
+    sort: HomeSort,
+    with: Option<&str>,
+    without: Option<&str>,
+    now: OffsetDateTime,
+) -> Value {
+    let mut filter = vec![schedule::published_filter(now)];
+    filter.extend(with.map(has_label));
+    let mut must_not = vec![has_label(UNLISTED_LABEL)];
+    must_not.extend(without.map(has_label));
+    let query = json!({ "bool": { "filter": filter, "must_not": must_not } });
+    json!({
+        "size": HOME_SIZE,
+        "query": query,
+        "sort": [ { (sort.field()): { "order": "desc" } } ]
+    })
+}
+
+async fn search_posts(es_client: &Elasticsearch, body: Value) -> Result<Vec<Post>> {
+    let r = es_client
+        .search(SearchParts::Index(&["blog"]))
+        .body(body)
+        .send()
+        .await?
+        .error_for_status_code()?
+        .json::<Value>()
+        .await?;
+    let mut posts = Vec::new();
+    for v in r["hits"]["hits"].as_array().into_iter().flatten() {
+        posts.push(serde_json::from_value::<Post>(v["_source"].clone())?);
+    }
+    Ok(posts)
+}
+
 pub async fn get_blogs_with_filter(
     es_client: &Elasticsearch,
     filter: Option<String>,
+    now: OffsetDateTime,
 ) -> Result<Vec<Post>> {
-    let posts = get_latest_with_filter("blog", es_client, filter).await?;
+    let posts = get_latest_with_filter("blog", es_client, filter, now).await?;
     Ok(posts)
 }
 
 #[cfg(test)]
 mod test {
+    use super::*;
+    use crate::testing::{fake_es, post};
+    use time::macros::datetime;
+
+    fn label(name: &str) -> PostLabel {
+        PostLabel {
+            name: name.to_string(),
+            description: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_post() {
+        let es_client = fake_es(Some(vec![
+            post(1, None),
+            post(2, Some(datetime!(2023-06-01 0:00 UTC))),
+        ]))
+        .await;
+        let before = datetime!(2023-05-31 23:59 UTC);
+        let after = datetime!(2023-06-01 0:00 UTC);
+        let numbers = |posts: Vec<Post>| posts.iter().map(|p| p.number).collect::<Vec<_>>();
+
+        // 到发布时间之前查不到，到了之后就能看到
+        assert!(get_one_blog(&es_client, 2, before).await.is_err());
+        assert_eq!(get_one_blog(&es_client, 2, after).await.unwrap().number, 2);
+        assert_eq!(
+            published_numbers(&es_client, before).await.unwrap(),
+            vec![1]
+        );
+        assert_eq!(
+            published_numbers(&es_client, after).await.unwrap(),
+            vec![1, 2]
+        );
+        let home = get_home(&es_client, HomeSort::UpdatedAt, before)
+            .await
+            .unwrap();
+        assert_eq!(numbers(home.posts), vec![1]);
+        let found = get_blogs_with_filter(&es_client, None, after)
+            .await
+            .unwrap();
+        assert_eq!(numbers(found), vec![1, 2]);
+        // 保存快照时不按时间过滤
+        assert_eq!(numbers(all_posts(&es_client).await.unwrap()), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_series() {
+        assert_eq!(
+            series_name(&[label("Publish"), label("Series:  Rust 入门 ")]),
+            Some("Rust 入门".to_string())
+        );
+        assert_eq!(series_name(&[label("Series:")]), None);
+        assert_eq!(series_part(&[label("part: 2")]), Some(2));
+        assert_eq!(series_part(&[label("part:x")]), None);
+
+        let link = |number, labels: &[&str], created_at| LinkSource {
+            number,
+            title: number.to_string(),
+            labels: labels.iter().map(|l| label(l)).collect(),
+            created_at,
+        };
+        let parts = order_series(vec![
+            link(1, &[], datetime!(2023-01-03 0:00 UTC)),
+            link(2, &["part:2"], datetime!(2023-01-01 0:00 UTC)),
+            link(3, &[], datetime!(2023-01-02 0:00 UTC)),
+            link(4, &["part:1"], datetime!(2023-01-04 0:00 UTC)),
+        ]);
+        assert_eq!(
+            parts.iter().map(|p| p.number).collect::<Vec<_>>(),
+            vec![4, 2, 3, 1]
+        );
+    }
+
+    #[test]
+    fn test_related_query() {
+        let post: Post = serde_json::from_value(json!({
+            "id": 233479897,
+            "number": 16,
+            "title": "t",
+            "labels": [label("Rust")],
+            "state": "open",
+            "created_at": "2017-06-05T02:27:43Z",
+            "updated_at": "2017-06-05T02:27:43Z",
+            "body_html": ""
+        }))
+        .unwrap();
+        let now = datetime!(2026-11-01 0:00 UTC);
+        let q = related_query(&post, now);
+        assert_eq!(q["size"], 5);
+        let b = &q["query"]["bool"];
+        assert_eq!(b["must"]["more_like_this"]["like"][0]["_id"], "233479897");
+        assert_eq!(
+            b["should"]["nested"]["query"]["terms"]["labels.name"][0],
+            "Rust"
+        );
+        assert_eq!(b["must_not"][0]["term"]["number"], 16);
+        assert_eq!(b["must_not"][1], has_label(UNLISTED_LABEL));
+        assert_eq!(b["filter"], schedule::published_filter(now));
+    }
+
+    #[test]
+    fn test_scheduled_queries() {
+        let now = datetime!(2026-11-01 0:00 UTC);
+        let published = schedule::published_filter(now);
+        assert_eq!(
+            latest_query(None, now)["query"]["bool"]["filter"],
+            published
+        );
+        assert_eq!(
+            latest_query(None, now)["query"]["bool"]["must_not"],
+            has_label(UNLISTED_LABEL)
+        );
+        let q = latest_query(Some("目标".to_string()), now);
+        let should = &q["query"]["bool"]["must"]["bool"]["should"];
+        assert_eq!(should[0]["match_phrase"]["title"], "目标");
+        assert_eq!(
+            should[1]["bool"]["must"]["match_phrase"]["body_text"],
+            "目标"
+        );
+        assert_eq!(should[1]["bool"]["must_not"], has_label(PROTECTED_LABEL));
+        assert_eq!(q["query"]["bool"]["filter"], published);
+        // 导出时 Unlisted 的文章也要生成页面
+        let q = numbers_query(now);
+        assert_eq!(q["query"]["bool"]["filter"], published);
+        assert!(q["query"]["bool"]["must_not"].is_null());
+
+        let post: Post = serde_json::from_value(json!({
+            "id": 1,
+            "number": 1,
+            "title": "t",
+            "labels": [],
+            "state": "open",
+            "created_at": "2017-06-05T02:27:43Z",
+            "updated_at": "2017-06-05T02:27:43Z",
+            "body_html": "",
+            "publish_at": "2026-12-01T00:00:00Z"
+        }))
+        .unwrap();
+        assert_eq!(post.publish_at, Some(datetime!(2026-12-01 0:00 UTC)));
+        let q = adjacent_query(&post, true, now).unwrap();
+        assert_eq!(
+            q["query"]["bool"]["must"]["range"]["created_at"]["gt"],
+            "2017-06-05T02:27:43Z"
+        );
+        assert_eq!(q["sort"][0]["created_at"]["order"], "asc");
+        assert_eq!(q["query"]["bool"]["filter"], published);
+    }
+
+    #[test]
+    fn test_home_query() {
+        let now = datetime!(2026-11-01 0:00 UTC);
+        let q = home_query(HomeSort::CreatedAt, Some(PINNED_LABEL), None, now);
+        assert_eq!(q["sort"][0]["created_at"]["order"], "desc");
+        assert_eq!(
+            q["query"]["bool"]["filter"][1]["nested"]["query"]["term"]["labels.name"],
+            "Pinned"
+        );
+        assert_eq!(
+            q["query"]["bool"]["must_not"],
+            json!([has_label(UNLISTED_LABEL)])
+        );
+
+        let q = home_query(HomeSort::default(), None, Some(PINNED_LABEL), now);
+        assert_eq!(q["sort"][0]["updated_at"]["order"], "desc");
+        assert_eq!(
+            q["query"]["bool"]["filter"],
+            json!([schedule::published_filter(now)])
+        );
+        assert_eq!(
+            q["query"]["bool"]["must_not"][1]["nested"]["query"]["term"]["labels.name"],
+            "Pinned"
+        );
+
+        let conf: crate::Config = toml::from_str(
+            "github_token = \"\"\ngithub_repo = \"\"\ngithub_owner = \"\"\nes_url = \"\"\nhome_sort = \"created_at\"",
+        )
+        .unwrap();
+        assert_eq!(conf.home_sort, HomeSort::CreatedAt);
+    }
+
+    #[test]
+    fn test_reactions() {
+        let p: super::PostReactions =
+            serde_json::from_value(serde_json::json!({"total_count": 3, "+1": 2, "rocket": 1}))
+                .unwrap();
+        assert_eq!(p.summary(), vec![("👍", 2), ("🚀", 1)]);
+        assert!(super::PostReactions::default().summary().is_empty());
+    }
+
     #[tokio::test]
     async fn test_get_by_number() {
         let transport =
@@ -138,7 +868,9 @@ mod test {
         let es_client = elasticsearch::Elasticsearch::new(transport);
         println!(
             "{:?}",
-            super::get_by_number(&35, "blog", &es_client).await.unwrap()
+            super::get_by_number(&35, "blog", &es_client, OffsetDateTime::now_utc())
+                .await
+                .unwrap()
         );
     }
     #[tokio::test]
@@ -149,9 +881,14 @@ mod test {
         let es_client = elasticsearch::Elasticsearch::new(transport);
         println!(
             "{:?}",
-            super::get_latest_with_filter("blog", &es_client, Some("目标".to_string()))
-                .await
-                .unwrap()
+            super::get_latest_with_filter(
+                "blog",
+                &es_client,
+                Some("目标".to_string()),
+                OffsetDateTime::now_utc()
+            )
+            .await
+            .unwrap()
         )
     }
 }
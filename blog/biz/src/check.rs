@@ -0,0 +1,398 @@
+use crate::github_issues::{self, COMMENTS_INDEX, INDEX_NAME};
+use crate::redact::MIN_SECRET_LEN;
+use crate::revision::{self, REVISIONS_INDEX};
+use crate::Config;
+use axum::http::HeaderValue;
+use elasticsearch::http::Url;
+use elasticsearch::indices::IndicesGetMappingParts;
+use elasticsearch::Elasticsearch;
+use serde_json::Value;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+/// 一项检查的结果，problems 为空表示通过
+#[derive(Debug)]
+pub struct CheckItem {
+    pub name: &'static str,
+    /// 补充说明，比如 ES 版本、token 的权限
+    pub detail: String,
+    pub problems: Vec<String>,
+}
+
+impl CheckItem {
+    fn new(name: &'static str) -> Self {
+        CheckItem {
+            name,
+            detail: String::new(),
+            problems: Vec::new(),
+        }
+    }
+
+    pub fn ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for CheckItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.ok() { "OK" } else { "FAIL" };
+        write!(f, "[{status}] {}", self.name)?;
+        if !self.detail.is_empty() {
+            write!(f, "：{}", self.detail)?;
+        }
+        for p in &self.problems {
+            write!(f, "\n  - {p}")?;
+        }
+        Ok(())
+    }
+}
+
+/// 检查配置、ES 连接、GitHub token 和索引的 mapping
+pub async fn run(conf: &Config, es_client: &Elasticsearch) -> Vec<CheckItem> {
+    vec![
+        check_config(conf),
+        check_es(es_client).await,
+        check_github(conf).await,
+        check_mappings(es_client).await,
+    ]
+}
+
+fn check_config(conf: &Config) -> CheckItem {
+    let mut item = CheckItem::new("config");
+    for (key, value) in [
+        ("github_token", &conf.github_token),
+        ("github_owner", &conf.github_owner),
+        ("github_repo", &conf.github_repo),
+        ("es_url", &conf.es_url),
+    ] {
+        if value.is_empty() {
+            item.problems.push(format!("{key} 未配置"));
+        }
+    }
+    if !conf.es_url.is_empty() && Url::parse(&conf.es_url).is_err() {
+        item.problems
+            .push(format!("es_url 不是合法的地址：{}", conf.es_url));
+    }
+    if let Some(csp) = &conf.content_security_policy {
+        if HeaderValue::from_str(csp).is_err() {
+            item.problems
+                .push("content_security_policy 不能作为响应头".to_string());
+        }
+    }
+    // 太短的密钥容易被猜到，日志里也只能按整个单词替换
+    for (key, value) in [
+        ("secret", &conf.secret),
+        ("webhook_secret", &conf.webhook_secret),
+    ] {
+        if !value.is_empty() && value.len() < MIN_SECRET_LEN {
+            item.problems
+                .push(format!("{key} 太短，至少需要 {MIN_SECRET_LEN} 个字符"));
+        }
+    }
+    item.problems.extend(media_dir_problem(&conf.media_dir));
+    item.detail = format!("{}/{}", conf.github_owner, conf.github_repo);
+    if conf.secret.is_empty() {
+        item.detail
+            .push_str("，secret 未配置，由 github_token 派生");
+    }
+    // 不接收 webhook 也能靠定时同步运行，只提示不算问题
+    if conf.webhook_secret.is_empty() {
+        item.detail
+            .push_str("，webhook_secret 未配置，不接收 GitHub webhook，只能靠定时同步");
+    }
+    item
+}
+
+// 只读取元数据，不创建目录；不存在时检查最近的上级目录，同步时会在那里创建
+fn media_dir_problem(media_dir: &str) -> Option<String> {
+    if media_dir.is_empty() {
+        return None;
+    }
+    let path = Path::new(media_dir);
+    let Some((dir, meta)) = path.ancestors().find_map(|p| {
+        let p = if p.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            p
+        };
+        p.metadata().ok().map(|m| (p, m))
+    }) else {
+        return Some(format!("media_dir {media_dir} 无法访问"));
+    };
+    if !meta.is_dir() {
+        Some(format!(
+            "media_dir {media_dir} 无法创建：{} 不是目录",
+            dir.display()
+        ))
+    } else if meta.permissions().readonly() {
+        Some(format!(
+            "media_dir {media_dir} 不可写：{} 是只读的",
+            dir.display()
+        ))
+    } else {
+        None
+    }
+}
+
+async fn check_es(es_client: &Elasticsearch) -> CheckItem {
+    let mut item = CheckItem::new("elasticsearch");
+    let resp = es_client
+        .info()
+        .request_timeout(Duration::new(3, 0))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status_code());
+    match resp {
+        Ok(r) => match r.json::<Value>().await {
+            Ok(v) => item.detail = format!("版本 {}", v["version"]["number"]),
+            Err(err) => item.problems.push(format!("无法解析 ES 的响应：{err}")),
+        },
+        Err(err) => item.problems.push(format!("连接 ES 失败：{err}")),
+    }
+    item
+}
+
+async fn check_github(conf: &Config) -> CheckItem {
+    let mut item = CheckItem::new("github");
+    let url = github_issues::repo_api_url(conf);
+    let resp = match github_issues::github_client(conf).get(&url).send().await {
+        Ok(r) => r,
+        Err(err) => {
+            item.problems.push(format!("请求 GitHub 失败：{err}"));
+            return item;
+        }
+    };
+    let status = resp.status();
+    if status == reqwest::StatusCode::UNAUTHORIZED {
+        item.problems.push("github_token 无效或已过期".to_string());
+        return item;
+    }
+    if !status.is_success() {
+        item.problems.push(format!(
+            "无法访问仓库 {url}：{status}，检查仓库名和 token 的权限"
+        ));
+        return item;
+    }
+    let scopes = resp
+        .headers()
+        .get("x-oauth-scopes")
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    let repo: Value = resp.json().await.unwrap_or_default();
+    item.detail = match &scopes {
+        Some(s) => format!("token 权限：{s}"),
+        None => "fine-grained token".to_string(),
+    };
+    item.problems.extend(token_problems(
+        scopes.as_deref(),
+        repo["private"].as_bool().unwrap_or(false),
+        repo["has_issues"].as_bool().unwrap_or(true),
+    ));
+    item
+}
+
+// classic token 才有 X-OAuth-Scopes，私有仓库需要 repo 权限才能读 issue
+fn token_problems(scopes: Option<&str>, private: bool, has_issues: bool) -> Vec<String> {
+    let mut problems = Vec::new();
+    if let Some(scopes) = scopes {
+        if private && !scopes.split(',').any(|s| s.trim() == "repo") {
+            problems.push(format!("私有仓库需要 repo 权限，当前为 \"{scopes}\""));
+        }
+    }
+    if !has_issues {
+        problems.push("仓库没有开启 issues".to_string());
+    }
+    problems
+}
+
+async fn check_mappings(es_client: &Elasticsearch) -> CheckItem {
+    let mut item = CheckItem::new("mapping");
+    for (index, expected) in [
+        (INDEX_NAME, github_issues::blog_mappings()),
+        (COMMENTS_INDEX, github_issues::comments_mappings()),
+        (REVISIONS_INDEX, revision::mappings()),
+    ] {
+        let resp = es_client
+            .indices()
+            .get_mapping(IndicesGetMappingParts::Index(&[index]))
+            .request_timeout(Duration::new(3, 0))
+            .send()
+            .await;
+        let actual = match resp {
+            Ok(r) if r.status_code().as_u16() == 404 => {
+                item.problems
+                    .push(format!("索引 {index} 不存在，运行 sync 创建"));
+                continue;
+            }
+            Ok(r) => match r.json::<Value>().await {
+                Ok(v) => v,
+                Err(err) => {
+                    item.problems
+                        .push(format!("读取 {index} 的 mapping 失败：{err}"));
+                    continue;
+                }
+            },
+            Err(err) => {
+                item.problems
+                    .push(format!("读取 {index} 的 mapping 失败：{err}"));
+                continue;
+            }
+        };
+        // 用别名时返回的 key 是实际的索引名
+        let actual = actual
+            .as_object()
+            .and_then(|m| m.values().next())
+            .map(|v| v["mappings"].clone())
+            .unwrap_or_default();
+        let problems = mapping_problems("", &expected, &actual);
+        if !problems.is_empty() {
+            item.problems.extend(
+                problems
+                    .into_iter()
+                    .map(|p| format!("{index}：{p}，运行 reindex 重建")),
+            );
+        }
+    }
+    item
+}
+
+/// 只检查需要的字段是否存在、类型是否一致，动态添加的字段不管
+fn mapping_problems(path: &str, expected: &Value, actual: &Value) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (field, spec) in expected["properties"].as_object().into_iter().flatten() {
+        let name = if path.is_empty() {
+            field.clone()
+        } else {
+            format!("{path}.{field}")
+        };
+        let found = &actual["properties"][field];
+        if found.is_null() {
+            problems.push(format!("缺少字段 {name}"));
+            continue;
+        }
+        let (want, got) = (field_type(spec), field_type(found));
+        if want != got {
+            problems.push(format!("字段 {name} 的类型是 {got}，应为 {want}"));
+            continue;
+        }
+        problems.extend(mapping_problems(&name, spec, found));
+    }
+    problems
+}
+
+// 只写了 properties 的字段是 object
+fn field_type(v: &Value) -> &str {
+    v["type"].as_str().unwrap_or("object")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_check_config() {
+        let conf = Config {
+            github_token: "t".to_string(),
+            github_owner: "hjin-me".to_string(),
+            github_repo: "blog".to_string(),
+            es_url: "http://127.0.0.1:9200".to_string(),
+            webhook_secret: "hook-secret".to_string(),
+            ..Default::default()
+        };
+        let item = check_config(&conf);
+        assert!(item.ok(), "{item}");
+        assert!(item.detail.starts_with("hjin-me/blog"));
+        assert!(!item.detail.contains("webhook_secret"));
+
+        // 没有 webhook_secret 只是提示
+        let item = check_config(&Config {
+            webhook_secret: String::new(),
+            ..conf
+        });
+        assert!(item.ok(), "{item}");
+        assert!(item
+            .detail
+            .ends_with("webhook_secret 未配置，不接收 GitHub webhook，只能靠定时同步"));
+
+        let conf = Config {
+            es_url: "not a url".to_string(),
+            secret: "abc".to_string(),
+            content_security_policy: Some("default-src\n'self'".to_string()),
+            ..Default::default()
+        };
+        let item = check_config(&conf);
+        assert_eq!(
+            item.problems,
+            vec![
+                "github_token 未配置",
+                "github_owner 未配置",
+                "github_repo 未配置",
+                "es_url 不是合法的地址：not a url",
+                "content_security_policy 不能作为响应头",
+                "secret 太短，至少需要 6 个字符",
+            ]
+        );
+        assert!(item.to_string().starts_with("[FAIL] config"));
+    }
+
+    #[test]
+    fn test_media_dir() {
+        let dir = crate::testing::temp_dir("check");
+        let media = dir.join("media/images");
+        // 不存在的目录不报错，也不会被创建
+        assert_eq!(media_dir_problem(media.to_str().unwrap()), None);
+        assert!(!dir.join("media").exists());
+        assert_eq!(media_dir_problem(""), None);
+
+        let file = dir.join("file");
+        std::fs::write(&file, "").unwrap();
+        let under_file = file.join("media");
+        assert!(media_dir_problem(under_file.to_str().unwrap())
+            .unwrap()
+            .ends_with("不是目录"));
+    }
+
+    #[test]
+    fn test_token_problems() {
+        assert!(token_problems(Some("repo, read:org"), true, true).is_empty());
+        assert!(token_problems(Some(""), false, true).is_empty());
+        assert!(token_problems(None, true, true).is_empty());
+        assert_eq!(
+            token_problems(Some("public_repo"), true, true),
+            vec!["私有仓库需要 repo 权限，当前为 \"public_repo\""]
+        );
+        assert_eq!(
+            token_problems(None, false, false),
+            vec!["仓库没有开启 issues"]
+        );
+    }
+
+    #[test]
+    fn test_mapping_problems() {
+        let expected = github_issues::blog_mappings();
+        assert!(mapping_problems("", &expected, &expected).is_empty());
+
+        // 旧索引：publish_at 没有定义，number 被动态映射成 long，reading 下面少了 minutes
+        let mut actual = expected.clone();
+        let props = actual["properties"].as_object_mut().unwrap();
+        props.remove("publish_at");
+        props.insert("number".to_string(), json!({ "type": "long" }));
+        props.insert(
+            "reading".to_string(),
+            json!({ "properties": { "words": { "type": "integer" } } }),
+        );
+        props.insert("html_url".to_string(), json!({ "type": "keyword" }));
+        let mut problems = mapping_problems("", &expected, &actual);
+        problems.sort();
+        assert_eq!(
+            problems,
+            vec![
+                "字段 number 的类型是 long，应为 integer",
+                "缺少字段 publish_at",
+                "缺少字段 reading.minutes",
+            ]
+        );
+    }
+}
@@ -13,7 +13,7 @@ mod test {
 
     #[tokio::test]
     async fn es() {
-        let client = init("http://127.0.0.1:9200").unwrap();
-        let rand_index = format!("test_{}", time::OffsetDateTime::now_utc().unix_timestamp());
+        let _client = init("http://127.0.0.1:9200").unwrap();
+        let _rand_index = format!("test_{}", time::OffsetDateTime::now_utc().unix_timestamp());
     }
 }
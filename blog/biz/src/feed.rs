@@ -0,0 +1,101 @@
+use crate::blog::Post;
+use crate::utils::escape;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// 导出静态站点时写出的 sitemap 和 Atom feed 的文件名
+pub const SITEMAP_FILE: &str = "sitemap.xml";
+pub const FEED_FILE: &str = "feed.xml";
+
+fn post_url(site_url: &str, post: &Post) -> String {
+    format!("{}/blog/{}", site_url.trim_end_matches('/'), post.number)
+}
+
+fn rfc3339(t: OffsetDateTime) -> String {
+    t.format(&Rfc3339).unwrap_or_default()
+}
+
+/// 首页和每篇文章的地址；posts 应该只包含列表里能看到的文章，Unlisted 的不放进来
+pub fn sitemap(site_url: &str, posts: &[Post]) -> String {
+    let mut xml = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+"#,
+    );
+    xml.push_str(&format!(
+        "<url><loc>{}/</loc></url>\n",
+        escape(site_url.trim_end_matches('/'))
+    ));
+    for post in posts {
+        xml.push_str(&format!(
+            "<url><loc>{}</loc><lastmod>{}</lastmod></url>\n",
+            escape(&post_url(site_url, post)),
+            rfc3339(post.updated_at)
+        ));
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+/// Atom feed，每篇文章只有标题和链接，不带正文，Protected 的文章也不会泄露内容
+pub fn atom(site_url: &str, title: &str, posts: &[Post]) -> String {
+    let site = escape(site_url.trim_end_matches('/'));
+    let updated = posts
+        .iter()
+        .map(|p| p.updated_at)
+        .max()
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    let mut xml = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<title>{}</title>
+<id>{site}/</id>
+<link href="{site}/"/>
+<link rel="self" href="{site}/{FEED_FILE}"/>
+<updated>{}</updated>
+"#,
+        escape(title),
+        rfc3339(updated)
+    );
+    for post in posts {
+        let url = escape(&post_url(site_url, post));
+        xml.push_str(&format!(
+            "<entry><title>{}</title><id>{url}</id><link href=\"{url}\"/><published>{}</published><updated>{}</updated></entry>\n",
+            escape(&post.title),
+            rfc3339(post.created_at),
+            rfc3339(post.updated_at)
+        ));
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::post;
+
+    #[test]
+    fn test_sitemap_and_atom() {
+        let mut a = post(1, None);
+        a.title = "<Rust> & Go".to_string();
+        let posts = vec![a, post(2, None)];
+
+        let xml = sitemap("https://example.com/", &posts);
+        assert!(xml.contains("<url><loc>https://example.com/</loc></url>"));
+        assert!(xml.contains(
+            "<url><loc>https://example.com/blog/2</loc><lastmod>2023-01-01T00:00:00Z</lastmod></url>"
+        ));
+        assert!(!xml.contains("blog/3"));
+
+        let xml = atom("https://example.com", "我的博客", &posts);
+        assert!(xml.contains("<title>我的博客</title>"));
+        assert!(xml.contains(r#"<link rel="self" href="https://example.com/feed.xml"/>"#));
+        assert!(xml.contains("<title>&lt;Rust&gt; &amp; Go</title>"));
+        assert!(xml.contains("<updated>2023-01-01T00:00:00Z</updated>"));
+        assert_eq!(xml.matches("<entry>").count(), 2);
+
+        let xml = atom("https://example.com", "我的博客", &[]);
+        assert!(xml.contains("<updated>1970-01-01T00:00:00Z</updated>"));
+    }
+}
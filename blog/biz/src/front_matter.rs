@@ -0,0 +1,130 @@
+const FENCE: &str = "---";
+
+/// 去掉正文开头的 front matter，剩下的才交给 Markdown 渲染
+pub fn strip(body: &str) -> &str {
+    match split(body) {
+        Some((_, rest)) => rest,
+        None => body,
+    }
+}
+
+/// 读取 front matter 里的 `key: value`，只支持单行的值，两边的引号会被去掉
+pub fn value<'a>(body: &'a str, key: &str) -> Option<&'a str> {
+    let (fm, _) = split(body)?;
+    fm.lines().find_map(|l| {
+        let (k, v) = l.split_once(':')?;
+        (k.trim() == key).then(|| v.trim().trim_matches(|c| c == '"' || c == '\''))
+    })
+}
+
+/// GitHub 把 front matter 当成普通 Markdown：开头的 --- 是 <hr>，中间的内容和结尾的 ---
+/// 合起来是一个 <h2>，新版本外面还包着 div 并跟着一个锚点链接。body 有 front matter 时
+/// 去掉 body_html 开头的这一段，认不出来时原样返回
+pub fn strip_html<'a>(body: &str, html: &'a str) -> &'a str {
+    if split(body).is_none() {
+        return html;
+    }
+    let Some(rest) = html.trim_start().strip_prefix("<hr") else {
+        return html;
+    };
+    let Some(end) = rest.find("</h2>") else {
+        return html;
+    };
+    let (heading, mut rest) = rest.split_at(end + "</h2>".len());
+    let open_divs = heading
+        .matches("<div")
+        .count()
+        .saturating_sub(heading.matches("</div>").count());
+    if open_divs > 0 {
+        if let Some(anchor) = rest.trim_start().strip_prefix("<a ") {
+            rest = anchor
+                .find("</a>")
+                .map_or(rest, |i| &anchor[i + "</a>".len()..]);
+        }
+        for _ in 0..open_divs {
+            rest = rest.trim_start().strip_prefix("</div>").unwrap_or(rest);
+        }
+    }
+    rest.trim_start()
+}
+
+/// body_text 是 body_html 的纯文本，front matter 的每一行都原样出现在开头，去掉这些行
+pub fn strip_text<'a>(body: &str, text: &'a str) -> &'a str {
+    let Some((fm, _)) = split(body) else {
+        return text;
+    };
+    let lines: Vec<&str> = fm
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let l = line.trim();
+        if !l.is_empty() && !lines.contains(&l) {
+            break;
+        }
+        offset += line.len();
+    }
+    &text[offset..]
+}
+
+fn split(body: &str) -> Option<(&str, &str)> {
+    let after = body.strip_prefix(FENCE)?;
+    let rest = after
+        .strip_prefix("\r\n")
+        .or_else(|| after.strip_prefix('\n'))?;
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == FENCE {
+            return Some((&rest[..offset], &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strip() {
+        assert_eq!(
+            strip("---\r\npublish_at: 2026-11-01\r\n---\r\n# 标题"),
+            "# 标题"
+        );
+        assert_eq!(strip("---\n---\n正文"), "正文");
+        // 没有闭合的 --- 不算 front matter，分隔线也不算
+        assert_eq!(strip("---\n正文"), "---\n正文");
+        assert_eq!(strip("正文\n---\n"), "正文\n---\n");
+    }
+
+    #[test]
+    fn test_strip_rendered() {
+        let body = "---\npublish_at: 2026-11-01\npassword: p@ss\n---\n正文";
+        let html = "<hr>\n<h2 dir=\"auto\">publish_at: 2026-11-01\npassword: p@ss</h2>\n<p dir=\"auto\">正文</p>";
+        assert_eq!(strip_html(body, html), "<p dir=\"auto\">正文</p>");
+        // 新版本的标题包在 div 里，锚点的 aria-label 里也有标题内容
+        let html = "<hr>\n<div class=\"markdown-heading\" dir=\"auto\"><h2 tabindex=\"-1\" class=\"heading-element\" dir=\"auto\">password: p@ss</h2><a id=\"user-content-password-pss\" class=\"anchor\" aria-label=\"Permalink: password: p@ss\" href=\"#password-pss\"><svg></svg></a></div>\n<div><p>正文</p></div>";
+        assert_eq!(strip_html(body, html), "<div><p>正文</p></div>");
+        // 没有 front matter 时开头的分隔线和标题都是正文
+        let html = "<hr>\n<h2>标题</h2>";
+        assert_eq!(strip_html("---\n\n## 标题", html), html);
+        assert_eq!(strip_html(body, "<p>正文</p>"), "<p>正文</p>");
+
+        let text = "publish_at: 2026-11-01\npassword: p@ss\n正文\npassword: p@ss";
+        assert_eq!(strip_text(body, text), "正文\npassword: p@ss");
+        assert_eq!(strip_text("正文", text), text);
+    }
+
+    #[test]
+    fn test_value() {
+        let body = "---\ntitle: a: b\npublish_at: \"2026-12-24\"\npassword: 'p@ss'\n---\n正文";
+        assert_eq!(value(body, "title"), Some("a: b"));
+        assert_eq!(value(body, "publish_at"), Some("2026-12-24"));
+        assert_eq!(value(body, "password"), Some("p@ss"));
+        assert_eq!(value(body, "missing"), None);
+        assert_eq!(value("password: x", "password"), None);
+    }
+}
@@ -1,59 +1,578 @@
-use crate::github_issues::sync_all_issues;
+use crate::redact::Redactor;
+use crate::syncer::Syncer;
 use crate::Config;
-use axum::extract::{Extension, TypedHeader};
-use axum::headers;
-use axum::headers::{Error, Header, HeaderName};
-use axum::http::StatusCode;
+use crate::{metrics, protect};
+use axum::body::Bytes;
+use axum::extract::Extension;
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::IntoResponse;
+use axum::Json;
 use elasticsearch::Elasticsearch;
-use lazy_static::lazy_static;
-use std::sync::Arc;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{info, warn};
 
-lazy_static! {
-    pub static ref X_HUB_SIGNATURE: HeaderName = HeaderName::from_static("x-hub-signature");
+/// 接收 GitHub webhook 的地址，只在配置了 webhook_secret 时提供
+pub const HOOK_PATH: &str = "/hook/github";
+/// 最近的处理记录
+pub const HOOK_LOG_PATH: &str = "/hook/github/log";
+const EVENT_HEADER: &str = "x-github-event";
+const DELIVERY_HEADER: &str = "x-github-delivery";
+const SIGNATURE_256_HEADER: &str = "x-hub-signature-256";
+// 记住最近多少个 delivery id 用来去重
+const SEEN_SIZE: usize = 1000;
+// 等待同步的事件最多多少个，满了以后返回 503，GitHub 那边可以重新投递
+const QUEUE_SIZE: usize = 100;
+// 处理记录最多保留多少条
+const LOG_SIZE: usize = 200;
+
+/// 要同步的范围：某一篇文章，或者所有文章（比如标签改名）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum Target {
+    Issue(u64),
+    All,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delivery {
+    pub id: String,
+    pub event: String,
+    /// None 表示不需要同步的事件，比如 ping
+    pub target: Option<Target>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status", content = "message")]
+pub enum Outcome {
+    Queued,
+    Ignored,
+    Succeeded(String),
+    Failed(String),
+}
+
+/// 一次 delivery 的处理记录
+#[derive(Debug, Clone, Serialize)]
+pub struct HookLogEntry {
+    pub delivery: String,
+    pub event: String,
+    pub target: Option<Target>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub received_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub finished_at: Option<OffsetDateTime>,
+    pub outcome: Outcome,
+}
+
+/// enqueue 的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Enqueued {
+    Queued,
+    Ignored,
+    /// GitHub 重新投递的同一个事件
+    Duplicate,
+    /// 队列满了，没有处理
+    Full,
+}
+
+/// webhook 只负责校验和入队，同步由后台的 worker 做，GitHub 那边 10 秒就超时
+pub struct HookQueue {
+    tx: mpsc::Sender<Delivery>,
+    seen: Mutex<(VecDeque<String>, HashSet<String>)>,
+    log: Mutex<VecDeque<HookLogEntry>>,
+}
+
+impl HookQueue {
+    /// 创建队列并启动 worker；同一篇文章在 debounce 时间内的多个事件只同步一次
+    pub fn start(
+        syncer: Arc<Syncer>,
+        conf: Arc<Config>,
+        es_client: Arc<Elasticsearch>,
+        debounce: Duration,
+    ) -> Arc<Self> {
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        let queue = Arc::new(HookQueue::new(tx));
+        tokio::spawn(worker(queue.clone(), rx, debounce, move |target| {
+            let (syncer, conf, es_client) = (syncer.clone(), conf.clone(), es_client.clone());
+            async move {
+                match target {
+                    Target::Issue(number) => syncer.sync_one(&conf, &es_client, number).await,
+                    Target::All => syncer.sync_all(&conf, &es_client).await,
+                }
+                .map(|report| report.to_string())
+                .map_err(|err| format!("{err:#}"))
+            }
+        }));
+        queue
+    }
+
+    fn new(tx: mpsc::Sender<Delivery>) -> Self {
+        HookQueue {
+            tx,
+            seen: Mutex::new((VecDeque::new(), HashSet::new())),
+            log: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// 最近的处理记录，新的在前
+    pub fn log(&self) -> Vec<HookLogEntry> {
+        self.log.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    fn enqueue(&self, delivery: Delivery) -> Enqueued {
+        {
+            let mut seen = self.seen.lock().unwrap();
+            let (order, ids) = &mut *seen;
+            if !ids.insert(delivery.id.clone()) {
+                return Enqueued::Duplicate;
+            }
+            order.push_back(delivery.id.clone());
+            if order.len() > SEEN_SIZE {
+                if let Some(old) = order.pop_front() {
+                    ids.remove(&old);
+                }
+            }
+        }
+        let (id, target) = (delivery.id.clone(), delivery.target);
+        // 先记下来再入队，worker 处理完时要能找到这条记录
+        self.record(HookLogEntry {
+            delivery: id.clone(),
+            event: delivery.event.clone(),
+            target,
+            received_at: OffsetDateTime::now_utc(),
+            finished_at: None,
+            outcome: match target {
+                Some(_) => Outcome::Queued,
+                None => Outcome::Ignored,
+            },
+        });
+        if target.is_none() {
+            return Enqueued::Ignored;
+        }
+        if self.tx.try_send(delivery).is_err() {
+            self.finish(&[id], &Outcome::Failed("队列已满".to_string()));
+            return Enqueued::Full;
+        }
+        Enqueued::Queued
+    }
+
+    fn record(&self, entry: HookLogEntry) {
+        let mut log = self.log.lock().unwrap();
+        log.push_back(entry);
+        while log.len() > LOG_SIZE {
+            log.pop_front();
+        }
+    }
+
+    fn finish(&self, ids: &[String], outcome: &Outcome) {
+        if let Outcome::Failed(_) = outcome {
+            // 忘掉失败的 delivery，GitHub 重新投递时还能处理
+            let mut seen = self.seen.lock().unwrap();
+            let (order, seen_ids) = &mut *seen;
+            order.retain(|id| !ids.contains(id));
+            for id in ids {
+                seen_ids.remove(id);
+            }
+        }
+        let now = OffsetDateTime::now_utc();
+        let result = match outcome {
+            Outcome::Failed(_) => "failed",
+            _ => "succeeded",
+        };
+        let mut log = self.log.lock().unwrap();
+        for entry in log.iter_mut().filter(|e| ids.contains(&e.delivery)) {
+            metrics::webhook_delivery(event_label(&entry.event), result);
+            entry.finished_at = Some(now);
+            entry.outcome = outcome.clone();
+        }
+    }
+}
+
+async fn worker<F, Fut>(
+    queue: Arc<HookQueue>,
+    mut rx: mpsc::Receiver<Delivery>,
+    debounce: Duration,
+    process: F,
+) where
+    F: Fn(Target) -> Fut,
+    Fut: std::future::Future<Output = Result<String, String>>,
+{
+    while let Some(batch) = next_batch(&mut rx, debounce).await {
+        // 要全量同步时单篇的就不用再做了
+        let batch = match batch.get(&Target::All) {
+            Some(_) => {
+                let ids = batch.into_values().flatten().collect();
+                BTreeMap::from([(Target::All, ids)])
+            }
+            None => batch,
+        };
+        for (target, ids) in batch {
+            let outcome = match process(target).await {
+                Ok(report) => {
+                    info!("webhook 同步 {:?} 完成：{}", target, report);
+                    Outcome::Succeeded(report)
+                }
+                Err(err) => {
+                    warn!("webhook 同步 {:?} 失败：{}", target, err);
+                    Outcome::Failed(err)
+                }
+            };
+            queue.finish(&ids, &outcome);
+        }
+    }
+}
+
+/// 等到第一个事件后，再收 debounce 时间内的事件，按同步范围合并；channel 关闭时返回 None
+async fn next_batch(
+    rx: &mut mpsc::Receiver<Delivery>,
+    debounce: Duration,
+) -> Option<BTreeMap<Target, Vec<String>>> {
+    let first = rx.recv().await?;
+    let mut batch: BTreeMap<Target, Vec<String>> = BTreeMap::new();
+    let mut add = |d: Delivery| {
+        if let Some(target) = d.target {
+            batch.entry(target).or_default().push(d.id);
+        }
+    };
+    add(first);
+    let deadline = Instant::now() + debounce;
+    while let Ok(Some(d)) = tokio::time::timeout_at(deadline, rx.recv()).await {
+        add(d);
+    }
+    Some(batch)
+}
+
+/// 校验请求头和 X-Hub-Signature-256 签名，解析出要同步的范围；secret 为空时一律拒绝
+pub fn parse_delivery(
+    secret: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<Delivery, StatusCode> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+    };
+    let (Some(event), Some(id)) = (header(EVENT_HEADER), header(DELIVERY_HEADER)) else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    let body = std::str::from_utf8(body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let signature = header(SIGNATURE_256_HEADER)
+        .and_then(|s| s.strip_prefix("sha256="))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    if secret.is_empty() || !protect::verify(secret.as_bytes(), body, signature) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let payload: Value = serde_json::from_str(body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let target = match event {
+        "issues" | "issue_comment" => Some(Target::Issue(
+            payload["issue"]["number"]
+                .as_u64()
+                .ok_or(StatusCode::BAD_REQUEST)?,
+        )),
+        "label" => Some(Target::All),
+        _ => None,
+    };
+    Ok(Delivery {
+        id: id.to_string(),
+        event: event.to_string(),
+        target,
+    })
+}
+
+// 指标标签只用固定的几个事件名，其他事件都算 other
+fn event_label(event: &str) -> &'static str {
+    match event {
+        "issues" => "issues",
+        "issue_comment" => "issue_comment",
+        "label" => "label",
+        "ping" => "ping",
+        _ => "other",
+    }
 }
 
 pub async fn github_hook(
-    TypedHeader(HubSignature(h)): TypedHeader<HubSignature>,
-    Extension(es_client): Extension<Arc<Elasticsearch>>,
+    Extension(queue): Extension<Arc<HookQueue>>,
     Extension(conf): Extension<Arc<Config>>,
+    headers: HeaderMap,
+    body: Bytes,
 ) -> impl IntoResponse {
-    if h.is_empty() {
-        return StatusCode::BAD_REQUEST;
-    }
-    match sync_all_issues(
-        &conf.github_token,
-        &conf.github_owner,
-        &conf.github_repo,
-        &es_client,
-    )
-    .await
-    {
-        Ok(_) => StatusCode::NO_CONTENT,
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
-    }
-}
-// X-Hub-Signature
-pub struct HubSignature(pub String);
-impl Header for HubSignature {
-    fn name() -> &'static HeaderName {
-        &X_HUB_SIGNATURE
-    }
-
-    fn decode<'i, I>(values: &mut I) -> Result<Self, Error>
-    where
-        I: Iterator<Item = &'i headers::HeaderValue>,
-    {
-        values
-            .next()
-            .and_then(|v| v.to_str().ok().map(String::from))
-            .map(HubSignature)
-            .ok_or_else(Error::invalid)
-    }
-
-    fn encode<E: Extend<headers::HeaderValue>>(&self, values: &mut E) {
-        values.extend(std::iter::once(
-            headers::HeaderValue::from_bytes(self.0.as_str().as_bytes()).unwrap(),
+    let delivery = match parse_delivery(&conf.webhook_secret, &headers, &body) {
+        Ok(d) => d,
+        Err(status) => {
+            // 没通过校验的请求不信任它的事件名，避免随便一个值就多一个标签
+            metrics::webhook_delivery("invalid", "rejected");
+            return status;
+        }
+    };
+    let event = event_label(&delivery.event);
+    let (status, result) = match queue.enqueue(delivery) {
+        Enqueued::Queued => (StatusCode::ACCEPTED, "queued"),
+        Enqueued::Ignored => (StatusCode::OK, "ignored"),
+        Enqueued::Duplicate => (StatusCode::OK, "duplicate"),
+        Enqueued::Full => {
+            warn!("webhook 队列已满，丢弃 {}", event);
+            (StatusCode::SERVICE_UNAVAILABLE, "dropped")
+        }
+    };
+    metrics::webhook_delivery(event, result);
+    status
+}
+
+/// 最近的 webhook 处理记录，只挂在 metrics_addr 上，不对公网开放；同步的错误信息里可能有密钥，先替换掉
+pub async fn hook_log(
+    Extension(queue): Extension<Arc<HookQueue>>,
+    Extension(conf): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    Json(redacted_log(&queue, &Redactor::new(&conf)))
+}
+
+fn redacted_log(queue: &HookQueue, redactor: &Redactor) -> Vec<HookLogEntry> {
+    let mut log = queue.log();
+    for entry in &mut log {
+        if let Outcome::Succeeded(msg) | Outcome::Failed(msg) = &mut entry.outcome {
+            *msg = redactor.redact(msg).into_owned();
+        }
+    }
+    log
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut h = HeaderMap::new();
+        for (k, v) in pairs {
+            h.insert(*k, HeaderValue::from_str(v).unwrap());
+        }
+        h
+    }
+
+    fn delivery(id: &str, target: Option<Target>) -> Delivery {
+        Delivery {
+            id: id.to_string(),
+            event: "issues".to_string(),
+            target,
+        }
+    }
+
+    fn signed(event: &str, id: &str, body: &[u8]) -> HeaderMap {
+        let sig = format!(
+            "sha256={}",
+            protect::sign(b"s3cret", std::str::from_utf8(body).unwrap())
+        );
+        headers(&[
+            (EVENT_HEADER, event),
+            (DELIVERY_HEADER, id),
+            (SIGNATURE_256_HEADER, &sig),
+        ])
+    }
+
+    #[test]
+    fn test_parse_delivery() {
+        let body = br#"{"action":"edited","issue":{"number":16}}"#;
+        let h = signed("issues", "d1", body);
+        assert_eq!(
+            parse_delivery("s3cret", &h, body),
+            Ok(Delivery {
+                id: "d1".to_string(),
+                event: "issues".to_string(),
+                target: Some(Target::Issue(16)),
+            })
+        );
+        let h = signed("issue_comment", "d2", body);
+        assert_eq!(
+            parse_delivery("s3cret", &h, body).map(|d| d.target),
+            Ok(Some(Target::Issue(16)))
+        );
+        assert_eq!(
+            parse_delivery("other", &h, body),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        // 没配置 secret 时不接收任何请求，签名对不对都一样
+        assert_eq!(parse_delivery("", &h, body), Err(StatusCode::UNAUTHORIZED));
+        let h = headers(&[
+            (EVENT_HEADER, "issues"),
+            (DELIVERY_HEADER, "d2"),
+            ("x-hub-signature", "sha1=x"),
+        ]);
+        assert_eq!(
+            parse_delivery("s3cret", &h, body),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        let mut h = signed("issues", "d1", body);
+        h.remove(DELIVERY_HEADER);
+        assert_eq!(
+            parse_delivery("s3cret", &h, body),
+            Err(StatusCode::BAD_REQUEST)
+        );
+
+        let body = br#"{"zen":"hi"}"#;
+        assert_eq!(
+            parse_delivery("s3cret", &signed("ping", "d3", body), body).map(|d| d.target),
+            Ok(None)
+        );
+        let body = br#"{"action":"edited"}"#;
+        assert_eq!(
+            parse_delivery("s3cret", &signed("label", "d4", body), body).map(|d| d.target),
+            Ok(Some(Target::All))
+        );
+    }
+
+    #[test]
+    fn test_event_label() {
+        assert_eq!(event_label("issue_comment"), "issue_comment");
+        assert_eq!(event_label("ping"), "ping");
+        assert_eq!(event_label("push"), "other");
+        assert_eq!(event_label("issues\n随便什么"), "other");
+    }
+
+    #[tokio::test]
+    async fn test_next_batch() {
+        let (tx, mut rx) = mpsc::channel(QUEUE_SIZE);
+        let debounce = Duration::from_millis(100);
+        tx.try_send(delivery("a", Some(Target::Issue(1)))).unwrap();
+        tx.try_send(delivery("b", Some(Target::Issue(2)))).unwrap();
+        tx.try_send(delivery("c", Some(Target::Issue(1)))).unwrap();
+        let late = tx.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            late.try_send(delivery("d", Some(Target::Issue(1))))
+                .unwrap();
+        });
+        let batch = next_batch(&mut rx, debounce).await.unwrap();
+        assert_eq!(
+            batch,
+            BTreeMap::from([
+                (Target::Issue(1), vec!["a".to_string(), "c".to_string()]),
+                (Target::Issue(2), vec!["b".to_string()]),
+            ])
+        );
+        // 窗口之后的事件进下一批
+        let batch = next_batch(&mut rx, debounce).await.unwrap();
+        assert_eq!(
+            batch,
+            BTreeMap::from([(Target::Issue(1), vec!["d".to_string()])])
+        );
+        drop(tx);
+        assert!(next_batch(&mut rx, debounce).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_queue() {
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        let queue = Arc::new(HookQueue::new(tx));
+        let processed = Arc::new(Mutex::new(Vec::new()));
+        let p = processed.clone();
+        tokio::spawn(worker(
+            queue.clone(),
+            rx,
+            Duration::from_millis(50),
+            move |target| {
+                p.lock().unwrap().push(target);
+                async move {
+                    match target {
+                        Target::Issue(2) => Err("GitHub 404".to_string()),
+                        _ => Ok("ok".to_string()),
+                    }
+                }
+            },
         ));
+
+        let queued = Enqueued::Queued;
+        assert_eq!(queue.enqueue(delivery("a", Some(Target::Issue(1)))), queued);
+        assert_eq!(queue.enqueue(delivery("b", Some(Target::Issue(1)))), queued);
+        assert_eq!(queue.enqueue(delivery("c", Some(Target::Issue(2)))), queued);
+        assert_eq!(queue.enqueue(delivery("p", None)), Enqueued::Ignored);
+        // 重新投递的同一个 delivery 不再处理
+        assert_eq!(
+            queue.enqueue(delivery("a", Some(Target::Issue(1)))),
+            Enqueued::Duplicate
+        );
+        assert_eq!(queue.log()[0].outcome, Outcome::Ignored);
+        assert_eq!(queue.log()[1].outcome, Outcome::Queued);
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(
+            *processed.lock().unwrap(),
+            vec![Target::Issue(1), Target::Issue(2)]
+        );
+        let log = queue.log();
+        assert_eq!(log.len(), 4);
+        let outcome = |id: &str| {
+            log.iter()
+                .find(|e| e.delivery == id)
+                .map(|e| e.outcome.clone())
+                .unwrap()
+        };
+        assert_eq!(outcome("a"), Outcome::Succeeded("ok".to_string()));
+        assert_eq!(outcome("b"), Outcome::Succeeded("ok".to_string()));
+        assert_eq!(outcome("c"), Outcome::Failed("GitHub 404".to_string()));
+        assert!(log
+            .iter()
+            .all(|e| e.delivery == "p" || e.finished_at.is_some()));
+
+        // 处理失败的 delivery 重新投递时再处理一次，成功的仍然算重复
+        processed.lock().unwrap().clear();
+        assert_eq!(queue.enqueue(delivery("c", Some(Target::Issue(2)))), queued);
+        assert_eq!(
+            queue.enqueue(delivery("b", Some(Target::Issue(1)))),
+            Enqueued::Duplicate
+        );
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(*processed.lock().unwrap(), vec![Target::Issue(2)]);
+
+        // 全量同步会吃掉同一批里单篇的同步
+        processed.lock().unwrap().clear();
+        assert_eq!(queue.enqueue(delivery("e", Some(Target::Issue(3)))), queued);
+        assert_eq!(queue.enqueue(delivery("f", Some(Target::All))), queued);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(*processed.lock().unwrap(), vec![Target::All]);
+        assert_eq!(queue.log()[1].outcome, Outcome::Succeeded("ok".to_string()));
+
+        // 记录有上限
+        for i in 0..LOG_SIZE + 10 {
+            queue.enqueue(delivery(&format!("x{i}"), None));
+        }
+        assert_eq!(queue.log().len(), LOG_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_queue_full() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let queue = Arc::new(HookQueue::new(tx));
+        let d = |id| delivery(id, Some(Target::Issue(1)));
+        assert_eq!(queue.enqueue(d("a")), Enqueued::Queued);
+        assert_eq!(queue.enqueue(d("b")), Enqueued::Full);
+        assert_eq!(
+            queue.log()[0].outcome,
+            Outcome::Failed("队列已满".to_string())
+        );
+        // 队列空出来以后，重新投递的 b 还能处理
+        rx.recv().await.unwrap();
+        assert_eq!(queue.enqueue(d("b")), Enqueued::Queued);
+
+        // 记录里的密钥要替换掉
+        let conf = Config {
+            github_token: "ghp_secret123".to_string(),
+            ..Default::default()
+        };
+        queue.finish(
+            &["b".to_string()],
+            &Outcome::Failed("401 ghp_secret123".to_string()),
+        );
+        let log = redacted_log(&queue, &Redactor::new(&conf));
+        assert_eq!(
+            log[0].outcome,
+            Outcome::Failed("401 [REDACTED]".to_string())
+        );
     }
 }
@@ -1,26 +1,330 @@
-use anyhow::Result;
-use elasticsearch::indices::{IndicesCreateParts, IndicesExistsParts};
-use elasticsearch::{Elasticsearch, UpdateParts};
+use crate::blog::{Post, PROTECTED_LABEL};
+use crate::media::{MediaImage, Mirror};
+use crate::reading::{self, ReadingStats};
+use crate::revision::{self, Revision, REVISIONS_INDEX};
+use crate::toc::{self, TocEntry};
+use crate::{front_matter, highlight, markdown, protect, sanitize, schedule, Config};
+use anyhow::{bail, Result};
+use elasticsearch::indices::{
+    IndicesCreateParts, IndicesDeleteParts, IndicesExistsParts, IndicesGetAliasParts,
+};
+use elasticsearch::{DeleteByQueryParts, Elasticsearch, SearchParts, UpdateParts};
 use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, USER_AGENT};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
+use std::fmt;
 use std::time::Duration;
-use tracing::trace;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::{trace, warn};
 
-pub async fn sync_all_issues(
-    github_token: &String,
-    owner: &String,
-    repo: &String,
+/// 评论单独存一个索引，按 issue_number 关联到文章
+pub const COMMENTS_INDEX: &str = "blog_comments";
+const PER_PAGE: usize = 100;
+
+/// 文章索引的别名，实际的索引名带着创建时间，比如 blog_1700000000；
+/// 更早的部署里 blog 是普通索引，reindex 时换成别名
+pub const INDEX_NAME: &str = "blog";
+
+/// 同步写入的文章和评论索引，平时是两个别名，reindex 时是新建的索引
+struct Indices<'a> {
+    posts: &'a str,
+    comments: &'a str,
+}
+
+const ALIASES: Indices<'static> = Indices {
+    posts: INDEX_NAME,
+    comments: COMMENTS_INDEX,
+};
+
+/// 一次同步的结果，单篇文章失败不影响其他文章
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub issues: usize,
+    pub comments: usize,
+    /// 新增的历史版本数
+    pub revisions: usize,
+    /// 同步失败的 issue 编号和原因
+    pub failed: Vec<(u64, String)>,
+}
+
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "同步了 {} 篇文章、{} 条评论，新增 {} 个历史版本",
+            self.issues, self.comments, self.revisions
+        )?;
+        if !self.failed.is_empty() {
+            write!(f, "，{} 篇失败：", self.failed.len())?;
+            for (number, err) in &self.failed {
+                write!(f, "\n  #{number}: {err}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub async fn sync_all_issues(conf: &Config, es_client: &Elasticsearch) -> Result<SyncReport> {
+    sync_issues(conf, es_client, None).await
+}
+
+/// 同步 since 之后有更新的 issue，since 为空时全量同步
+pub async fn sync_issues(
+    conf: &Config,
+    es_client: &Elasticsearch,
+    since: Option<OffsetDateTime>,
+) -> Result<SyncReport> {
+    create_indices(es_client).await?;
+    sync_into(conf, es_client, since, &ALIASES).await
+}
+
+async fn sync_into(
+    conf: &Config,
+    es_client: &Elasticsearch,
+    since: Option<OffsetDateTime>,
+    indices: &Indices<'_>,
+) -> Result<SyncReport> {
+    let request_url = format!("{}/issues", repo_api_url(conf));
+    trace!("request_url: {}, since: {:?}", request_url, since);
+    let client = github_client(conf);
+    let mut params = Vec::new();
+    if let Some(since) = since {
+        params.push(("since", since.format(&Rfc3339)?));
+    }
+    let issues: Vec<Issue> = fetch_all(&client, &request_url, &params).await?;
+
+    let mirror = mirror(conf)?;
+    let mut report = SyncReport::default();
+    for mut issue in issues {
+        let synced = sync_issue(
+            conf,
+            &client,
+            mirror.as_ref(),
+            es_client,
+            indices,
+            &mut issue,
+        );
+        match synced.await {
+            Ok((revised, comments)) => {
+                report.issues += 1;
+                report.comments += comments;
+                report.revisions += usize::from(revised);
+            }
+            Err(err) => {
+                warn!("同步文章 {} 失败：{:?}", issue.number, err);
+                report.failed.push((issue.number, err.to_string()));
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// 只同步一篇文章，webhook 收到某个 issue 的事件时用
+pub async fn sync_one(conf: &Config, es_client: &Elasticsearch, number: u64) -> Result<SyncReport> {
+    let client = github_client(conf);
+    let mut issue = fetch_issue(conf, &client, number).await?;
+    create_indices(es_client).await?;
+    let mirror = mirror(conf)?;
+    let (revised, comments) = sync_issue(
+        conf,
+        &client,
+        mirror.as_ref(),
+        es_client,
+        &ALIASES,
+        &mut issue,
+    )
+    .await?;
+    Ok(SyncReport {
+        issues: 1,
+        comments,
+        revisions: usize::from(revised),
+        failed: vec![],
+    })
+}
+
+fn mirror(conf: &Config) -> Result<Option<Mirror>> {
+    if conf.media_dir.is_empty() {
+        Ok(None)
+    } else {
+        let index_file =
+            (!conf.media_index_path.is_empty()).then(|| conf.media_index_path.clone().into());
+        Ok(Some(Mirror::new(&conf.media_dir, index_file)?))
+    }
+}
+
+/// 按当前的 mapping 新建文章和评论索引并全量同步，全部成功后一次性把别名切过去、删掉旧索引，
+/// 重建期间线上照常读旧索引；有文章同步失败时不切换。历史版本没法从 GitHub 找回，保留不动
+pub async fn reindex(conf: &Config, es_client: &Elasticsearch) -> Result<SyncReport> {
+    let version = OffsetDateTime::now_utc().unix_timestamp();
+    let posts = format!("{INDEX_NAME}_{version}");
+    let comments = format!("{COMMENTS_INDEX}_{version}");
+    create_index(es_client, &posts, None).await?;
+    create_comments_index(es_client, &comments, None).await?;
+    if !index_exist(es_client, REVISIONS_INDEX).await? {
+        revision::create_index(es_client).await?;
+    }
+    let indices = Indices {
+        posts: &posts,
+        comments: &comments,
+    };
+    let report = match sync_into(conf, es_client, None, &indices).await {
+        Ok(report) if report.failed.is_empty() => report,
+        result => {
+            delete_indices(es_client, &[&posts, &comments]).await?;
+            match result {
+                Ok(report) => bail!("{report}，没有切换到新索引"),
+                Err(err) => return Err(err),
+            }
+        }
+    };
+    swap_aliases(
+        es_client,
+        &[(INDEX_NAME, &posts), (COMMENTS_INDEX, &comments)],
+    )
+    .await?;
+    Ok(report)
+}
+
+/// name 实际对应的索引：别名返回它指向的索引，普通索引返回自己，不存在时返回 None
+pub(crate) async fn resolve_index(
     es_client: &Elasticsearch,
-) -> Result<()> {
-    let request_url = format!("https://api.github.com/repos/{owner}/{repo}/issues");
-    trace!("request_url: {}", request_url);
-    trace!("github_token: {}", github_token);
-    trace!("es_client: {:?}", es_client);
+    name: &str,
+) -> Result<Option<Vec<String>>, elasticsearch::Error> {
+    let resp = es_client
+        .indices()
+        .get_alias(IndicesGetAliasParts::Index(&[name]))
+        .request_timeout(Duration::new(2, 0))
+        .send()
+        .await?;
+    if resp.status_code().as_u16() == 404 {
+        return Ok(None);
+    }
+    let v = resp.error_for_status_code()?.json::<Value>().await?;
+    Ok(Some(
+        v.as_object()
+            .map(|m| m.keys().cloned().collect())
+            .unwrap_or_default(),
+    ))
+}
+
+// 同一个请求里把别名指向新索引并删掉旧索引，ES 保证原子生效；
+// 旧的同名普通索引也在这里删掉，换成别名
+async fn swap_aliases(es_client: &Elasticsearch, swaps: &[(&str, &str)]) -> Result<()> {
+    let mut actions = Vec::new();
+    for (alias, index) in swaps {
+        for old in resolve_index(es_client, alias).await?.unwrap_or_default() {
+            actions.push(json!({ "remove_index": { "index": old } }));
+        }
+        actions.push(json!({ "add": { "index": index, "alias": alias } }));
+    }
+    es_client
+        .indices()
+        .update_aliases()
+        .body(json!({ "actions": actions }))
+        .send()
+        .await?
+        .error_for_status_code()?;
+    Ok(())
+}
+
+async fn delete_indices(es_client: &Elasticsearch, indices: &[&str]) -> Result<()> {
+    es_client
+        .indices()
+        .delete(IndicesDeleteParts::Index(indices))
+        .send()
+        .await?
+        .error_for_status_code()?;
+    Ok(())
+}
+
+/// 索引里最后一次更新的时间，增量同步从这里开始；索引还没建时返回 None
+pub async fn last_updated_at(es_client: &Elasticsearch) -> Result<Option<OffsetDateTime>> {
+    let resp = es_client
+        .search(SearchParts::Index(&[INDEX_NAME]))
+        .body(json!({
+            "size": 1,
+            "_source": ["updated_at"],
+            "sort": [ { "updated_at": { "order": "desc" } } ]
+        }))
+        .send()
+        .await?;
+    if !resp.status_code().is_success() {
+        return Ok(None);
+    }
+    let r = resp.json::<Value>().await?;
+    Ok(r["hits"]["hits"][0]["_source"]["updated_at"]
+        .as_str()
+        .and_then(|t| OffsetDateTime::parse(t, &Rfc3339).ok()))
+}
+
+// 第一次同步时建好带版本的索引和别名
+async fn create_indices(es_client: &Elasticsearch) -> Result<()> {
+    let version = OffsetDateTime::now_utc().unix_timestamp();
+    if !index_exist(es_client, INDEX_NAME).await? {
+        let index = format!("{INDEX_NAME}_{version}");
+        create_index(es_client, &index, Some(INDEX_NAME)).await?;
+    }
+    if !index_exist(es_client, COMMENTS_INDEX).await? {
+        let index = format!("{COMMENTS_INDEX}_{version}");
+        create_comments_index(es_client, &index, Some(COMMENTS_INDEX)).await?;
+    }
+    if !index_exist(es_client, REVISIONS_INDEX).await? {
+        revision::create_index(es_client).await?;
+    }
+    Ok(())
+}
+
+// 返回是否记录了新的历史版本和评论数
+async fn sync_issue(
+    conf: &Config,
+    client: &reqwest::Client,
+    mirror: Option<&Mirror>,
+    es_client: &Elasticsearch,
+    indices: &Indices<'_>,
+    issue: &mut Issue,
+) -> Result<(bool, usize)> {
+    prepare_issue(conf, mirror, issue).await;
+    let revised = record_revision(es_client, issue).await?;
+    upsert_issue(es_client, indices.posts, issue).await?;
+    let comments = sync_comments(conf, client, es_client, indices.comments, issue).await?;
+    Ok((revised, comments))
+}
+
+// 覆盖之前先把这一版存进历史
+async fn record_revision(es_client: &Elasticsearch, issue: &Issue) -> Result<bool> {
+    let revised_at = OffsetDateTime::parse(&issue.updated_at, &Rfc3339)
+        .unwrap_or_else(|_| OffsetDateTime::now_utc());
+    let content = issue.body.as_deref().unwrap_or(&issue.body_text);
+    let rev = Revision::new(
+        issue.number,
+        revised_at,
+        &issue.title,
+        &issue.body_text,
+        content,
+    );
+    let revised = revision::record(es_client, &rev).await?;
+    if revised {
+        trace!("文章 {} 记录了新的历史版本 {}", issue.number, rev.revision);
+    }
+    Ok(revised)
+}
+
+/// 仓库的 API 地址，比如 https://api.github.com/repos/hjin-me/blog
+pub(crate) fn repo_api_url(conf: &Config) -> String {
+    let api = match conf.github_api_url.trim_end_matches('/') {
+        "" => "https://api.github.com",
+        api => api,
+    };
+    format!("{api}/repos/{}/{}", conf.github_owner, conf.github_repo)
+}
+
+pub(crate) fn github_client(conf: &Config) -> reqwest::Client {
     let mut header = HeaderMap::new();
     header.insert(
         AUTHORIZATION,
-        HeaderValue::from_str(&format!("Bearer {github_token}")).unwrap(),
+        HeaderValue::from_str(&format!("Bearer {}", conf.github_token)).unwrap(),
     );
     header.insert(
         ACCEPT,
@@ -28,32 +332,164 @@ pub async fn sync_all_issues(
     );
     header.insert(USER_AGENT, HeaderValue::from_str("rust-client").unwrap());
     header.insert("Time-Zone", HeaderValue::from_str("Asia/Shanghai").unwrap());
-    let client = reqwest::ClientBuilder::new()
+    reqwest::ClientBuilder::new()
         .default_headers(header)
         .timeout(Duration::new(10, 0))
         .build()
-        .unwrap();
-    let response = client.get(&request_url).send().await?;
-    // println!("{:?}", response.bytes().await.unwrap());
-    let issues: Vec<Issue> = response.json().await?;
-    // println!("{:?}", &issuess);
+        .unwrap()
+}
+
+// 解析标签和 front matter，渲染正文，生成目录和阅读统计
+async fn prepare_issue(conf: &Config, mirror: Option<&Mirror>, issue: &mut Issue) {
+    issue.publish_at = schedule::publish_at(
+        issue.labels.iter().map(|l| l.name.as_str()),
+        issue.body.as_deref(),
+    );
+    issue.password_hash = if issue.labels.iter().any(|l| l.name == PROTECTED_LABEL) {
+        let h = protect::password_hash(&conf.secret_key(), issue.id, issue.body.as_deref());
+        if h.is_none() {
+            warn!("文章 {} 带有 Protected 标签但没有设置密码", issue.number);
+        }
+        h
+    } else {
+        None
+    };
+    strip_front_matter(issue);
+    let (html, images) = render_body(conf, mirror, issue.body.as_deref(), &issue.body_html).await;
+    issue.images = images;
+    let (html, toc) = toc::anchor_headings(&html);
+    issue.rendered_html = sanitize::clean(&html);
+    issue.toc = toc;
+    issue.reading = reading::stats(&issue.body_text, &issue.rendered_html);
+}
 
-    const INDEX_NAME: &str = "blog";
-    let exist = index_exist(es_client, INDEX_NAME).await?;
-    if !exist {
-        create_index(es_client, INDEX_NAME).await?;
+// front matter 里可能有明文密码，读完就从 body、body_text、body_html 里都去掉，
+// 后面的渲染、索引、历史版本都看不到
+fn strip_front_matter(issue: &mut Issue) {
+    let Some(body) = issue.body.take() else {
+        return;
+    };
+    issue.body_text = front_matter::strip_text(&body, &issue.body_text).to_string();
+    issue.body_html = front_matter::strip_html(&body, &issue.body_html).to_string();
+    let stripped = front_matter::strip(&body);
+    // GitHub 的渲染结果认不出来时，宁可用本地渲染的结果，也不能把密码留在里面
+    if let Some(password) = protect::password(&body) {
+        if issue.body_text.contains(password) || issue.body_html.contains(password) {
+            warn!(
+                "文章 {} 的 body_html 里找不到 front matter，改用本地渲染",
+                issue.number
+            );
+            issue.body_html = markdown::render(stripped);
+            issue.body_text = stripped.to_string();
+        }
     }
-    for issue in issues {
-        upsert_issue(es_client, INDEX_NAME, &issue).await?
+    issue.body = Some(stripped.to_string());
+}
+
+/// 直接从 GitHub 取一篇 issue 并渲染，不写入 ES；草稿、已关闭的 issue 也能取到，用于预览
+pub async fn fetch_post(conf: &Config, number: u64) -> Result<Post> {
+    let mut issue = fetch_issue(conf, &github_client(conf), number).await?;
+    prepare_issue(conf, None, &mut issue).await;
+    Ok(serde_json::from_value(serde_json::to_value(&issue)?)?)
+}
+
+async fn fetch_issue(conf: &Config, client: &reqwest::Client, number: u64) -> Result<Issue> {
+    let url = format!("{}/issues/{number}", repo_api_url(conf));
+    Ok(client
+        .get(&url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+// 文章和评论共用的渲染流程：Markdown/GitHub HTML -> 代码高亮 -> 镜像图片，清理由调用方做
+async fn render_body(
+    conf: &Config,
+    mirror: Option<&Mirror>,
+    body: Option<&str>,
+    body_html: &str,
+) -> (String, Vec<MediaImage>) {
+    let html = if conf.github_html {
+        highlight::rewrite_github_html(body_html)
+    } else {
+        markdown::render(body.unwrap_or_default())
+    };
+    match mirror {
+        Some(m) => m.mirror(&html).await,
+        None => (html, vec![]),
     }
-    Ok(())
 }
+
+async fn sync_comments(
+    conf: &Config,
+    client: &reqwest::Client,
+    es_client: &Elasticsearch,
+    index: &str,
+    issue: &Issue,
+) -> Result<usize> {
+    let comments = if issue.comments > 0 {
+        fetch_comments(client, &issue.comments_url).await?
+    } else {
+        vec![]
+    };
+    let mut ids = Vec::with_capacity(comments.len());
+    for mut comment in comments {
+        render_comment(conf, &mut comment).await;
+        comment.issue_number = issue.number;
+        upsert_comment(es_client, index, &comment).await?;
+        ids.push(comment.id);
+    }
+    // GitHub 上已经删掉的评论
+    delete_stale_comments(es_client, index, issue.number, &ids).await?;
+    Ok(ids.len())
+}
+
+// 任何人都能评论，评论里的图片不镜像，否则评论者可以让服务器去下载任意地址
+async fn render_comment(conf: &Config, comment: &mut IssueComment) {
+    let (html, _) = render_body(conf, None, comment.body.as_deref(), &comment.body_html).await;
+    comment.rendered_html = sanitize::clean(&html);
+}
+
+async fn fetch_comments(client: &reqwest::Client, comments_url: &str) -> Result<Vec<IssueComment>> {
+    fetch_all(client, comments_url, &[]).await
+}
+
+/// 按页拉取全部数据，一页不满说明已经到最后一页
+async fn fetch_all<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+    params: &[(&str, String)],
+) -> Result<Vec<T>> {
+    let mut all = Vec::new();
+    for page in 1.. {
+        let items: Vec<T> = client
+            .get(url)
+            .query(params)
+            .query(&[("per_page", PER_PAGE), ("page", page)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let n = items.len();
+        all.extend(items);
+        if n < PER_PAGE {
+            break;
+        }
+    }
+    Ok(all)
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct IssueUser {
     login: String,
     id: u64,
     node_id: String,
     avatar_url: String,
+    #[serde(default)]
+    html_url: String, // "https://github.com/hjin-me",
 }
 #[derive(Deserialize, Serialize, Debug)]
 pub struct IssueLabel {
@@ -64,18 +500,26 @@ pub struct IssueLabel {
     color: String,       //"0e8a16",
     description: String, // "可以被展现的文章"
 }
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Default)]
 pub struct IssueReactions {
     // "url": "https://api.github.com/repos/hjin-me/blog/issues/16/reactions",
     total_count: u64, // 0,
-                      // "+1": 0,
-                      // "-1": 0,
-                      // "laugh": 0,
-                      // "hooray": 0,
-                      // "confused": 0,
-                      // "heart": 0,
-                      // "rocket": 0,
-                      // "eyes": 0
+    #[serde(rename = "+1", default)]
+    plus_one: u64,
+    #[serde(rename = "-1", default)]
+    minus_one: u64,
+    #[serde(default)]
+    laugh: u64,
+    #[serde(default)]
+    hooray: u64,
+    #[serde(default)]
+    confused: u64,
+    #[serde(default)]
+    heart: u64,
+    #[serde(default)]
+    rocket: u64,
+    #[serde(default)]
+    eyes: u64,
 }
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Issue {
@@ -110,17 +554,53 @@ pub struct Issue {
     // "closed_at": null,
     // "author_association": "OWNER",
     // "active_lock_reason": null,
-    body_text: String, // "## 如何
-    body_html: String, // "## 如
+    #[serde(default)]
+    html_url: String, // "https://github.com/hjin-me/blog/issues/16",
+    body: Option<String>, // "## 如何
+    body_text: String,    // "## 如何
+    body_html: String,    // "## 如
+    // 同步时生成的展示用 HTML：默认由 body 在本地渲染，开启 github_html 时由 body_html 重新高亮代码得到
+    #[serde(default)]
+    rendered_html: String,
+    #[serde(default)]
+    toc: Vec<TocEntry>,
+    #[serde(default)]
+    images: Vec<MediaImage>,
+    #[serde(default)]
+    reading: ReadingStats,
+    // 定时发布时间，在这之前查询时会被过滤掉
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    publish_at: Option<time::OffsetDateTime>,
+    // Protected 文章的密码 hash
+    #[serde(default)]
+    password_hash: Option<String>,
     reactions: IssueReactions, // "timeline_url": "https://api.github.com/repos/hjin-me/blog/issues/16/timeline",
                                // "performed_via_github_app": null,
                                // "state_reason": null
 }
 
+#[derive(Deserialize, Serialize, Debug)]
+pub struct IssueComment {
+    id: u64,
+    // 同步时填上所属文章的 number
+    #[serde(default)]
+    issue_number: u64,
+    html_url: String, // "https://github.com/hjin-me/blog/issues/16#issuecomment-1",
+    user: IssueUser,
+    created_at: String, //"2017-06-05T02:27:43Z",
+    updated_at: String,
+    author_association: String, // "OWNER",
+    body: Option<String>,
+    #[serde(default)]
+    body_html: String,
+    #[serde(default)]
+    rendered_html: String,
+}
+
 async fn index_exist(client: &Elasticsearch, index: &str) -> Result<bool, elasticsearch::Error> {
     let resp = client
         .indices()
-        .exists(IndicesExistsParts::Index(&[&index]))
+        .exists(IndicesExistsParts::Index(&[index]))
         .request_timeout(Duration::new(1, 0))
         .send()
         .await?; //.map(|r| r.status_code() == 200)
@@ -133,56 +613,92 @@ async fn index_exist(client: &Elasticsearch, index: &str) -> Result<bool, elasti
     }
 }
 
-async fn create_index(client: &Elasticsearch, index: &str) -> Result<(), elasticsearch::Error> {
+/// 文章索引的字段定义，check 时也用来和线上的 mapping 对比
+pub(crate) fn blog_mappings() -> Value {
+    json!({
+      "properties": {
+        "body": {
+          "type": "text",
+          "index": false
+        },
+        "body_html": {
+          "type": "text"
+        },
+        "rendered_html": {
+          "type": "text",
+          "index": false
+        },
+        "toc": {
+          "type": "object",
+          "enabled": false
+        },
+        "images": {
+          "type": "object",
+          "enabled": false
+        },
+        "reading": {
+          "properties": {
+            "words": {
+              "type": "integer"
+            },
+            "minutes": {
+              "type": "integer"
+            }
+          }
+        },
+        "body_text": {
+          "type": "text",
+          "analyzer": "ik_max_word",
+          "search_analyzer": "ik_max_word"
+        },
+        "number": {
+          "type": "integer"
+        },
+        "closed": {
+          "type": "boolean"
+        },
+        "created_at": {
+          "type": "date"
+        },
+        "publish_at": {
+          "type": "date"
+        },
+        "password_hash": {
+          "type": "keyword",
+          "index": false
+        },
+        "title": {
+          "type": "text",
+          "analyzer": "ik_max_word",
+          "search_analyzer": "ik_max_word"
+        },
+        "updated_at": {
+          "type": "date"
+        },
+        "labels": {
+          "type": "nested",
+          "properties": {
+            "description": {
+              "type": "text"
+            },
+            "name": {
+              "type": "keyword"
+            }
+          }
+        }
+      }
+    })
+}
+
+async fn create_index(
+    client: &Elasticsearch,
+    index: &str,
+    alias: Option<&str>,
+) -> Result<(), elasticsearch::Error> {
     let resp = client
         .indices()
-        .create(IndicesCreateParts::Index(&index))
-        .body(json!({
-          "settings": {
-            "number_of_shards": 1,
-            "number_of_replicas": 0
-          },
-          "mappings": {
-              "properties": {
-                "body_html": {
-                  "type": "text"
-                },
-                "body_text": {
-                  "type": "text",
-                  "analyzer": "ik_max_word",
-                  "search_analyzer": "ik_max_word"
-                },
-                "number": {
-                  "type": "integer"
-                },
-                "closed": {
-                  "type": "boolean"
-                },
-                "created_at": {
-                  "type": "date"
-                },
-                "title": {
-                  "type": "text",
-                  "analyzer": "ik_max_word",
-                  "search_analyzer": "ik_max_word"
-                },
-                "updated_at": {
-                  "type": "date"
-                },
-                "labels": {
-                  "type": "nested",
-                  "properties": {
-                    "description": {
-                      "type": "text"
-                    },
-                    "name": {
-                      "type": "keyword"
-                    }
-                  }
-                }
-              }
-            }
-        }))
+        .create(IndicesCreateParts::Index(index))
+        .body(index_body(blog_mappings(), alias))
         .request_timeout(Duration::new(1, 0))
         .send()
         .await?;
@@ -196,13 +712,73 @@ async fn create_index(client: &Elasticsearch, index: &str) -> Result<(), elastic
     }
 }
 
+fn index_body(mappings: Value, alias: Option<&str>) -> Value {
+    let mut body = json!({
+      "settings": {
+        "number_of_shards": 1,
+        "number_of_replicas": 0
+      },
+      "mappings": mappings
+    });
+    if let Some(alias) = alias {
+        body["aliases"] = json!({ alias: {} });
+    }
+    body
+}
+
+pub(crate) fn comments_mappings() -> Value {
+    json!({
+      "properties": {
+        "issue_number": {
+          "type": "integer"
+        },
+        "created_at": {
+          "type": "date"
+        },
+        "updated_at": {
+          "type": "date"
+        },
+        "body": {
+          "type": "text",
+          "index": false
+        },
+        "body_html": {
+          "type": "text",
+          "index": false
+        },
+        "rendered_html": {
+          "type": "text",
+          "index": false
+        }
+      }
+    })
+}
+
+async fn create_comments_index(
+    client: &Elasticsearch,
+    index: &str,
+    alias: Option<&str>,
+) -> Result<(), elasticsearch::Error> {
+    let resp = client
+        .indices()
+        .create(IndicesCreateParts::Index(index))
+        .body(index_body(comments_mappings(), alias))
+        .request_timeout(Duration::new(1, 0))
+        .send()
+        .await?;
+    match resp.error_for_status_code() {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
 async fn upsert_issue(
     client: &Elasticsearch,
     index: &str,
     issue: &Issue,
 ) -> Result<(), elasticsearch::Error> {
     let resp = client
-        .update(UpdateParts::IndexId(&index, &issue.id.to_string()))
+        .update(UpdateParts::IndexId(index, &issue.id.to_string()))
         .body(json!({
             "doc": issue,
             "doc_as_upsert": true
@@ -218,13 +794,58 @@ async fn upsert_issue(
     }
 }
 
+async fn upsert_comment(
+    client: &Elasticsearch,
+    index: &str,
+    comment: &IssueComment,
+) -> Result<(), elasticsearch::Error> {
+    let resp = client
+        .update(UpdateParts::IndexId(index, &comment.id.to_string()))
+        .body(json!({
+            "doc": comment,
+            "doc_as_upsert": true
+        }))
+        .request_timeout(Duration::new(1, 0))
+        .send()
+        .await?;
+    match resp.error_for_status_code() {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+async fn delete_stale_comments(
+    client: &Elasticsearch,
+    index: &str,
+    issue_number: u64,
+    keep: &[u64],
+) -> Result<(), elasticsearch::Error> {
+    let resp = client
+        .delete_by_query(DeleteByQueryParts::Index(&[index]))
+        .body(json!({
+            "query": {
+                "bool": {
+                    "filter": [{ "term": { "issue_number": issue_number } }],
+                    "must_not": [{ "ids": { "values": keep.iter().map(|i| i.to_string()).collect::<Vec<_>>() } }]
+                }
+            }
+        }))
+        .request_timeout(Duration::new(5, 0))
+        .send()
+        .await?;
+    match resp.error_for_status_code() {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use std::fs;
 
     #[derive(Debug, Deserialize)]
-    struct Config {
+    struct TestConfig {
         // github_sec: String,
         github_token: String,
         es_url: String,
@@ -234,18 +855,19 @@ mod test {
     async fn test_all_issues() {
         let contents =
             fs::read_to_string("./config.toml").expect("Should have been able to read the file");
-        let conf: Config = toml::from_str(contents.as_str()).unwrap();
-
-        let github_token = conf.github_token;
-        let owner = "hjin-me".to_string();
-        let repo = "blog".to_string();
+        let conf: TestConfig = toml::from_str(contents.as_str()).unwrap();
+        let conf = Config {
+            github_token: conf.github_token,
+            github_owner: "hjin-me".to_string(),
+            github_repo: "blog".to_string(),
+            es_url: conf.es_url,
+            ..Default::default()
+        };
 
         let transport =
             elasticsearch::http::transport::Transport::single_node(conf.es_url.as_str()).unwrap();
         let client = Elasticsearch::new(transport);
-        sync_all_issues(&github_token, &owner, &repo, &client)
-            .await
-            .unwrap();
+        sync_all_issues(&conf, &client).await.unwrap();
         // assert_eq!(1, 2)
     }
 
@@ -257,8 +879,8 @@ mod test {
         let client = Elasticsearch::new(transport);
         let rand_index = format!("test_{}", time::OffsetDateTime::now_utc().unix_timestamp());
         println!("{:?}", index_exist(&client, &rand_index).await.unwrap());
-        create_index(&client, &rand_index).await.unwrap();
-        assert_eq!(true, index_exist(&client, &rand_index).await.unwrap());
+        create_index(&client, &rand_index, None).await.unwrap();
+        assert!(index_exist(&client, &rand_index).await.unwrap());
 
         upsert_issue(
             &client,
@@ -266,6 +888,7 @@ mod test {
             &Issue {
                 comments_url: "https://api.github.com/repos/hjin-me/blog/issues/16/comments"
                     .to_string(),
+                html_url: "https://github.com/hjin-me/blog/issues/16".to_string(),
                 id: 233479897,
                 node_id: "MDU6SXNzdWUyMzM0Nzk4OTc=".to_string(),
                 number: 16,
@@ -275,18 +898,209 @@ mod test {
                     id: 102523,
                     node_id: "MDQ6VXNlcjEwMjUyMw==".to_string(),
                     avatar_url: "https://avatars.githubusercontent.com/u/102523?v=4".to_string(),
+                    html_url: "https://github.com/hjin-me".to_string(),
                 },
                 labels: vec![],
                 state: "".to_string(),
                 comments: 0,
                 created_at: "2018-05-23T16:30:10Z".to_string(),
                 updated_at: "2018-05-23T16:30:10Z".to_string(),
+                body: Some("# this is **HTML**".to_string()),
                 body_text: "this is text".to_string(),
                 body_html: "<h1>this is <b>HTML</b></h1>".to_string(),
-                reactions: IssueReactions { total_count: 0 },
+                rendered_html: "<h1>this is <strong>HTML</strong></h1>".to_string(),
+                toc: vec![],
+                images: vec![],
+                reading: ReadingStats {
+                    words: 3,
+                    minutes: 1,
+                },
+                publish_at: None,
+                password_hash: None,
+                reactions: IssueReactions {
+                    total_count: 3,
+                    plus_one: 2,
+                    heart: 1,
+                    ..Default::default()
+                },
             },
         )
         .await
         .unwrap();
     }
+
+    // 密码不能出现在写进 ES 的任何字段里
+    #[tokio::test]
+    async fn test_prepare_protected() {
+        let body = "---\npassword: s3cret-pass\n---\n# 正文\n\n内容";
+        for (github_html, body_html) in [
+            (false, "<hr>\n<h2 dir=\"auto\">password: s3cret-pass</h2>\n<h1 dir=\"auto\">正文</h1>\n<p dir=\"auto\">内容</p>"),
+            // 认不出的格式时改用本地渲染
+            (true, "<hr><p>password: s3cret-pass</p><h1>正文</h1><p>内容</p>"),
+        ] {
+            let mut issue: Issue = serde_json::from_value(json!({
+                "url": "", "repository_url": "", "labels_url": "", "comments_url": "",
+                "events_url": "", "html_url": "", "id": 1, "node_id": "", "number": 1,
+                "title": "加密文章",
+                "user": {"login": "hjin-me", "id": 1, "node_id": "x", "avatar_url": "a", "html_url": ""},
+                "labels": [{"id": 1, "node_id": "", "url": "", "name": PROTECTED_LABEL, "color": "", "default": false, "description": ""}],
+                "state": "open", "comments": 0,
+                "created_at": "2018-05-23T16:30:10Z", "updated_at": "2018-05-23T16:30:10Z",
+                "body": body,
+                "body_text": "password: s3cret-pass\n正文\n内容",
+                "body_html": body_html,
+                "reactions": {"total_count": 0}
+            }))
+            .unwrap();
+            let conf = Config {
+                github_html,
+                ..Default::default()
+            };
+            prepare_issue(&conf, None, &mut issue).await;
+            assert!(issue.password_hash.is_some());
+            assert!(issue.rendered_html.contains("正文"), "{}", issue.rendered_html);
+            let indexed = serde_json::to_string(&issue).unwrap();
+            assert!(!indexed.contains("s3cret-pass"), "{indexed}");
+        }
+    }
+
+    #[test]
+    fn test_sync_report() {
+        let mut report = SyncReport {
+            issues: 3,
+            comments: 10,
+            revisions: 1,
+            failed: vec![],
+        };
+        assert_eq!(
+            report.to_string(),
+            "同步了 3 篇文章、10 条评论，新增 1 个历史版本"
+        );
+        report.failed.push((16, "timeout".to_string()));
+        assert_eq!(
+            report.to_string(),
+            "同步了 3 篇文章、10 条评论，新增 1 个历史版本，1 篇失败：\n  #16: timeout"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_comments() {
+        use axum::extract::Query;
+        use axum::routing::get;
+        use axum::{Json, Router};
+        use std::collections::HashMap;
+
+        // 假的 GitHub 评论接口，共 150 条
+        let app = Router::new().route(
+            "/comments",
+            get(|Query(q): Query<HashMap<String, usize>>| async move {
+                let (page, per_page) = (q["page"], q["per_page"]);
+                let ids = ((page - 1) * per_page + 1)..=(page * per_page).min(150);
+                Json(
+                    ids.map(|id| {
+                        json!({
+                            "id": id,
+                            "html_url": format!("https://github.com/hjin-me/blog/issues/1#issuecomment-{id}"),
+                            "user": {"login": "hjin-me", "id": 1, "node_id": "x", "avatar_url": "a"},
+                            "created_at": "2018-05-23T16:30:10Z",
+                            "updated_at": "2018-05-23T16:30:10Z",
+                            "author_association": "OWNER",
+                            "body": "**hi**",
+                            "body_html": "<p><strong>hi</strong></p>"
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+                )
+            }),
+        );
+        let addr = crate::testing::serve(app).await;
+
+        let comments = fetch_comments(&reqwest::Client::new(), &format!("http://{addr}/comments"))
+            .await
+            .unwrap();
+        assert_eq!(comments.len(), 150);
+        assert_eq!(comments[0].id, 1);
+        assert_eq!(comments[149].id, 150);
+        assert_eq!(comments[0].body.as_deref(), Some("**hi**"));
+    }
+
+    #[tokio::test]
+    async fn test_swap_aliases() {
+        use axum::extract::Path;
+        use axum::routing::{get, post};
+        use axum::{Json, Router};
+        use std::sync::{Arc, Mutex};
+
+        // blog 还是普通索引，blog_comments 已经是别名
+        let actions = Arc::new(Mutex::new(Value::Null));
+        let recorded = actions.clone();
+        let app = Router::new()
+            .route(
+                "/:index/_alias",
+                get(|Path(index): Path<String>| async move {
+                    let body = match index.as_str() {
+                        "blog" => json!({ "blog": { "aliases": {} } }),
+                        _ => json!({ "blog_comments_1": { "aliases": { "blog_comments": {} } } }),
+                    };
+                    Json(body)
+                }),
+            )
+            .route(
+                "/_aliases",
+                post(move |Json(body): Json<Value>| async move {
+                    *recorded.lock().unwrap() = body["actions"].clone();
+                    Json(json!({ "acknowledged": true }))
+                }),
+            );
+        let addr = crate::testing::serve(app).await;
+        let es_client = crate::es::init(&format!("http://{addr}")).unwrap();
+        swap_aliases(
+            &es_client,
+            &[(INDEX_NAME, "blog_2"), (COMMENTS_INDEX, "blog_comments_2")],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            *actions.lock().unwrap(),
+            json!([
+                { "remove_index": { "index": "blog" } },
+                { "add": { "index": "blog_2", "alias": "blog" } },
+                { "remove_index": { "index": "blog_comments_1" } },
+                { "add": { "index": "blog_comments_2", "alias": "blog_comments" } },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_index_body() {
+        let body = index_body(json!({}), Some(INDEX_NAME));
+        assert_eq!(body["aliases"], json!({ "blog": {} }));
+        assert!(index_body(json!({}), None).get("aliases").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_render_comment() {
+        // 评论里的图片保留原地址，不下载
+        let mut comment: IssueComment = serde_json::from_value(json!({
+            "id": 1,
+            "html_url": "https://github.com/hjin-me/blog/issues/1#issuecomment-1",
+            "user": {"login": "someone", "id": 2, "node_id": "x", "avatar_url": "a"},
+            "created_at": "2018-05-23T16:30:10Z",
+            "updated_at": "2018-05-23T16:30:10Z",
+            "author_association": "NONE",
+            "body": "![x](https://user-images.githubusercontent.com/1/a.png)",
+            "body_html": ""
+        }))
+        .unwrap();
+        let dir = crate::testing::temp_dir("comment").join("media");
+        let conf = Config {
+            media_dir: dir.display().to_string(),
+            ..Default::default()
+        };
+        render_comment(&conf, &mut comment).await;
+        assert!(comment
+            .rendered_html
+            .contains(r#"src="https://user-images.githubusercontent.com/1/a.png""#));
+        assert!(!dir.exists());
+    }
 }
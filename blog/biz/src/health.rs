@@ -0,0 +1,392 @@
+use crate::github_issues::{resolve_index, INDEX_NAME};
+use crate::syncer::{SyncStatus, Syncer};
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use elasticsearch::{CountParts, Elasticsearch};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::warn;
+
+// watchdog 多久跳一次
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+// 心跳超过这么久没更新就认为 runtime 卡住了
+const LIVENESS_TIMEOUT: Duration = Duration::from_secs(10);
+const ES_TIMEOUT: Duration = Duration::from_secs(2);
+
+static HEARTBEAT: Heartbeat = Heartbeat::new();
+
+/// watchdog 最近一次运行的时间，单位毫秒，0 表示还没运行过
+#[derive(Debug)]
+pub struct Heartbeat(AtomicI64);
+
+impl Heartbeat {
+    const fn new() -> Self {
+        Heartbeat(AtomicI64::new(0))
+    }
+
+    fn beat(&self, now: OffsetDateTime) {
+        self.0.store(millis(now), Ordering::Relaxed);
+    }
+
+    /// 距离上次心跳多久了，还没跳过时返回 None
+    fn age(&self, now: OffsetDateTime) -> Option<Duration> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            last => Some(Duration::from_millis((millis(now) - last).max(0) as u64)),
+        }
+    }
+}
+
+fn millis(t: OffsetDateTime) -> i64 {
+    (t.unix_timestamp_nanos() / 1_000_000) as i64
+}
+
+/// 在 runtime 里定时更新心跳；有任务长时间占住线程时心跳会停，liveness 随之失败
+pub fn spawn_watchdog() {
+    tokio::spawn(async {
+        loop {
+            let started = std::time::Instant::now();
+            HEARTBEAT.beat(OffsetDateTime::now_utc());
+            tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+            let lag = started.elapsed().saturating_sub(HEARTBEAT_INTERVAL);
+            if lag > HEARTBEAT_INTERVAL {
+                warn!("runtime 卡顿，watchdog 晚了 {:?}", lag);
+            }
+        }
+    });
+}
+
+pub async fn liveness() -> impl IntoResponse {
+    liveness_response(&HEARTBEAT, OffsetDateTime::now_utc())
+}
+
+fn liveness_response(heartbeat: &Heartbeat, now: OffsetDateTime) -> (StatusCode, String) {
+    match heartbeat.age(now) {
+        Some(age) if age <= LIVENESS_TIMEOUT => (StatusCode::OK, "I'm alive!".to_string()),
+        Some(age) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("心跳已经 {} 秒没有更新", age.as_secs()),
+        ),
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "watchdog 还没有运行".to_string(),
+        ),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Component {
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Component {
+    fn ok(detail: impl Into<String>) -> Self {
+        Component {
+            ok: true,
+            detail: detail.into(),
+        }
+    }
+
+    fn fail(detail: impl Into<String>) -> Self {
+        Component {
+            ok: false,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// readiness 的响应，三项都通过才算就绪
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub elasticsearch: Component,
+    pub index: Component,
+    pub sync: Component,
+    /// 距离上次成功同步的秒数
+    pub sync_age_secs: Option<i64>,
+    /// blog 索引里的文章数
+    pub documents: Option<u64>,
+    /// 最近一次同步里没同步成功的文章，不影响就绪
+    pub failed_issues: Vec<u64>,
+}
+
+/// 检查 ES 能否连上、blog 别名和它指向的索引是否存在，以及是否成功同步过
+pub async fn check_readiness(
+    es_client: &Elasticsearch,
+    status: &SyncStatus,
+    now: OffsetDateTime,
+) -> Readiness {
+    let ping = es_client
+        .ping()
+        .request_timeout(ES_TIMEOUT)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status_code());
+    let (elasticsearch, index, documents) = match ping {
+        Err(err) => (
+            Component::fail(format!("连接 ES 失败：{err}")),
+            Component::fail("ES 不可用，无法检查"),
+            None,
+        ),
+        Ok(_) => {
+            let (index, documents) = check_index(es_client).await;
+            (Component::ok("可以连接"), index, documents)
+        }
+    };
+
+    let sync_age_secs = status
+        .last_success_at
+        .map(|t| (now - t).whole_seconds().max(0));
+    let sync = match (sync_age_secs, &status.last_error) {
+        (Some(age), _) if !status.failed_issues.is_empty() => Component::ok(format!(
+            "{age} 秒前同步成功，{} 篇文章同步失败",
+            status.failed_issues.len()
+        )),
+        (Some(age), _) => Component::ok(format!("{age} 秒前同步成功")),
+        // --skip-sync 启动时索引里有文章就可以提供服务
+        (None, _) if status.initial_sync_skipped && documents.is_some_and(|n| n > 0) => {
+            Component::ok("启动时跳过了同步，使用索引里已有的文章")
+        }
+        (None, Some(err)) => Component::fail(format!("还没有成功同步过，最近一次失败：{err}")),
+        (None, None) if status.running => Component::fail("首次同步进行中"),
+        (None, None) => Component::fail("还没有成功同步过"),
+    };
+    Readiness {
+        ready: elasticsearch.ok && index.ok && sync.ok,
+        elasticsearch,
+        index,
+        sync,
+        sync_age_secs,
+        documents,
+        failed_issues: status.failed_issues.clone(),
+    }
+}
+
+// blog 别名和它指向的索引都要存在；还是普通索引的旧部署照常可用，只提示迁移
+async fn check_index(es_client: &Elasticsearch) -> (Component, Option<u64>) {
+    let index = match resolve_index(es_client, INDEX_NAME).await {
+        Ok(None) => return (Component::fail(format!("索引 {INDEX_NAME} 不存在")), None),
+        Ok(Some(indices)) if indices.iter().any(|i| i == INDEX_NAME) => Component::ok(format!(
+            "索引 {INDEX_NAME} 存在，还不是别名，运行 reindex 迁移"
+        )),
+        Ok(Some(indices)) if indices.len() == 1 => {
+            Component::ok(format!("别名 {INDEX_NAME} 指向 {}", indices[0]))
+        }
+        Ok(Some(indices)) => {
+            let detail = format!("别名 {INDEX_NAME} 指向了多个索引：{}", indices.join(", "));
+            return (Component::fail(detail), None);
+        }
+        Err(err) => {
+            let detail = format!("查询索引 {INDEX_NAME} 失败：{err}");
+            return (Component::fail(detail), None);
+        }
+    };
+    let resp = es_client
+        .count(CountParts::Index(&[INDEX_NAME]))
+        .request_timeout(ES_TIMEOUT)
+        .send()
+        .await;
+    match resp {
+        Ok(r) if r.status_code().as_u16() == 404 => {
+            (Component::fail(format!("索引 {INDEX_NAME} 不存在")), None)
+        }
+        Ok(r) if !r.status_code().is_success() => (
+            Component::fail(format!("查询索引 {INDEX_NAME} 失败：{}", r.status_code())),
+            None,
+        ),
+        Ok(r) => match r.json::<Value>().await {
+            Ok(v) => (index, v["count"].as_u64()),
+            Err(err) => (Component::fail(format!("无法解析 ES 的响应：{err}")), None),
+        },
+        Err(err) => (
+            Component::fail(format!("查询索引 {INDEX_NAME} 失败：{err}")),
+            None,
+        ),
+    }
+}
+
+pub async fn readiness(
+    Extension(es_client): Extension<Arc<Elasticsearch>>,
+    Extension(syncer): Extension<Arc<Syncer>>,
+) -> impl IntoResponse {
+    let r = check_readiness(&es_client, &syncer.status(), OffsetDateTime::now_utc()).await;
+    let status = if r.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(r))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{self, post};
+    use time::macros::datetime;
+
+    const NOW: OffsetDateTime = datetime!(2023-08-01 12:00 UTC);
+
+    #[test]
+    fn test_liveness() {
+        let heartbeat = Heartbeat::new();
+        assert_eq!(
+            liveness_response(&heartbeat, NOW).0,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        heartbeat.beat(NOW);
+        assert_eq!(
+            liveness_response(&heartbeat, NOW + Duration::from_secs(3)),
+            (StatusCode::OK, "I'm alive!".to_string())
+        );
+        // runtime 卡住，心跳停了
+        assert_eq!(
+            liveness_response(&heartbeat, NOW + Duration::from_secs(30)),
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "心跳已经 30 秒没有更新".to_string()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watchdog() {
+        spawn_watchdog();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(liveness().await.into_response().status(), StatusCode::OK);
+    }
+
+    // 假 ES：index 为 false 时 blog 索引不存在，否则里面有两篇文章
+    async fn fake_es(index: bool) -> Elasticsearch {
+        testing::fake_es(index.then(|| vec![post(1, None), post(2, None)])).await
+    }
+
+    fn synced() -> SyncStatus {
+        SyncStatus {
+            last_success_at: Some(NOW - Duration::from_secs(90)),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ready() {
+        let r = check_readiness(&fake_es(true).await, &synced(), NOW).await;
+        assert!(r.ready, "{r:?}");
+        assert_eq!(r.sync_age_secs, Some(90));
+        assert_eq!(r.documents, Some(2));
+        assert_eq!(r.index.detail, "别名 blog 指向 blog_1");
+        assert_eq!(r.sync.detail, "90 秒前同步成功");
+    }
+
+    #[tokio::test]
+    async fn test_legacy_index() {
+        use axum::routing::get;
+        use axum::Router;
+
+        // 旧部署里 blog 是普通索引
+        let app = Router::new()
+            .route("/", get(|| async { "{}" }))
+            .route(
+                "/blog/_alias",
+                get(|| async { r#"{"blog":{"aliases":{}}}"# }),
+            )
+            .route("/blog/_count", get(|| async { r#"{"count":3}"# }));
+        let addr = testing::serve(app).await;
+        let es_client = crate::es::init(&format!("http://{addr}")).unwrap();
+        let r = check_readiness(&es_client, &synced(), NOW).await;
+        assert!(r.ready, "{r:?}");
+        assert_eq!(
+            r.index.detail,
+            "索引 blog 存在，还不是别名，运行 reindex 迁移"
+        );
+        assert_eq!(r.documents, Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_partial_failure() {
+        let status = SyncStatus {
+            failed_issues: vec![16],
+            ..synced()
+        };
+        let r = check_readiness(&fake_es(true).await, &status, NOW).await;
+        assert!(r.ready, "{r:?}");
+        assert_eq!(r.sync.detail, "90 秒前同步成功，1 篇文章同步失败");
+        assert_eq!(r.failed_issues, vec![16]);
+    }
+
+    #[tokio::test]
+    async fn test_skip_sync() {
+        let status = SyncStatus {
+            initial_sync_skipped: true,
+            ..Default::default()
+        };
+        let r = check_readiness(&fake_es(true).await, &status, NOW).await;
+        assert!(r.ready, "{r:?}");
+        assert_eq!(r.sync_age_secs, None);
+        // 索引不存在时还是不能就绪
+        let r = check_readiness(&fake_es(false).await, &status, NOW).await;
+        assert!(!r.ready);
+        assert!(!r.sync.ok);
+    }
+
+    #[tokio::test]
+    async fn test_es_down() {
+        let es_client = crate::es::init("http://127.0.0.1:9").unwrap();
+        let r = check_readiness(&es_client, &synced(), NOW).await;
+        assert!(!r.ready);
+        assert!(!r.elasticsearch.ok);
+        assert!(!r.index.ok);
+        assert!(r.sync.ok);
+        assert_eq!(r.documents, None);
+    }
+
+    #[tokio::test]
+    async fn test_index_missing() {
+        let r = check_readiness(&fake_es(false).await, &synced(), NOW).await;
+        assert!(!r.ready);
+        assert!(r.elasticsearch.ok);
+        assert_eq!(r.index, Component::fail("索引 blog 不存在"));
+    }
+
+    #[tokio::test]
+    async fn test_never_synced() {
+        let es_client = fake_es(true).await;
+        let r = check_readiness(&es_client, &SyncStatus::default(), NOW).await;
+        assert!(!r.ready);
+        assert_eq!(r.sync, Component::fail("还没有成功同步过"));
+        assert_eq!(r.sync_age_secs, None);
+
+        let status = SyncStatus {
+            running: true,
+            ..Default::default()
+        };
+        let r = check_readiness(&es_client, &status, NOW).await;
+        assert_eq!(r.sync, Component::fail("首次同步进行中"));
+
+        let status = SyncStatus {
+            last_error: Some("GitHub 超时".to_string()),
+            consecutive_failures: 1,
+            ..Default::default()
+        };
+        let r = check_readiness(&es_client, &status, NOW).await;
+        assert_eq!(
+            r.sync,
+            Component::fail("还没有成功同步过，最近一次失败：GitHub 超时")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_readiness_status() {
+        let syncer = Arc::new(Syncer::new());
+        let res = readiness(Extension(Arc::new(fake_es(true).await)), Extension(syncer))
+            .await
+            .into_response();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::utils::{escape, unescape};
+use lazy_static::lazy_static;
+use lol_html::html_content::ContentType;
+use lol_html::{element, end_tag, rewrite_str, text, RewriteStrSettings};
+use std::cell::RefCell;
+use std::rc::Rc;
+use syntect::html::{ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+/// 生成的 span 都带 hl- 前缀，对应 style.css 里的主题，避免和 GitHub 的 pl- 样式冲突
+pub const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+lazy_static! {
+    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+}
+
+/// 高亮一段代码，返回完整的 `<pre>` 块；识别不了语言时返回 None，由调用方按普通代码块输出
+pub fn highlight(code: &str, lang: &str) -> Option<String> {
+    let inner = highlight_inner(code, lang)?;
+    // lang 来自文章内容，写进属性前转义，不依赖后面的语法查找和 ammonia
+    Some(format!(
+        r#"<pre class="hl-code"><code class="language-{}">{}</code></pre>"#,
+        escape(lang),
+        inner
+    ))
+}
+
+fn highlight_inner(code: &str, lang: &str) -> Option<String> {
+    if lang.is_empty() {
+        return None;
+    }
+    let syntax = SYNTAX_SET.find_syntax_by_token(lang)?;
+    let mut generator =
+        ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAX_SET, CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        generator
+            .parse_html_for_line_which_includes_newline(line)
+            .ok()?;
+    }
+    Some(generator.finalize())
+}
+
+/// 重新高亮 GitHub body_html 里的代码块，语言取自 `highlight-source-*` class
+pub fn rewrite_github_html(html: &str) -> String {
+    const BLOCK: &str = r#"div[class*="highlight-source-"]"#;
+    let lang = Rc::new(RefCell::new(String::new()));
+    let code = Rc::new(RefCell::new(String::new()));
+    let pre = format!("{BLOCK} > pre");
+    let pre_descendants = format!("{BLOCK} > pre *");
+    let rewritten = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!(BLOCK, |el| {
+                    *lang.borrow_mut() =
+                        github_lang(&el.get_attribute("class").unwrap_or_default());
+                    Ok(())
+                }),
+                // 丢掉 GitHub 自带的 pl-* span，只保留文本
+                element!(pre_descendants, |el| {
+                    el.remove_and_keep_content();
+                    Ok(())
+                }),
+                text!(pre, |t| {
+                    code.borrow_mut().push_str(t.as_str());
+                    t.remove();
+                    Ok(())
+                }),
+                element!(pre, |el| {
+                    let lang = lang.clone();
+                    let code = code.clone();
+                    el.set_attribute("class", "hl-code")?;
+                    el.on_end_tag(end_tag!(move |end| {
+                        let raw = unescape(&code.take());
+                        let inner =
+                            highlight_inner(&raw, &lang.borrow()).unwrap_or_else(|| escape(&raw));
+                        end.before(&inner, ContentType::Html);
+                        Ok(())
+                    }))
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    );
+    rewritten.unwrap_or_else(|_| html.to_string())
+}
+
+// highlight highlight-source-rust notranslate => rust
+fn github_lang(class: &str) -> String {
+    class
+        .split_whitespace()
+        .find_map(|c| c.strip_prefix("highlight-source-"))
+        .map(|l| l.rsplit('.').next().unwrap_or(l).to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_highlight() {
+        let html = highlight("fn main() {}\n", "rust").unwrap();
+        assert!(html.starts_with(r#"<pre class="hl-code"><code class="language-rust">"#));
+        assert!(html.contains(r#"<span class="hl-storage hl-type hl-function hl-rust">fn</span>"#));
+        assert!(highlight("fn main() {}\n", "no-such-lang").is_none());
+        assert!(highlight("fn main() {}\n", "").is_none());
+    }
+
+    #[test]
+    fn test_rewrite_github_html() {
+        let html = r#"<p>a</p><div class="highlight highlight-source-rust notranslate position-relative overflow-auto" dir="auto"><pre><span class="pl-k">fn</span> <span class="pl-en">main</span>() { <span class="pl-s">"&lt;b&gt;"</span>; }</pre></div>"#;
+        let out = rewrite_github_html(html);
+        assert!(!out.contains("pl-"));
+        assert!(out.contains(r#"<pre class="hl-code"><span class="hl-source hl-rust">"#));
+        assert!(out.contains("&lt;b&gt;"));
+        assert!(out.starts_with("<p>a</p>"));
+        // 不认识的语言保留原文
+        let html = r#"<div class="highlight highlight-source-unknown"><pre>a &amp; b</pre></div>"#;
+        assert_eq!(
+            rewrite_github_html(html),
+            r#"<div class="highlight highlight-source-unknown"><pre class="hl-code">a &amp; b</pre></div>"#
+        );
+        assert_eq!(github_lang("highlight highlight-source-js"), "js");
+        assert_eq!(github_lang("highlight"), "");
+    }
+}
@@ -1,30 +1,111 @@
 pub mod blog;
+pub mod check;
 pub mod es;
+pub mod feed;
+pub mod front_matter;
 pub mod github_hook;
 pub mod github_issues;
+pub mod health;
+pub mod highlight;
+pub mod markdown;
+pub mod media;
+pub mod metrics;
+pub mod protect;
+pub mod reading;
+pub mod redact;
+pub mod revision;
+pub mod sanitize;
+pub mod schedule;
+pub mod snapshot;
+pub mod staleness;
+pub mod syncer;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod toc;
+pub mod utils;
 
-use anyhow::Result;
-use elasticsearch::Elasticsearch;
 use serde::Deserialize;
-use tracing::{info, trace};
-#[derive(Debug, Deserialize)]
+/// Debug 在 redact 里实现，不输出密钥
+#[derive(Default, Deserialize)]
 pub struct Config {
     pub github_token: String,
     pub github_repo: String,
     pub github_owner: String,
+    /// GitHub API 的地址，GitHub Enterprise 时改成 https://<host>/api/v3；为空时用 https://api.github.com
+    #[serde(default)]
+    pub github_api_url: String,
     pub es_url: String,
+    /// 为 true 时不在本地渲染 Markdown，改用 GitHub 返回的 body_html（代码块仍会重新高亮）
+    #[serde(default)]
+    pub github_html: bool,
+    /// 覆盖默认的 Content-Security-Policy 响应头，其中的 {nonce} 在渲染页面时替换成本次的 nonce
+    #[serde(default)]
+    pub content_security_policy: Option<String>,
+    /// 文章图片镜像到的本地目录，由 serv 挂在 /media 下；为空时不镜像
+    #[serde(default = "default_media_dir")]
+    pub media_dir: String,
+    /// 已镜像图片的索引文件，不能放在 media_dir 里，否则会被 /media 公开；为空时不保存索引
+    #[serde(default = "default_media_index_path")]
+    pub media_index_path: String,
+    /// 文章过期提示规则，按顺序匹配；不配置时使用 staleness::default_rules
+    #[serde(default = "staleness::default_rules")]
+    pub staleness: Vec<staleness::StalenessRule>,
+    /// 首页列表按 updated_at（默认）还是 created_at 排序
+    #[serde(default)]
+    pub home_sort: blog::HomeSort,
+    /// 签名 cookie、预览链接用的密钥；不配置时由 github_token 派生
+    #[serde(default)]
+    pub secret: String,
+    /// 加密文章输错密码的计数窗口，单位秒，窗口内同一篇文章最多输错 5 次，之后所有人都要等窗口过去才能再试。
+    /// 拿不到可靠的客户端 IP，只能按文章计数，匿名访客故意输错就能让新读者一段时间内打不开这篇文章
+    /// （已经解锁的读者有 cookie，不受影响）；为 0 时不限制次数
+    #[serde(default = "default_unlock_failure_window")]
+    pub unlock_failure_window: u64,
+    /// 后台增量同步的间隔，单位秒；为 0 时只在启动和收到 webhook 时同步
+    #[serde(default = "default_sync_interval")]
+    pub sync_interval: u64,
+    /// GitHub webhook 的 secret，用来校验 X-Hub-Signature-256；不配置时不提供 /hook/github
+    #[serde(default)]
+    pub webhook_secret: String,
+    /// webhook 事件合并的窗口，单位秒；窗口内同一篇文章的多次修改只同步一次
+    #[serde(default = "default_webhook_debounce")]
+    pub webhook_debounce: u64,
+    /// ES 不可用时用来只读展示的文章快照；为空时不保存快照
+    #[serde(default = "default_snapshot_path")]
+    pub snapshot_path: String,
+    /// 单独监听 /metrics 的地址，比如 127.0.0.1:9100，避免暴露在公网；为空时 /metrics 挂在主端口上。
+    /// webhook 的处理记录 /hook/github/log 只在这个地址上提供
+    #[serde(default)]
+    pub metrics_addr: String,
+    /// OTLP/HTTP collector 的完整地址，比如 http://127.0.0.1:4318/v1/traces；
+    /// 需要编译时启用 serv 的 otlp feature，为空时不导出
+    #[serde(default)]
+    pub otlp_endpoint: String,
+    /// 站点的完整地址，比如 https://huangj.in；导出静态站点时用来生成 sitemap 和 feed，为空时不生成
+    #[serde(default)]
+    pub site_url: String,
 }
-pub async fn serv(es_client: &Elasticsearch, conf: &Config) -> Result<()> {
-    es::init(conf.es_url.as_str()).expect("初始化ES失败");
 
-    trace!("开始同步所有 issue...");
-    github_issues::sync_all_issues(
-        &conf.github_token,
-        &conf.github_owner,
-        &conf.github_repo,
-        es_client,
-    )
-    .await?;
-    info!("issue 同步完毕");
-    Ok(())
+fn default_media_dir() -> String {
+    "./media".to_string()
+}
+
+fn default_media_index_path() -> String {
+    "./media_index.json".to_string()
+}
+
+fn default_unlock_failure_window() -> u64 {
+    5 * 60
+}
+
+fn default_sync_interval() -> u64 {
+    15 * 60
+}
+
+fn default_webhook_debounce() -> u64 {
+    5
+}
+
+fn default_snapshot_path() -> String {
+    "./snapshot.json".to_string()
 }
@@ -0,0 +1,184 @@
+use crate::highlight;
+use pulldown_cmark::{
+    html, CodeBlockKind, CowStr, Event, LinkType, Options, Parser, Tag, TagEnd, TextMergeStream,
+};
+
+/// 把 issue 的 Markdown 原文渲染成 HTML，支持 GFM 的表格、任务列表、脚注、删除线和自动链接
+pub fn render(md: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH;
+    let mut in_link = 0;
+    // 代码块的语言和内容，结束时整体交给 highlight
+    let mut code: Option<(String, String)> = None;
+    let mut events = Vec::new();
+    for event in TextMergeStream::new(Parser::new_ext(md, options)) {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let lang = match kind {
+                    CodeBlockKind::Fenced(info) => info
+                        .split_whitespace()
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                events.push(Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(
+                    lang.clone().into(),
+                ))));
+                code = Some((lang, String::new()));
+            }
+            Event::Text(text) if code.is_some() => {
+                if let Some((_, buf)) = code.as_mut() {
+                    buf.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                let (lang, buf) = code.take().unwrap_or_default();
+                match highlight::highlight(&buf, &lang) {
+                    Some(html) => {
+                        events.pop();
+                        events.push(Event::Html(html.into()));
+                    }
+                    None => {
+                        events.push(Event::Text(buf.into()));
+                        events.push(Event::End(TagEnd::CodeBlock));
+                    }
+                }
+            }
+            Event::Start(Tag::Link { .. }) => {
+                in_link += 1;
+                events.push(event);
+            }
+            Event::End(TagEnd::Link) => {
+                in_link -= 1;
+                events.push(event);
+            }
+            Event::Text(text) if in_link == 0 => autolink(text, &mut events),
+            e => events.push(e),
+        }
+    }
+    let mut out = String::with_capacity(md.len() * 3 / 2);
+    html::push_html(&mut out, events.into_iter());
+    out
+}
+
+// GFM 的扩展自动链接：正文里裸写的 http(s):// 地址也要变成链接
+fn autolink<'a>(text: CowStr<'a>, events: &mut Vec<Event<'a>>) {
+    let mut rest: &str = &text;
+    while let Some(start) = find_url(rest) {
+        let len = url_len(&rest[start..]);
+        if start > 0 {
+            events.push(Event::Text(rest[..start].to_string().into()));
+        }
+        let url: CowStr = rest[start..start + len].to_string().into();
+        events.push(Event::Start(Tag::Link {
+            link_type: LinkType::Autolink,
+            dest_url: url.clone(),
+            title: "".into(),
+            id: "".into(),
+        }));
+        events.push(Event::Text(url));
+        events.push(Event::End(TagEnd::Link));
+        rest = &rest[start + len..];
+    }
+    if rest.len() == text.len() {
+        events.push(Event::Text(text));
+    } else if !rest.is_empty() {
+        events.push(Event::Text(rest.to_string().into()));
+    }
+}
+
+fn find_url(s: &str) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(i) = s[offset..].find("http") {
+        let start = offset + i;
+        let tail = &s[start..];
+        let boundary = s[..start]
+            .chars()
+            .next_back()
+            .is_none_or(|c| !c.is_alphanumeric());
+        if boundary && (tail.starts_with("https://") || tail.starts_with("http://")) {
+            let scheme = if tail.starts_with("https://") { 8 } else { 7 };
+            if url_len(tail) > scheme {
+                return Some(start);
+            }
+        }
+        offset = start + 4;
+    }
+    None
+}
+
+fn url_len(s: &str) -> usize {
+    let end = s
+        .find(|c: char| c.is_whitespace() || c == '<' || !c.is_ascii())
+        .unwrap_or(s.len());
+    // 和 GFM 一致，去掉结尾的标点
+    let trimmed =
+        s[..end].trim_end_matches(['.', ',', ':', ';', '!', '?', '"', '\'', '*', '_', '~']);
+    let mut len = trimmed.len();
+    if trimmed.ends_with(')') {
+        let open = trimmed.matches('(').count();
+        let close = trimmed.matches(')').count();
+        if close > open {
+            len -= 1;
+        }
+    }
+    len
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_gfm_extensions() {
+        let html = render("| a | b |\n|---|---|\n| 1 | 2 |\n");
+        assert!(html.contains("<table>"));
+        assert!(html.contains("<td>1</td>"));
+
+        let html = render("- [x] done\n- [ ] todo\n");
+        assert!(html.contains(r#"<input disabled="" type="checkbox" checked=""/>"#));
+
+        let html = render("~~删除~~");
+        assert!(html.contains("<del>删除</del>"));
+
+        let html = render("正文[^1]\n\n[^1]: 脚注\n");
+        assert!(html.contains("footnote-definition"));
+    }
+
+    #[test]
+    fn test_autolink() {
+        assert_eq!(
+            render("见 https://huangj.in/blog/1. 谢谢"),
+            "<p>见 <a href=\"https://huangj.in/blog/1\">https://huangj.in/blog/1</a>. 谢谢</p>\n"
+        );
+        assert_eq!(
+            render("(https://example.com/a_(b))"),
+            "<p>(<a href=\"https://example.com/a_(b)\">https://example.com/a_(b)</a>)</p>\n"
+        );
+        // 已经是链接或代码的不再处理
+        assert_eq!(
+            render("[x](https://a.com) `https://b.com`"),
+            "<p><a href=\"https://a.com\">x</a> <code>https://b.com</code></p>\n"
+        );
+        assert_eq!(render("xhttps://a.com"), "<p>xhttps://a.com</p>\n");
+    }
+
+    #[test]
+    fn test_code_block() {
+        let html = render("```rust\nlet a = 1;\n```\n");
+        assert!(html.starts_with(r#"<pre class="hl-code"><code class="language-rust">"#));
+        assert!(html.contains("hl-"));
+
+        assert_eq!(
+            render("```\nhttps://a.com <b>\n```\n"),
+            "<pre><code>https://a.com &lt;b&gt;\n</code></pre>\n"
+        );
+        assert_eq!(
+            render("```nope\nx\n```\n"),
+            "<pre><code class=\"language-nope\">x\n</code></pre>\n"
+        );
+    }
+}
@@ -0,0 +1,679 @@
+use crate::metrics;
+use crate::utils::unescape;
+use anyhow::{anyhow, bail, Result};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageReader};
+use lol_html::{element, rewrite_str, RewriteStrSettings};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, trace, warn};
+
+/// serv 把 media_dir 挂在这个路径下
+pub const MEDIA_PATH: &str = "/media";
+
+/// 响应式图片生成的宽度，比原图小的才会生成
+pub const VARIANT_WIDTHS: [u32; 3] = [480, 960, 1920];
+
+/// 文章栏最宽 760px 左右，手机上占满屏幕
+const SIZES: &str = "(max-width: 768px) 100vw, 760px";
+
+/// 只镜像 GitHub 存放附件和代理外链图片的域名（及其子域名），
+/// 文章里的任意地址都下载的话，写文章的人就能让服务器去访问内网
+const ALLOWED_HOSTS: [&str; 2] = ["github.com", "githubusercontent.com"];
+
+/// GitHub 附件的图片最大 10MB，留一些余量
+const MAX_IMAGE_SIZE: usize = 20 * 1024 * 1024;
+
+/// 旧版本放在 media_dir 里的图片索引，会被 /media 公开访问，打开 Mirror 时挪到 media_index_path
+const LEGACY_INDEX_FILE: &str = ".index.json";
+
+/// 镜像到本地的一张图片，文件名是内容的 sha256
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MediaImage {
+    pub source: String,
+    pub file: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// 已生成的缩小版本的宽度，文件名为 <hash>-<width>.<ext>
+    #[serde(default)]
+    pub variants: Vec<u32>,
+}
+
+impl MediaImage {
+    pub fn url(&self) -> String {
+        format!("{MEDIA_PATH}/{}", self.file)
+    }
+
+    fn variant_url(&self, width: u32) -> String {
+        format!("{MEDIA_PATH}/{}", variant_file(&self.file, width))
+    }
+
+    /// 没有缩小版本时返回 None，不需要 srcset
+    pub fn srcset(&self) -> Option<String> {
+        let width = self.width?;
+        if self.variants.is_empty() {
+            return None;
+        }
+        let mut candidates: Vec<String> = self
+            .variants
+            .iter()
+            .map(|w| format!("{} {}w", self.variant_url(*w), w))
+            .collect();
+        candidates.push(format!("{} {}w", self.url(), width));
+        Some(candidates.join(", "))
+    }
+}
+
+async fn write_index(index_file: &Path, json: &[u8]) -> Result<()> {
+    if let Some(parent) = index_file.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut tmp = index_file.as_os_str().to_owned();
+    tmp.push(".tmp");
+    tokio::fs::write(&tmp, json).await?;
+    tokio::fs::rename(&tmp, index_file).await?;
+    Ok(())
+}
+
+// 先读 index_file，没有的话把 media_dir 里的旧索引挪过来，
+// 旧索引写到新位置后就删掉，不再从 /media 公开
+fn load_index(dir: &Path, index_file: Option<&Path>) -> HashMap<String, MediaImage> {
+    let legacy = dir.join(LEGACY_INDEX_FILE);
+    let (bytes, from_legacy) = match index_file.map(std::fs::read) {
+        Some(Ok(bytes)) => (bytes, false),
+        _ => match std::fs::read(&legacy) {
+            Ok(bytes) => (bytes, true),
+            Err(_) => return HashMap::new(),
+        },
+    };
+    let index = serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+        warn!("图片索引格式不正确，重新建立: {}", e);
+        HashMap::new()
+    });
+    if from_legacy {
+        let moved = match index_file {
+            Some(index_file) => index_file
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|_| std::fs::write(index_file, &bytes)),
+            None => Ok(()),
+        };
+        match moved.and_then(|_| std::fs::remove_file(&legacy)) {
+            Ok(_) => info!("图片索引已经从 {:?} 挪到 {:?}", legacy, index_file),
+            Err(e) => warn!("挪走旧的图片索引 {:?} 失败: {}", legacy, e),
+        }
+    }
+    index
+}
+
+// abc.png + 480 => abc-480.png
+fn variant_file(file: &str, width: u32) -> String {
+    match file.rsplit_once('.') {
+        Some((stem, ext)) => format!("{stem}-{width}.{ext}"),
+        None => format!("{file}-{width}"),
+    }
+}
+
+/// 浏览器接受 webp 且存在同名的 .webp 文件时，返回应当改为读取的文件名
+pub fn negotiate(dir: &Path, file: &str, accept: &str) -> Option<String> {
+    if !accept.contains("image/webp") {
+        return None;
+    }
+    let (stem, ext) = file.rsplit_once('.')?;
+    if !matches!(ext, "png" | "jpg" | "jpeg") || stem.contains(['/', '\\']) || stem.contains("..") {
+        return None;
+    }
+    let webp = format!("{stem}.webp");
+    dir.join(&webp).is_file().then_some(webp)
+}
+
+pub struct Mirror {
+    dir: PathBuf,
+    /// 已镜像图片的索引，记录地址对应的文件，下次同步时不用再下载；为 None 时只保存在内存里。
+    /// 私有图片的地址带着 token，索引里用地址的 hash 做 key
+    index_file: Option<PathBuf>,
+    client: reqwest::Client,
+    hosts: Arc<Vec<String>>,
+    max_size: usize,
+    index: Mutex<HashMap<String, MediaImage>>,
+}
+
+impl Mirror {
+    pub fn new(dir: impl Into<PathBuf>, index_file: Option<PathBuf>) -> Result<Self> {
+        Self::with_hosts(dir, index_file, &ALLOWED_HOSTS)
+    }
+
+    fn with_hosts(
+        dir: impl Into<PathBuf>,
+        index_file: Option<PathBuf>,
+        hosts: &[&str],
+    ) -> Result<Self> {
+        let dir = dir.into();
+        let hosts = Arc::new(hosts.iter().map(|h| h.to_string()).collect::<Vec<_>>());
+        let redirect_hosts = hosts.clone();
+        // 不能复用同步 issue 的 client，它带着 GitHub token
+        let client = reqwest::ClientBuilder::new()
+            .user_agent("rust-client")
+            .connect_timeout(Duration::new(5, 0))
+            // 包括读取响应体的时间
+            .timeout(Duration::new(30, 0))
+            // 重定向也只能去允许的域名
+            .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() >= 5 {
+                    attempt.error("重定向次数太多")
+                } else if !host_allowed(&redirect_hosts, attempt.url()) {
+                    let err = format!("重定向到不允许的地址: {}", attempt.url());
+                    attempt.error(err)
+                } else {
+                    attempt.follow()
+                }
+            }))
+            .build()?;
+        let index = load_index(&dir, index_file.as_deref());
+        Ok(Mirror {
+            dir,
+            index_file,
+            client,
+            hosts,
+            max_size: MAX_IMAGE_SIZE,
+            index: Mutex::new(index),
+        })
+    }
+
+    fn allowed(&self, src: &str) -> bool {
+        reqwest::Url::parse(src).is_ok_and(|url| host_allowed(&self.hosts, &url))
+    }
+
+    // 索引里有记录且文件都还在，就不用再下载
+    fn known(&self, src: &str) -> Option<MediaImage> {
+        let index = self.index.lock().unwrap();
+        let img = index.get(&url_key(src))?;
+        let files_exist = std::iter::once(img.file.clone())
+            .chain(img.variants.iter().map(|w| variant_file(&img.file, *w)))
+            .all(|f| self.dir.join(f).is_file());
+        files_exist.then(|| MediaImage {
+            source: src.to_string(),
+            ..img.clone()
+        })
+    }
+
+    async fn save_index(&self) -> Result<()> {
+        let Some(index_file) = &self.index_file else {
+            return Ok(());
+        };
+        let json = serde_json::to_vec(&*self.index.lock().unwrap())?;
+        write_index(index_file, &json).await
+    }
+
+    /// 下载 HTML 里允许的域名下的外链图片，改写成 /media/<hash>.<ext> 并补上宽高。
+    /// 下载失败或不允许镜像的图片保留原地址，不影响同步
+    pub async fn mirror(&self, html: &str) -> (String, Vec<MediaImage>) {
+        let mut images = HashMap::new();
+        let mut fetched = false;
+        for src in image_sources(html) {
+            if images.contains_key(&src) {
+                continue;
+            }
+            if let Some(img) = self.known(&src) {
+                metrics::cache_lookup("media", true);
+                images.insert(src, img);
+                continue;
+            }
+            if !self.allowed(&src) {
+                trace!("不镜像这个域名的图片: {}", src);
+                continue;
+            }
+            match self.fetch(&src).await {
+                Ok(img) => {
+                    let entry = MediaImage {
+                        source: String::new(),
+                        ..img.clone()
+                    };
+                    self.index.lock().unwrap().insert(url_key(&src), entry);
+                    images.insert(src, img);
+                    fetched = true;
+                }
+                Err(e) => warn!("镜像图片失败 {}: {}", src, e),
+            }
+        }
+        if fetched {
+            if let Err(e) = self.save_index().await {
+                warn!("保存图片索引失败: {}", e);
+            }
+        }
+        let rewritten = rewrite_str(
+            html,
+            RewriteStrSettings {
+                element_content_handlers: vec![element!("img[src]", |el| {
+                    let src = unescape(&el.get_attribute("src").unwrap_or_default());
+                    if let Some(img) = images.get(&src) {
+                        el.set_attribute("src", &img.url())?;
+                        if let (Some(w), Some(h)) = (img.width, img.height) {
+                            if !el.has_attribute("width") && !el.has_attribute("height") {
+                                el.set_attribute("width", &w.to_string())?;
+                                el.set_attribute("height", &h.to_string())?;
+                            }
+                        }
+                        if let Some(srcset) = img.srcset() {
+                            el.set_attribute("srcset", &srcset)?;
+                            el.set_attribute("sizes", SIZES)?;
+                        }
+                    }
+                    if !el.has_attribute("loading") {
+                        el.set_attribute("loading", "lazy")?;
+                    }
+                    el.set_attribute("decoding", "async")?;
+                    Ok(())
+                })],
+                ..RewriteStrSettings::new()
+            },
+        );
+        let mut images: Vec<MediaImage> = images.into_values().collect();
+        images.sort_by(|a, b| a.source.cmp(&b.source));
+        match rewritten {
+            Ok(h) => (h, images),
+            Err(_) => (html.to_string(), vec![]),
+        }
+    }
+
+    async fn fetch(&self, url: &str) -> Result<MediaImage> {
+        let resp = self.client.get(url).send().await?.error_for_status()?;
+        let content_type = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let bytes = read_limited(resp, self.max_size).await?;
+
+        let (format, ext, width, height) = match image::guess_format(&bytes) {
+            Ok(format) => {
+                let ext = format.extensions_str().first().copied().unwrap_or("bin");
+                let dimensions = ImageReader::with_format(Cursor::new(&bytes), format)
+                    .into_dimensions()
+                    .ok();
+                (
+                    Some(format),
+                    ext,
+                    dimensions.map(|d| d.0),
+                    dimensions.map(|d| d.1),
+                )
+            }
+            // image 不解析 svg，只按 Content-Type 认
+            Err(_) if content_type.starts_with("image/svg+xml") => (None, "svg", None, None),
+            Err(_) => match ImageFormat::from_mime_type(&content_type) {
+                Some(f) => (
+                    None,
+                    f.extensions_str().first().copied().unwrap_or("bin"),
+                    None,
+                    None,
+                ),
+                None => return Err(anyhow!("不是图片: {}", content_type)),
+            },
+        };
+
+        let hash = format!("{:x}", Sha256::digest(&bytes));
+        let file = format!("{hash}.{ext}");
+        let path = self.dir.join(&file);
+        let exists = tokio::fs::try_exists(&path).await?;
+        metrics::cache_lookup("media", exists);
+        if exists {
+            trace!("图片已存在: {}", file);
+        } else {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            // 先写临时文件再改名，避免 serv 读到写了一半的文件
+            let tmp = self.dir.join(format!(".{file}.tmp"));
+            tokio::fs::write(&tmp, &bytes).await?;
+            tokio::fs::rename(&tmp, &path).await?;
+        }
+        let variants = match (format, width) {
+            (Some(format @ (ImageFormat::Png | ImageFormat::Jpeg)), Some(width)) => {
+                let dir = self.dir.clone();
+                let file = file.clone();
+                // 缩放和编码比较耗 CPU，不要卡住异步运行时
+                tokio::task::spawn_blocking(move || {
+                    write_variants(&dir, &file, &bytes, format, width, !exists)
+                })
+                .await?
+                .unwrap_or_else(|e| {
+                    warn!("生成图片缩略图失败 {}: {}", url, e);
+                    vec![]
+                })
+            }
+            _ => vec![],
+        };
+        Ok(MediaImage {
+            source: url.to_string(),
+            file,
+            width,
+            height,
+            variants,
+        })
+    }
+}
+
+fn url_key(src: &str) -> String {
+    format!("{:x}", Sha256::digest(src.as_bytes()))
+}
+
+fn host_allowed(hosts: &[String], url: &reqwest::Url) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    matches!(url.scheme(), "https" | "http")
+        && hosts.iter().any(|h| {
+            host == h
+                || host
+                    .strip_suffix(h.as_str())
+                    .is_some_and(|p| p.ends_with('.'))
+        })
+}
+
+// 边读边检查大小，不信任 Content-Length
+async fn read_limited(mut resp: reqwest::Response, limit: usize) -> Result<Vec<u8>> {
+    if let Some(len) = resp.content_length().filter(|l| *l > limit as u64) {
+        bail!("图片太大: {} 字节", len);
+    }
+    let mut buf = Vec::new();
+    while let Some(chunk) = resp.chunk().await? {
+        if buf.len() + chunk.len() > limit {
+            bail!("图片超过 {} 字节", limit);
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf)
+}
+
+/// 生成各个宽度的缩小版本，以及它们和原图的 webp 版本。
+/// webp 只在比原格式小的时候保留，serv 按 Accept 头决定是否返回。
+/// webp 只在写入它的源文件时生成一次；fresh 表示原图是刚写入的，
+/// 原图不是新的、缩小版本也都在时直接返回，不再解码和缩放
+fn write_variants(
+    dir: &Path,
+    file: &str,
+    bytes: &[u8],
+    format: ImageFormat,
+    width: u32,
+    fresh: bool,
+) -> Result<Vec<u32>> {
+    let widths: Vec<u32> = VARIANT_WIDTHS.into_iter().filter(|w| *w < width).collect();
+    let missing: Vec<u32> = widths
+        .iter()
+        .copied()
+        .filter(|w| !dir.join(variant_file(file, *w)).is_file())
+        .collect();
+    if !fresh && missing.is_empty() {
+        return Ok(widths);
+    }
+    let original = image::load_from_memory_with_format(bytes, format)?;
+    if fresh {
+        write_webp(dir, file, &original, bytes.len() as u64)?;
+    }
+    for w in missing {
+        let name = variant_file(file, w);
+        let path = dir.join(&name);
+        let resized = original.resize(w, u32::MAX, FilterType::Lanczos3);
+        resized.save_with_format(&path, format)?;
+        write_webp(dir, &name, &resized, std::fs::metadata(&path)?.len())?;
+    }
+    Ok(widths)
+}
+
+fn write_webp(dir: &Path, file: &str, img: &DynamicImage, original_size: u64) -> Result<()> {
+    let Some((stem, _)) = file.rsplit_once('.') else {
+        return Ok(());
+    };
+    let path = dir.join(format!("{stem}.webp"));
+    if path.is_file() {
+        return Ok(());
+    }
+    let mut buf = Cursor::new(Vec::new());
+    // 编码器只支持 8 位的 RGB(A)
+    DynamicImage::ImageRgba8(img.to_rgba8()).write_to(&mut buf, ImageFormat::WebP)?;
+    let buf = buf.into_inner();
+    if (buf.len() as u64) < original_size {
+        std::fs::write(path, buf)?;
+    } else {
+        trace!("webp 比原图大，跳过: {}", file);
+    }
+    Ok(())
+}
+
+// 只处理外链，已经镜像过的 /media/ 地址和 data: 之类的跳过
+fn image_sources(html: &str) -> Vec<String> {
+    let sources = RefCell::new(Vec::new());
+    let _ = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![element!("img[src]", |el| {
+                let src = unescape(&el.get_attribute("src").unwrap_or_default());
+                if src.starts_with("https://") || src.starts_with("http://") {
+                    sources.borrow_mut().push(src);
+                }
+                Ok(())
+            })],
+            ..RewriteStrSettings::new()
+        },
+    );
+    sources.into_inner()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{serve, temp_dir};
+    use axum::http::header;
+    use axum::response::Redirect;
+    use axum::routing::get;
+    use axum::Router;
+    use image::{ImageBuffer, Rgb};
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let img = ImageBuffer::from_pixel(width, height, Rgb([200u8, 10, 10]));
+        let mut buf = Cursor::new(Vec::new());
+        img.write_to(&mut buf, ImageFormat::Png).unwrap();
+        buf.into_inner()
+    }
+
+    // 本地起一个假的 user-images.githubusercontent.com
+    // 返回地址和收到的请求数
+    async fn fake_github() -> (SocketAddr, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let a = png(40, 30);
+        let big = png(1000, 500);
+        let app = Router::new()
+            .route(
+                "/redirect.png",
+                get(|| async { Redirect::temporary("http://localhost:1/a.png") }),
+            )
+            .route(
+                "/big.png",
+                get(move || async move { ([(header::CONTENT_TYPE, "image/png")], big) }),
+            )
+            .route(
+                "/a.png",
+                get(move || async move { ([(header::CONTENT_TYPE, "image/png")], a) }),
+            )
+            .route(
+                "/b.svg",
+                get(|| async { ([(header::CONTENT_TYPE, "image/svg+xml")], "<svg></svg>") }),
+            )
+            .route("/c.html", get(|| async { "<html></html>" }))
+            .layer(axum::middleware::from_fn(
+                move |req, next: axum::middleware::Next<axum::body::Body>| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    next.run(req)
+                },
+            ));
+        (serve(app).await, requests)
+    }
+
+    #[tokio::test]
+    async fn test_mirror() {
+        let (addr, requests) = fake_github().await;
+        let dir = temp_dir("mirror");
+        let index_file = temp_dir("mirror_index").join("media_index.json");
+        let mirror = Mirror::with_hosts(&dir, Some(index_file.clone()), &["127.0.0.1"]).unwrap();
+        let html = format!(
+            r#"<p><img src="http://{addr}/a.png" alt="a"><img src="http://{addr}/a.png" width="20"><img src="http://{addr}/b.svg"><img src="http://{addr}/c.html"><img src="http://{addr}/404.png"><img src="/media/x.png"></p>"#
+        );
+        let (out, images) = mirror.mirror(&html).await;
+
+        assert_eq!(images.len(), 2);
+        let a = images.iter().find(|i| i.source.ends_with("a.png")).unwrap();
+        assert_eq!((a.width, a.height), (Some(40), Some(30)));
+        assert!(a.file.ends_with(".png"));
+        assert_eq!(a.file.len(), 64 + 4);
+        assert_eq!(std::fs::read(dir.join(&a.file)).unwrap(), png(40, 30));
+        let b = images.iter().find(|i| i.source.ends_with("b.svg")).unwrap();
+        assert!(b.file.ends_with(".svg"));
+        assert_eq!(b.width, None);
+
+        let expected = format!(
+            r#"<p><img src="/media/{a}" alt="a" width="40" height="30" loading="lazy" decoding="async"><img src="/media/{a}" width="20" loading="lazy" decoding="async"><img src="/media/{b}" loading="lazy" decoding="async"><img src="http://{addr}/c.html" loading="lazy" decoding="async"><img src="http://{addr}/404.png" loading="lazy" decoding="async"><img src="/media/x.png" loading="lazy" decoding="async"></p>"#,
+            a = a.file,
+            b = b.file
+        );
+        assert_eq!(out, expected);
+
+        // 下次同步，索引里有的图片不再下载，只会重试失败的两张
+        let index = std::fs::read_to_string(&index_file).unwrap();
+        assert!(!index.contains("127.0.0.1"));
+        // 索引不在公开的 media_dir 里
+        assert!(std::fs::read_dir(&dir).unwrap().all(|e| !e
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .ends_with(".json")));
+        let before = requests.load(Ordering::SeqCst);
+        let mirror = Mirror::with_hosts(&dir, Some(index_file), &["127.0.0.1"]).unwrap();
+        let (again, _) = mirror.mirror(&html).await;
+        assert_eq!(again, expected);
+        assert_eq!(requests.load(Ordering::SeqCst) - before, 2);
+
+        // 文件被删了就重新下载
+        std::fs::remove_file(dir.join(&a.file)).unwrap();
+        mirror.mirror(&html).await;
+        assert!(dir.join(&a.file).is_file());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_not_allowed() {
+        let (addr, requests) = fake_github().await;
+        let dir = temp_dir("not_allowed");
+        let html = format!(r#"<img src="http://{addr}/a.png" loading="lazy" decoding="async">"#);
+        let (out, images) = Mirror::new(&dir, None).unwrap().mirror(&html).await;
+        assert_eq!(out, html);
+        assert!(images.is_empty());
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+
+        // 重定向到不允许的地址、超过大小限制的都不下载
+        let index_file = dir.join("media_index.json");
+        let mut mirror =
+            Mirror::with_hosts(&dir, Some(index_file.clone()), &["127.0.0.1"]).unwrap();
+        mirror.max_size = 1024;
+        let html =
+            format!(r#"<img src="http://{addr}/redirect.png"><img src="http://{addr}/big.png">"#);
+        let (_, images) = mirror.mirror(&html).await;
+        assert!(images.is_empty());
+        assert!(!index_file.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_legacy_index() {
+        let dir = temp_dir("legacy");
+        let index_file = temp_dir("legacy_index").join("media_index.json");
+        let img = MediaImage {
+            source: String::new(),
+            file: "a.png".to_string(),
+            width: Some(40),
+            height: Some(30),
+            variants: vec![],
+        };
+        let json = serde_json::to_vec(&HashMap::from([(url_key("https://a/a.png"), img)])).unwrap();
+        std::fs::write(dir.join(LEGACY_INDEX_FILE), &json).unwrap();
+        std::fs::write(dir.join("a.png"), "").unwrap();
+
+        // 旧索引挪到 index_file，media_dir 里不再留着
+        let mirror = Mirror::new(&dir, Some(index_file.clone())).unwrap();
+        assert!(mirror.known("https://a/a.png").is_some());
+        assert!(!dir.join(LEGACY_INDEX_FILE).exists());
+        assert_eq!(std::fs::read(&index_file).unwrap(), json);
+
+        // 已经有新索引时读新的
+        let mirror = Mirror::new(&dir, Some(index_file)).unwrap();
+        assert!(mirror.known("https://a/a.png").is_some());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_host_allowed() {
+        let hosts: Vec<String> = ALLOWED_HOSTS.iter().map(|h| h.to_string()).collect();
+        let allowed = |s: &str| host_allowed(&hosts, &reqwest::Url::parse(s).unwrap());
+        assert!(allowed("https://user-images.githubusercontent.com/1/a.png"));
+        assert!(allowed("https://camo.githubusercontent.com/abc"));
+        assert!(allowed("https://github.com/user-attachments/assets/x"));
+        assert!(!allowed("https://evilgithub.com/a.png"));
+        assert!(!allowed("https://githubusercontent.com.evil.com/a.png"));
+        assert!(!allowed("http://169.254.169.254/latest/meta-data"));
+        assert!(!allowed("http://127.0.0.1:9200/_cat/indices"));
+        assert!(!allowed("file:///etc/passwd"));
+    }
+
+    #[tokio::test]
+    async fn test_variants() {
+        let (addr, _) = fake_github().await;
+        let dir = temp_dir("variants");
+        let mirror = Mirror::with_hosts(&dir, None, &["127.0.0.1"]).unwrap();
+        let html = format!(r#"<img src="http://{addr}/big.png">"#);
+        let (out, images) = mirror.mirror(&html).await;
+
+        let img = &images[0];
+        assert_eq!(img.variants, vec![480, 960]);
+        let stem = img.file.trim_end_matches(".png");
+        assert_eq!(
+            out,
+            format!(
+                r#"<img src="/media/{stem}.png" width="1000" height="500" srcset="/media/{stem}-480.png 480w, /media/{stem}-960.png 960w, /media/{stem}.png 1000w" sizes="{SIZES}" loading="lazy" decoding="async">"#
+            )
+        );
+        let small = image::open(dir.join(format!("{stem}-480.png"))).unwrap();
+        assert_eq!((small.width(), small.height()), (480, 240));
+        // 纯色图片 webp 一定更小
+        assert!(dir.join(format!("{stem}.webp")).is_file());
+        assert!(dir.join(format!("{stem}-960.webp")).is_file());
+        assert!(!dir.join(format!("{stem}-1920.png")).exists());
+
+        let png = format!("{stem}-480.png");
+        assert_eq!(
+            negotiate(&dir, &png, "image/avif,image/webp,*/*"),
+            Some(format!("{stem}-480.webp"))
+        );
+        assert_eq!(negotiate(&dir, &png, "image/png,*/*"), None);
+        assert_eq!(negotiate(&dir, "none.png", "image/webp"), None);
+        assert_eq!(negotiate(&dir, "../x.png", "image/webp"), None);
+
+        // 已经存在的版本不重新生成，缺的才补上
+        let bytes = std::fs::read(dir.join(&img.file)).unwrap();
+        std::fs::remove_file(dir.join(format!("{stem}-480.webp"))).unwrap();
+        std::fs::remove_file(dir.join(format!("{stem}-960.png"))).unwrap();
+        let widths =
+            write_variants(&dir, &img.file, &bytes, ImageFormat::Png, 1000, false).unwrap();
+        assert_eq!(widths, vec![480, 960]);
+        assert!(!dir.join(format!("{stem}-480.webp")).exists());
+        assert!(dir.join(format!("{stem}-960.png")).is_file());
+        assert!(dir.join(format!("{stem}-960.webp")).is_file());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
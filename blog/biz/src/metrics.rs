@@ -0,0 +1,244 @@
+use anyhow::Result;
+use axum::extract::MatchedPath;
+use axum::http::{header, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, Encoder, HistogramVec,
+    IntCounter, IntCounterVec, TextEncoder,
+};
+use std::future::Future;
+use std::time::Instant;
+use tracing::{info_span, Instrument};
+
+// 同步比页面请求慢得多，单独定义桶
+const SYNC_BUCKETS: &[f64] = &[1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0];
+
+lazy_static! {
+    static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "blog_http_requests_total",
+        "HTTP 请求数",
+        &["method", "route", "status"]
+    )
+    .unwrap();
+    static ref HTTP_DURATION: HistogramVec = register_histogram_vec!(
+        "blog_http_request_duration_seconds",
+        "HTTP 请求耗时",
+        &["method", "route"]
+    )
+    .unwrap();
+    static ref SERVER_FN_DURATION: HistogramVec = register_histogram_vec!(
+        "blog_server_fn_duration_seconds",
+        "server function 调用耗时",
+        &["name"]
+    )
+    .unwrap();
+    static ref ES_DURATION: HistogramVec =
+        register_histogram_vec!("blog_es_query_duration_seconds", "ES 查询耗时", &["query"])
+            .unwrap();
+    static ref ES_ERRORS: IntCounterVec =
+        register_int_counter_vec!("blog_es_query_errors_total", "ES 查询失败次数", &["query"])
+            .unwrap();
+    static ref SYNC_DURATION: HistogramVec = register_histogram_vec!(
+        "blog_sync_duration_seconds",
+        "同步耗时",
+        &["kind", "result"],
+        SYNC_BUCKETS.to_vec()
+    )
+    .unwrap();
+    static ref ISSUES_INDEXED: IntCounter =
+        register_int_counter!("blog_issues_indexed_total", "写入 ES 的文章数").unwrap();
+    static ref WEBHOOK_DELIVERIES: IntCounterVec = register_int_counter_vec!(
+        "blog_webhook_deliveries_total",
+        "webhook 投递数，result 是入队时的处理结果或 worker 同步的结果",
+        &["event", "result"]
+    )
+    .unwrap();
+    static ref CACHE_LOOKUPS: IntCounterVec = register_int_counter_vec!(
+        "blog_cache_lookups_total",
+        "缓存查询次数，按 hit/miss 统计命中率",
+        &["cache", "result"]
+    )
+    .unwrap();
+}
+
+/// 记录每个路由的请求数和耗时；route 用匹配到的路由模板，避免每篇文章一个标签
+pub async fn track<B>(req: Request<B>, next: Next<B>) -> Response {
+    let method = req.method().to_string();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let started = Instant::now();
+    let res = next.run(req).await;
+    let elapsed = started.elapsed().as_secs_f64();
+    HTTP_REQUESTS
+        .with_label_values(&[&method, &route, res.status().as_str()])
+        .inc();
+    HTTP_DURATION
+        .with_label_values(&[&method, &route])
+        .observe(elapsed);
+    res
+}
+
+/// 记录一次 server function 调用的耗时。SSR 时页面渲染直接调用 server function，不经过 /api，
+/// 所以在函数体里计时，name 用函数名
+pub async fn server_fn<T>(name: &'static str, f: impl Future<Output = T>) -> T {
+    let started = Instant::now();
+    let r = f.instrument(info_span!("server_fn", name)).await;
+    SERVER_FN_DURATION
+        .with_label_values(&[name])
+        .observe(started.elapsed().as_secs_f64());
+    r
+}
+
+/// 记录一次 ES 查询的耗时，失败时计数；查询在 es_query span 里执行
+pub async fn es_query<T>(query: &str, f: impl Future<Output = Result<T>>) -> Result<T> {
+    let started = Instant::now();
+    let r = f.instrument(info_span!("es_query", query)).await;
+    ES_DURATION
+        .with_label_values(&[query])
+        .observe(started.elapsed().as_secs_f64());
+    if r.is_err() {
+        ES_ERRORS.with_label_values(&[query]).inc();
+    }
+    r
+}
+
+pub fn sync_finished(kind: &str, ok: bool, seconds: f64, issues: usize) {
+    let result = if ok { "success" } else { "failure" };
+    SYNC_DURATION
+        .with_label_values(&[kind, result])
+        .observe(seconds);
+    ISSUES_INDEXED.inc_by(issues as u64);
+}
+
+pub fn webhook_delivery(event: &str, result: &str) {
+    WEBHOOK_DELIVERIES.with_label_values(&[event, result]).inc();
+}
+
+pub fn cache_lookup(cache: &str, hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    CACHE_LOOKUPS.with_label_values(&[cache, result]).inc();
+}
+
+/// Prometheus 文本格式的所有指标
+pub fn render() -> String {
+    let mut buf = Vec::new();
+    if let Err(err) = TextEncoder::new().encode(&prometheus::gather(), &mut buf) {
+        tracing::warn!("导出指标失败：{}", err);
+    }
+    String::from_utf8(buf).unwrap_or_default()
+}
+
+pub async fn metrics() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], render())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use tower::ServiceExt;
+
+    // 指标是全局的，其他测试也会计数，这里只看带特定标签的那一行
+    fn line(text: &str, prefix: &str) -> Option<String> {
+        text.lines()
+            .find(|l| l.starts_with(prefix))
+            .map(String::from)
+    }
+
+    #[tokio::test]
+    async fn test_track() {
+        let app = Router::new()
+            .route("/blog/:id", get(|| async { "post" }))
+            .route("/api/*fn_name", get(|| async { "fn" }))
+            .route("/metrics", get(metrics))
+            .route_layer(middleware::from_fn(track));
+        for uri in ["/blog/1", "/blog/2", "/api/track_test", "/api/get_home"] {
+            let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            app.clone().oneshot(req).await.unwrap();
+        }
+
+        let req = Request::builder()
+            .uri("/metrics")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.headers()[header::CONTENT_TYPE], prometheus::TEXT_FORMAT);
+        let text = render();
+        assert_eq!(
+            line(
+                &text,
+                r#"blog_http_requests_total{method="GET",route="/blog/:id",status="200"}"#
+            )
+            .unwrap()
+            .rsplit(' ')
+            .next(),
+            Some("2")
+        );
+        // server function 的耗时在函数体里记录，不按地址推断
+        assert!(line(
+            &text,
+            r#"blog_server_fn_duration_seconds_count{name="track_test"}"#
+        )
+        .is_none());
+        assert!(line(
+            &text,
+            r#"blog_http_requests_total{method="GET",route="/api/*fn_name",status="200"} 2"#
+        )
+        .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_es_query() {
+        es_query("test_ok", async { Ok(1) }).await.unwrap();
+        assert_eq!(server_fn("test_fn", async { 2 }).await, 2);
+        es_query::<()>("test_err", async { Err(anyhow::anyhow!("连不上")) })
+            .await
+            .unwrap_err();
+        sync_finished("test", true, 3.0, 5);
+        webhook_delivery("test_event", "queued");
+        cache_lookup("test", true);
+        cache_lookup("test", false);
+
+        let text = render();
+        assert!(line(
+            &text,
+            r#"blog_es_query_duration_seconds_count{query="test_ok"} 1"#
+        )
+        .is_some());
+        assert!(line(&text, r#"blog_es_query_errors_total{query="test_err"} 1"#).is_some());
+        assert!(line(
+            &text,
+            r#"blog_server_fn_duration_seconds_count{name="test_fn"} 1"#
+        )
+        .is_some());
+        assert!(line(&text, r#"blog_es_query_errors_total{query="test_ok"}"#).is_none());
+        assert!(line(
+            &text,
+            r#"blog_sync_duration_seconds_count{kind="test",result="success"} 1"#
+        )
+        .is_some());
+        assert!(line(&text, "blog_issues_indexed_total").is_some());
+        assert!(line(
+            &text,
+            r#"blog_webhook_deliveries_total{event="test_event",result="queued"} 1"#
+        )
+        .is_some());
+        assert!(line(
+            &text,
+            r#"blog_cache_lookups_total{cache="test",result="hit"} 1"#
+        )
+        .is_some());
+        assert!(line(
+            &text,
+            r#"blog_cache_lookups_total{cache="test",result="miss"} 1"#
+        )
+        .is_some());
+    }
+}
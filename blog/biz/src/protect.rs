@@ -0,0 +1,345 @@
+use crate::{front_matter, Config};
+use anyhow::{bail, Result};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use pbkdf2::pbkdf2_hmac;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+
+/// 文章密码写在 front matter 的 `password` 里，只在带 Protected 标签时生效
+const FRONT_MATTER_KEY: &str = "password";
+const COOKIE_PREFIX: &str = "unlock_";
+/// 解锁后 cookie 保留 30 天
+pub const COOKIE_MAX_AGE: u64 = 30 * 24 * 3600;
+
+const HASH_SCHEME: &str = "pbkdf2-sha256";
+// 验证一次密码在 release 下大约几十毫秒
+const PBKDF2_ROUNDS: u32 = 100_000;
+/// 同一篇文章在 unlock_failure_window 内最多输错 MAX_FAILURES 次
+const MAX_FAILURES: u32 = 5;
+
+type HmacSha256 = Hmac<Sha256>;
+
+lazy_static! {
+    static ref LIMITER: UnlockLimiter = UnlockLimiter::default();
+}
+
+/// 同步时调用：ES 里只存密码的 hash，不存明文。格式是 `pbkdf2-sha256$<轮数>$<salt>$<hash>`，
+/// salt 由站点密钥和文章 id 派生，每次同步结果相同，已经发出去的解锁 cookie 不会失效
+pub fn password_hash(key: &[u8], id: u64, body: Option<&str>) -> Option<String> {
+    Some(hash(password(body?)?, &salt(key, id), PBKDF2_ROUNDS))
+}
+
+fn salt(key: &[u8], id: u64) -> String {
+    sign(key, &format!("password-salt:{id}"))[..32].to_string()
+}
+
+/// front matter 里的明文密码
+pub(crate) fn password(body: &str) -> Option<&str> {
+    front_matter::value(body, FRONT_MATTER_KEY).filter(|p| !p.is_empty())
+}
+
+fn hash(password: &str, salt: &str, rounds: u32) -> String {
+    let mut dk = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(password.as_bytes(), salt.as_bytes(), rounds, &mut dk);
+    let dk: String = dk.iter().map(|b| format!("{b:02x}")).collect();
+    format!("{HASH_SCHEME}${rounds}${salt}${dk}")
+}
+
+/// 计算量比较大，在异步代码里用 unlock
+pub fn check_password(password_hash: &str, password: &str) -> bool {
+    let parts: Vec<&str> = password_hash.split('$').collect();
+    let [HASH_SCHEME, rounds, salt, _] = parts[..] else {
+        return false;
+    };
+    let Ok(rounds) = rounds.parse::<u32>() else {
+        return false;
+    };
+    // 逐字节比较所有字符，耗时和哪一位不同无关
+    let expected = hash(password, salt, rounds);
+    expected.len() == password_hash.len()
+        && expected
+            .bytes()
+            .zip(password_hash.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// 检查解锁密码，同一篇文章输错太多次时返回错误，暂时不再验证；unlock_failure_window 为 0 时不限制
+pub async fn unlock(
+    conf: &Config,
+    number: u64,
+    password_hash: &str,
+    password: &str,
+) -> Result<bool> {
+    let window = Duration::from_secs(conf.unlock_failure_window);
+    let limited = !window.is_zero();
+    if limited && !LIMITER.acquire(number, Instant::now(), window) {
+        bail!(
+            "密码错误次数太多，请 {} 分钟后再试",
+            window.as_secs().div_ceil(60)
+        );
+    }
+    let (h, p) = (password_hash.to_string(), password.to_string());
+    let ok = tokio::task::spawn_blocking(move || check_password(&h, &p)).await?;
+    if limited && ok {
+        LIMITER.succeeded(number);
+    }
+    Ok(ok)
+}
+
+/// 按文章统计输错密码的次数。拿不到可靠的客户端 IP，所以不按 IP 计数；
+/// 被人刷到上限时新读者要等窗口过去，已经解锁的读者有 cookie，不受影响
+#[derive(Debug, Default)]
+struct UnlockLimiter {
+    // 文章 number -> (窗口开始时间, 失败次数)
+    failures: Mutex<HashMap<u64, (Instant, u32)>>,
+}
+
+impl UnlockLimiter {
+    // 先按失败计一次，验证通过后再退回，并发的请求也不能绕过上限
+    fn acquire(&self, number: u64, now: Instant, window: Duration) -> bool {
+        let mut failures = self.failures.lock().unwrap();
+        let entry = failures.entry(number).or_insert((now, 0));
+        if now.duration_since(entry.0) >= window {
+            *entry = (now, 0);
+        }
+        if entry.1 >= MAX_FAILURES {
+            return false;
+        }
+        entry.1 += 1;
+        true
+    }
+
+    fn succeeded(&self, number: u64) {
+        if let Some(entry) = self.failures.lock().unwrap().get_mut(&number) {
+            entry.1 = entry.1.saturating_sub(1);
+        }
+    }
+}
+
+impl Config {
+    /// 签名用的密钥，没有配置 secret 时由 github_token 派生
+    pub fn secret_key(&self) -> Vec<u8> {
+        if self.secret.is_empty() {
+            Sha256::digest(format!("blog-secret:{}", self.github_token)).to_vec()
+        } else {
+            self.secret.as_bytes().to_vec()
+        }
+    }
+}
+
+/// HMAC-SHA256 签名，返回十六进制字符串
+pub fn sign(key: &[u8], message: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC 可以接受任意长度的 key");
+    mac.update(message.as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+pub fn verify(key: &[u8], message: &str, signature: &str) -> bool {
+    let Some(sig) = decode_hex(signature) else {
+        return false;
+    };
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC 可以接受任意长度的 key");
+    mac.update(message.as_bytes());
+    mac.verify_slice(&sig).is_ok()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+pub fn cookie_name(number: u64) -> String {
+    format!("{COOKIE_PREFIX}{number}")
+}
+
+// 把密码 hash 也签进去，改了密码以后旧的 cookie 自动失效
+fn unlock_message(number: u64, password_hash: &str) -> String {
+    format!("unlock:{number}:{password_hash}")
+}
+
+/// 密码正确后写进 cookie 的值
+pub fn unlock_token(key: &[u8], number: u64, password_hash: &str) -> String {
+    sign(key, &unlock_message(number, password_hash))
+}
+
+pub fn is_unlocked(key: &[u8], number: u64, password_hash: &str, token: &str) -> bool {
+    verify(key, &unlock_message(number, password_hash), token)
+}
+
+/// 预览链接的 token：`<过期时间戳>.<签名>`
+pub fn preview_token(key: &[u8], number: u64, expires: OffsetDateTime) -> String {
+    let ts = expires.unix_timestamp();
+    format!("{ts}.{}", sign(key, &format!("preview:{number}:{ts}")))
+}
+
+pub fn verify_preview(key: &[u8], number: u64, token: &str, now: OffsetDateTime) -> bool {
+    let Some((ts, sig)) = token.split_once('.') else {
+        return false;
+    };
+    let Ok(expires) = ts.parse::<i64>() else {
+        return false;
+    };
+    expires > now.unix_timestamp() && verify(key, &format!("preview:{number}:{ts}"), sig)
+}
+
+/// 从 Cookie 请求头里取出某个 cookie 的值
+pub fn cookie<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header.split(';').find_map(|c| {
+        let (k, v) = c.split_once('=')?;
+        (k.trim() == name).then(|| v.trim())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pbkdf2() {
+        // RFC 7914 11 节的测试向量，和之前存下的 hash 格式一致
+        assert_eq!(
+            hash("password", "salt", 1),
+            "pbkdf2-sha256$1$salt$120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"
+        );
+        assert_eq!(
+            hash("password", "salt", 4096),
+            "pbkdf2-sha256$4096$salt$c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a"
+        );
+    }
+
+    #[test]
+    fn test_password() {
+        let body = "---\npassword: \"p@ss word\"\n---\n正文";
+        let h = password_hash(b"secret", 42, Some(body)).unwrap();
+        let salt = salt(b"secret", 42);
+        assert_eq!(
+            h.rsplit_once('$').unwrap().0,
+            format!("pbkdf2-sha256$100000${salt}")
+        );
+        assert!(check_password(&h, "p@ss word"));
+        // 同样的密码在不同文章、不同站点上 salt 不同，同一篇文章每次同步相同
+        assert_ne!(super::salt(b"secret", 43), salt);
+        assert_ne!(super::salt(b"other", 42), salt);
+        assert_eq!(super::salt(b"secret", 42), salt);
+        // 轮数记在 hash 里，以后可以调整
+        let h = hash("p@ss word", &salt, 2);
+        assert!(check_password(&h, "p@ss word"));
+        assert!(!check_password(&h, "p@ss"));
+        assert_eq!(password_hash(b"secret", 42, Some("正文")), None);
+        assert_eq!(
+            password_hash(b"secret", 42, Some("---\npassword:\n---\n")),
+            None
+        );
+        // 旧版本不加盐的 hash 不再认
+        let old = format!("{:x}", Sha256::digest("42:p@ss word"));
+        assert!(!check_password(&old, "p@ss word"));
+        assert!(!check_password("pbkdf2-sha256$x$salt$00", "p@ss word"));
+    }
+
+    #[test]
+    fn test_limiter() {
+        let limiter = UnlockLimiter::default();
+        let now = Instant::now();
+        let window = Duration::from_secs(300);
+        for _ in 0..MAX_FAILURES {
+            assert!(limiter.acquire(1, now, window));
+        }
+        assert!(!limiter.acquire(1, now, window));
+        // 其他文章不受影响，窗口过去以后重新计数
+        assert!(limiter.acquire(2, now, window));
+        assert!(limiter.acquire(1, now + window, window));
+        // 输对了不算失败
+        for _ in 0..MAX_FAILURES * 2 {
+            assert!(limiter.acquire(3, now, window));
+            limiter.succeeded(3);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unlock() {
+        let conf = Config {
+            unlock_failure_window: 300,
+            ..Default::default()
+        };
+        let h = hash("pass", "salt", 1);
+        assert!(unlock(&conf, 1_000_001, &h, "pass").await.unwrap());
+        for _ in 0..MAX_FAILURES {
+            assert!(!unlock(&conf, 1_000_001, &h, "wrong").await.unwrap());
+        }
+        // 到了上限以后密码对了也要等
+        let err = unlock(&conf, 1_000_001, &h, "pass").await.unwrap_err();
+        assert_eq!(err.to_string(), "密码错误次数太多，请 5 分钟后再试");
+
+        // 窗口为 0 时不限制
+        let conf = Config::default();
+        for _ in 0..MAX_FAILURES + 1 {
+            assert!(!unlock(&conf, 1_000_002, &h, "wrong").await.unwrap());
+        }
+        assert!(unlock(&conf, 1_000_002, &h, "pass").await.unwrap());
+    }
+
+    #[test]
+    fn test_unlock_token() {
+        let key = b"secret";
+        let token = unlock_token(key, 42, "hash");
+        assert_eq!(token.len(), 64);
+        assert!(is_unlocked(key, 42, "hash", &token));
+        assert!(!is_unlocked(key, 43, "hash", &token));
+        assert!(!is_unlocked(key, 42, "new-hash", &token));
+        assert!(!is_unlocked(b"other", 42, "hash", &token));
+        assert!(!is_unlocked(key, 42, "hash", "zz"));
+        assert!(!is_unlocked(key, 42, "hash", "abc"));
+    }
+
+    #[test]
+    fn test_preview_token() {
+        use time::macros::datetime;
+        use time::Duration;
+
+        let key = b"secret";
+        let now = datetime!(2026-11-01 0:00 UTC);
+        let token = preview_token(key, 42, now + Duration::hours(24));
+        assert!(token.starts_with("1793577600."));
+        assert!(verify_preview(key, 42, &token, now));
+        assert!(verify_preview(key, 42, &token, now + Duration::hours(23)));
+        // 过期、换了文章、换了 key、改了时间戳都不行
+        assert!(!verify_preview(key, 42, &token, now + Duration::hours(24)));
+        assert!(!verify_preview(key, 43, &token, now));
+        assert!(!verify_preview(b"other", 42, &token, now));
+        let forged = token.replacen("1793577600", "1893577600", 1);
+        assert!(!verify_preview(key, 42, &forged, now));
+        assert!(!verify_preview(key, 42, "garbage", now));
+    }
+
+    #[test]
+    fn test_cookie() {
+        let header = "a=1; unlock_42=abc; unlock_4=def";
+        assert_eq!(cookie(header, &cookie_name(42)), Some("abc"));
+        assert_eq!(cookie(header, &cookie_name(4)), Some("def"));
+        assert_eq!(cookie(header, &cookie_name(5)), None);
+    }
+
+    #[test]
+    fn test_secret_key() {
+        let conf = Config {
+            github_token: "token".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(conf.secret_key().len(), 32);
+        let conf = Config {
+            secret: "s".to_string(),
+            ..conf
+        };
+        assert_eq!(conf.secret_key(), b"s");
+    }
+}
@@ -0,0 +1,143 @@
+use crate::utils::unescape;
+use lol_html::{rewrite_str, text, RewriteStrSettings};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+// 中文按字、英文按词计算阅读速度，代码读得慢，按英文的一半算
+const CJK_PER_MINUTE: u64 = 400;
+const WORDS_PER_MINUTE: u64 = 200;
+const CODE_WORDS_PER_MINUTE: u64 = 100;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadingStats {
+    /// 正文字数：中文字符数加英文单词数，不含代码
+    pub words: u64,
+    /// 预计阅读分钟数，至少 1 分钟；旧数据为 0
+    pub minutes: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Count {
+    cjk: u64,
+    words: u64,
+}
+
+/// body_text 是 GitHub 给的纯文本，里面也包含代码块，代码部分从渲染后 HTML 的 `<pre>` 里取出来单独计算
+pub fn stats(body_text: &str, html: &str) -> ReadingStats {
+    let total = count(body_text);
+    let code = count(&code_text(html));
+    let prose = Count {
+        cjk: total.cjk.saturating_sub(code.cjk),
+        words: total.words.saturating_sub(code.words),
+    };
+    let words = prose.cjk + prose.words;
+    if words == 0 && code.words == 0 && code.cjk == 0 {
+        return ReadingStats::default();
+    }
+    let seconds = prose.cjk * 60 / CJK_PER_MINUTE
+        + prose.words * 60 / WORDS_PER_MINUTE
+        + (code.words + code.cjk) * 60 / CODE_WORDS_PER_MINUTE;
+    ReadingStats {
+        words,
+        minutes: seconds.div_ceil(60).max(1),
+    }
+}
+
+fn count(text: &str) -> Count {
+    let mut c = Count::default();
+    let mut in_word = false;
+    for ch in text.chars() {
+        if is_cjk(ch) {
+            c.cjk += 1;
+            in_word = false;
+        } else if ch.is_alphanumeric() || ch == '\'' || ch == '_' {
+            if !in_word {
+                c.words += 1;
+                in_word = true;
+            }
+        } else {
+            in_word = false;
+        }
+    }
+    c
+}
+
+// 中日韩文字，不含全角标点
+fn is_cjk(ch: char) -> bool {
+    matches!(ch,
+        '\u{4E00}'..='\u{9FFF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{20000}'..='\u{2A6DF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{3040}'..='\u{309F}'
+        | '\u{30A0}'..='\u{30FF}'
+        | '\u{AC00}'..='\u{D7AF}')
+}
+
+fn code_text(html: &str) -> String {
+    let code = RefCell::new(String::new());
+    let _ = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![text!("pre", |t| {
+                code.borrow_mut().push_str(t.as_str());
+                Ok(())
+            })],
+            ..RewriteStrSettings::new()
+        },
+    );
+    unescape(&code.take())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_count() {
+        assert_eq!(count("你好，世界"), Count { cjk: 4, words: 0 });
+        assert_eq!(
+            count("使用 Rust 写博客, it's fun_2 3.5"),
+            Count { cjk: 5, words: 5 }
+        );
+        assert_eq!(count("こんにちは 안녕"), Count { cjk: 7, words: 0 });
+    }
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(stats("", ""), ReadingStats::default());
+        assert_eq!(
+            stats("短文", ""),
+            ReadingStats {
+                words: 2,
+                minutes: 1
+            }
+        );
+
+        // 800 个汉字 + 400 个英文单词 = 2 + 2 分钟
+        let text = format!("{}{}", "字".repeat(800), " word".repeat(400));
+        assert_eq!(
+            stats(&text, ""),
+            ReadingStats {
+                words: 1200,
+                minutes: 4
+            }
+        );
+
+        // 代码不计入字数，但按更慢的速度计入时间
+        let code = " let".repeat(200);
+        let text = format!("{}{}", "字".repeat(400), code);
+        let html = format!(
+            "<p>{}</p><pre><code>{}</code></pre>",
+            "字".repeat(400),
+            code
+        );
+        assert_eq!(
+            stats(&text, &html),
+            ReadingStats {
+                words: 400,
+                minutes: 3
+            }
+        );
+    }
+}
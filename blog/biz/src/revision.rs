@@ -0,0 +1,236 @@
+use crate::metrics;
+use anyhow::Result;
+use elasticsearch::indices::IndicesCreateParts;
+use elasticsearch::{Elasticsearch, IndexParts, SearchParts};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use similar::{ChangeTag, TextDiff};
+use std::time::Duration;
+use time::OffsetDateTime;
+
+/// 文章每次修改标题或正文都会在这里留一份快照
+pub const REVISIONS_INDEX: &str = "blog_revisions";
+const DIFF_CONTEXT: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Revision {
+    pub issue_number: u64,
+    /// 修改时间的时间戳，同一篇文章内唯一，用在链接里
+    pub revision: i64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub revised_at: OffsetDateTime,
+    pub title: String,
+    pub body_hash: String,
+    /// Markdown 原文，没有时是 body_text
+    pub content: String,
+}
+
+impl Revision {
+    pub fn new(
+        issue_number: u64,
+        revised_at: OffsetDateTime,
+        title: &str,
+        body_text: &str,
+        content: &str,
+    ) -> Self {
+        Revision {
+            issue_number,
+            revision: revised_at.unix_timestamp(),
+            revised_at,
+            title: title.to_string(),
+            body_hash: body_hash(title, body_text),
+            content: content.to_string(),
+        }
+    }
+
+    // 标题也算进对比的内容里
+    fn text(&self) -> String {
+        format!("# {}\n\n{}", self.title, self.content)
+    }
+}
+
+/// 只看标题和 body_text，渲染方式变了不算修改
+pub fn body_hash(title: &str, body_text: &str) -> String {
+    format!("{:x}", Sha256::digest(format!("{title}\n{body_text}")))
+}
+
+/// 和最近一次快照比较，标题或正文变了才写入新的快照，返回是否写入
+pub async fn record(es_client: &Elasticsearch, rev: &Revision) -> Result<bool> {
+    let latest = list(es_client, rev.issue_number, 1).await?;
+    if latest.first().is_some_and(|r| r.body_hash == rev.body_hash) {
+        return Ok(false);
+    }
+    es_client
+        .index(IndexParts::IndexId(
+            REVISIONS_INDEX,
+            &format!("{}-{}", rev.issue_number, rev.revision),
+        ))
+        .body(rev)
+        .request_timeout(Duration::new(1, 0))
+        .send()
+        .await?
+        .error_for_status_code()?;
+    Ok(true)
+}
+
+/// 文章的历史版本，新的在前
+pub async fn list(es_client: &Elasticsearch, number: u64, size: usize) -> Result<Vec<Revision>> {
+    metrics::es_query("revisions", async {
+        let r = es_client
+            .search(SearchParts::Index(&[REVISIONS_INDEX]))
+            .body(json!({
+                "size": size,
+                "query": { "term": { "issue_number": number } },
+                "sort": [ { "revision": { "order": "desc" } } ]
+            }))
+            .send()
+            .await?
+            .json::<Value>()
+            .await?;
+        let mut revisions = Vec::new();
+        for v in r["hits"]["hits"].as_array().into_iter().flatten() {
+            revisions.push(serde_json::from_value::<Revision>(v["_source"].clone())?);
+        }
+        Ok(revisions)
+    })
+    .await
+}
+
+pub(crate) fn mappings() -> Value {
+    json!({
+      "properties": {
+        "issue_number": {
+          "type": "integer"
+        },
+        "revision": {
+          "type": "long"
+        },
+        "revised_at": {
+          "type": "date"
+        },
+        "title": {
+          "type": "text",
+          "index": false
+        },
+        "body_hash": {
+          "type": "keyword"
+        },
+        "content": {
+          "type": "text",
+          "index": false
+        }
+      }
+    })
+}
+
+pub async fn create_index(client: &Elasticsearch) -> Result<(), elasticsearch::Error> {
+    let resp = client
+        .indices()
+        .create(IndicesCreateParts::Index(REVISIONS_INDEX))
+        .body(json!({
+          "settings": {
+            "number_of_shards": 1,
+            "number_of_replicas": 0
+          },
+          "mappings": mappings()
+        }))
+        .request_timeout(Duration::new(1, 0))
+        .send()
+        .await?;
+    match resp.error_for_status_code() {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiffKind {
+    Equal,
+    Insert,
+    Delete,
+    /// 省略掉的没有变化的行
+    Skip,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiffLine {
+    pub kind: DiffKind,
+    pub text: String,
+}
+
+/// 按行对比两个版本，只保留改动附近的几行
+pub fn diff(old: &Revision, new: &Revision) -> Vec<DiffLine> {
+    let (old, new) = (old.text(), new.text());
+    let diff = TextDiff::from_lines(&old, &new);
+    let mut lines = Vec::new();
+    for (i, group) in diff.grouped_ops(DIFF_CONTEXT).iter().enumerate() {
+        if i > 0 {
+            lines.push(DiffLine {
+                kind: DiffKind::Skip,
+                text: String::new(),
+            });
+        }
+        for op in group {
+            for change in diff.iter_changes(op) {
+                let kind = match change.tag() {
+                    ChangeTag::Equal => DiffKind::Equal,
+                    ChangeTag::Insert => DiffKind::Insert,
+                    ChangeTag::Delete => DiffKind::Delete,
+                };
+                lines.push(DiffLine {
+                    kind,
+                    text: change.value().trim_end_matches(['\r', '\n']).to_string(),
+                });
+            }
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_revision() {
+        let a = Revision::new(1, datetime!(2023-08-01 0:00 UTC), "标题", "正文", "正文");
+        assert_eq!(a.revision, 1690848000);
+        assert_eq!(a.body_hash, body_hash("标题", "正文"));
+        assert_ne!(a.body_hash, body_hash("标题", "正文2"));
+        assert_ne!(a.body_hash, body_hash("标题2", "正文"));
+    }
+
+    #[test]
+    fn test_diff() {
+        let content: String = (1..=20).map(|i| format!("line {i}\n")).collect();
+        let old = Revision::new(1, datetime!(2023-08-01 0:00 UTC), "旧标题", "", &content);
+        let new = Revision::new(
+            1,
+            datetime!(2023-08-02 0:00 UTC),
+            "新标题",
+            "",
+            &content.replace("line 15\n", "line 15 changed\n"),
+        );
+        let lines = diff(&old, &new);
+        let kinds: Vec<_> = lines.iter().map(|l| l.kind).collect();
+        assert_eq!(lines[0].text, "# 旧标题");
+        assert_eq!(kinds[0], DiffKind::Delete);
+        assert_eq!(lines[1].text, "# 新标题");
+        assert_eq!(kinds[1], DiffKind::Insert);
+        assert!(kinds.contains(&DiffKind::Skip));
+        let changed: Vec<_> = lines
+            .iter()
+            .filter(|l| l.kind != DiffKind::Equal && l.kind != DiffKind::Skip)
+            .map(|l| l.text.as_str())
+            .collect();
+        assert_eq!(
+            changed,
+            vec!["# 旧标题", "# 新标题", "line 15", "line 15 changed"]
+        );
+        // 中间没改的行被省略
+        assert!(!lines.iter().any(|l| l.text == "line 8"));
+        assert!(diff(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,113 @@
+use ammonia::Builder;
+use lazy_static::lazy_static;
+use std::borrow::Cow;
+
+/// 文章里的 id 都加上这个前缀（和 GitHub 一样），避免 id="__LEPTOS..." 这样覆盖页面的全局变量
+pub const ID_PREFIX: &str = "user-content-";
+
+lazy_static! {
+    static ref CLEANER: Builder<'static> = {
+        let mut b = Builder::default();
+        b.add_tags(&["input", "section"])
+            // 代码高亮、标题锚点、脚注都依赖 class 和 id
+            .add_generic_attributes(&["class", "id", "dir", "aria-hidden", "aria-label"])
+            .add_tag_attributes("input", &["type", "checked", "disabled"])
+            .add_tag_attributes("img", &["srcset", "sizes", "loading", "decoding"])
+            .add_tag_attributes("td", &["align"])
+            .add_tag_attributes("th", &["align"])
+            .add_tag_attributes("section", &["data-footnotes"])
+            .attribute_filter(|element, attribute, value| match (element, attribute) {
+                // 只保留任务列表的复选框
+                ("input", "type") if value != "checkbox" => None,
+                // srcset 只会由镜像图片生成
+                ("img", "srcset") if !srcset_is_local(value) => None,
+                (_, "id") if !value.starts_with(ID_PREFIX) => {
+                    Some(Cow::Owned(format!("{ID_PREFIX}{value}")))
+                }
+                // 页内链接跟着 id 一起加前缀
+                ("a", "href") if value.len() > 1 && value.starts_with('#') => {
+                    match value[1..].starts_with(ID_PREFIX) {
+                        true => Some(Cow::Borrowed(value)),
+                        false => Some(Cow::Owned(format!("#{ID_PREFIX}{}", &value[1..]))),
+                    }
+                }
+                _ => Some(Cow::Borrowed(value)),
+            });
+        b
+    };
+}
+
+fn srcset_is_local(srcset: &str) -> bool {
+    srcset
+        .split(',')
+        .all(|c| c.trim().starts_with(crate::media::MEDIA_PATH))
+}
+
+/// 按白名单清理文章 HTML，保留 GitHub 的排版、表格、代码和图片，去掉脚本、事件属性和危险链接
+pub fn clean(html: &str) -> String {
+    CLEANER.clean(html).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_keep_markup() {
+        let html = r##"<h2 id="user-content-安装"><a class="heading-anchor" href="#user-content-安装" aria-hidden="true">#</a>安装</h2><table><thead><tr><th align="left">a</th></tr></thead><tbody><tr><td align="left">1</td></tr></tbody></table><pre class="hl-code"><code class="language-rust"><span class="hl-source hl-rust">fn</span></code></pre><ul><li><input disabled="" type="checkbox" checked="">done</li></ul><p><img src="/media/abc.png" alt="图" width="10" height="20" srcset="/media/abc-480.png 480w, /media/abc.png 960w" sizes="100vw" loading="lazy" decoding="async"><del>x</del><sup class="footnote-reference"><a href="#user-content-1">1</a></sup></p>"##;
+        // 链接会统一加上 rel="noopener noreferrer"
+        let out = clean(html).replace(r#" rel="noopener noreferrer""#, "");
+        assert_eq!(out, html);
+    }
+
+    #[test]
+    fn test_prefix_ids() {
+        assert_eq!(
+            clean(
+                r##"<a href="#x">x</a><div id="x">a</div><form id="__LEPTOS_PENDING_RESOURCES"></form><a href="#">top</a>"##
+            ),
+            r##"<a href="#user-content-x" rel="noopener noreferrer">x</a><div id="user-content-x">a</div><a href="#" rel="noopener noreferrer">top</a>"##
+        );
+        assert_eq!(
+            clean(r#"<h2 id="__LEPTOS_PENDING_RESOURCES">x</h2>"#),
+            r#"<h2 id="user-content-__LEPTOS_PENDING_RESOURCES">x</h2>"#
+        );
+    }
+
+    #[test]
+    fn test_xss_payloads() {
+        let payloads = [
+            r#"<script>alert(1)</script>"#,
+            r#"<img src=x onerror=alert(1)>"#,
+            r#"<a href="javascript:alert(1)">x</a>"#,
+            r#"<a href="JaVaScRiPt:alert(1)">x</a>"#,
+            r#"<a href="&#106;avascript:alert(1)">x</a>"#,
+            r#"<svg onload=alert(1)><circle/></svg>"#,
+            r#"<iframe src="javascript:alert(1)"></iframe>"#,
+            r#"<div style="background:url(javascript:alert(1))">x</div>"#,
+            r#"<body onload=alert(1)>"#,
+            r#"<details open ontoggle=alert(1)>x</details>"#,
+            r#"<math><mtext><table><mglyph><style><img src=x onerror=alert(1)>"#,
+            r#"<form action="javascript:alert(1)"><button>x</button></form>"#,
+            r#"<object data="data:text/html;base64,PHNjcmlwdD5hbGVydCgxKTwvc2NyaXB0Pg=="></object>"#,
+            r#"<input type="image" src=x onerror=alert(1)>"#,
+            r#"<img src="data:image/svg+xml,<svg onload=alert(1)>">"#,
+            r#"<meta http-equiv="refresh" content="0;url=javascript:alert(1)">"#,
+            r#"<img src="/media/a.png" srcset="javascript:alert(1) 1x">"#,
+        ];
+        for p in payloads {
+            let out = clean(p).to_lowercase();
+            assert!(!out.contains("<script"), "{p} => {out}");
+            assert!(!out.contains("javascript:"), "{p} => {out}");
+            assert!(!out.contains("onerror"), "{p} => {out}");
+            assert!(!out.contains("onload"), "{p} => {out}");
+            assert!(!out.contains("ontoggle"), "{p} => {out}");
+            assert!(!out.contains("<iframe"), "{p} => {out}");
+            assert!(!out.contains("<object"), "{p} => {out}");
+            assert!(!out.contains("<meta"), "{p} => {out}");
+            assert!(!out.contains("style="), "{p} => {out}");
+            assert!(!out.contains("data:"), "{p} => {out}");
+            assert!(!out.contains(r#"type="image""#), "{p} => {out}");
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use crate::front_matter;
+use serde_json::{json, Value};
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{Date, OffsetDateTime};
+
+const PUBLISH_LABEL_PREFIX: &str = "publish:";
+const FRONT_MATTER_KEY: &str = "publish_at";
+
+/// 定时发布时间：优先取 `publish:2026-11-01` 标签，其次是正文开头 front matter 里的 `publish_at`
+pub fn publish_at<'a>(
+    labels: impl IntoIterator<Item = &'a str>,
+    body: Option<&str>,
+) -> Option<OffsetDateTime> {
+    labels
+        .into_iter()
+        .find_map(|l| l.strip_prefix(PUBLISH_LABEL_PREFIX).and_then(parse_time))
+        .or_else(|| front_matter::value(body?, FRONT_MATTER_KEY).and_then(parse_time))
+}
+
+/// 2026-11-01T08:00:00+08:00，或者只写日期，按 UTC 零点算
+pub fn parse_time(s: &str) -> Option<OffsetDateTime> {
+    let s = s.trim();
+    OffsetDateTime::parse(s, &Rfc3339).ok().or_else(|| {
+        Date::parse(s, format_description!("[year]-[month]-[day]"))
+            .ok()
+            .map(|d| d.midnight().assume_utc())
+    })
+}
+
+/// 查询时用的过滤条件：没有定时发布时间，或者发布时间已经到了
+pub fn published_filter(now: OffsetDateTime) -> Value {
+    json!({
+        "bool": {
+            "should": [
+                { "bool": { "must_not": { "exists": { "field": "publish_at" } } } },
+                { "range": { "publish_at": { "lte": now.format(&Rfc3339).unwrap_or_default() } } }
+            ],
+            "minimum_should_match": 1
+        }
+    })
+}
+
+pub fn is_published(publish_at: Option<OffsetDateTime>, now: OffsetDateTime) -> bool {
+    publish_at.is_none_or(|t| t <= now)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_publish_at() {
+        assert_eq!(
+            publish_at(["Publish", "publish:2026-11-01"], None),
+            Some(datetime!(2026-11-01 0:00 UTC))
+        );
+        assert_eq!(
+            publish_at(["publish:2026-11-01T08:00:00+08:00"], None),
+            Some(datetime!(2026-11-01 0:00 UTC))
+        );
+        let body = "---\ntitle: x\npublish_at: \"2026-12-24\"\n---\n正文";
+        assert_eq!(
+            publish_at([], Some(body)),
+            Some(datetime!(2026-12-24 0:00 UTC))
+        );
+        // 标签优先于 front matter
+        assert_eq!(
+            publish_at(["publish:2026-11-01"], Some(body)),
+            Some(datetime!(2026-11-01 0:00 UTC))
+        );
+        assert_eq!(publish_at(["publish:soon"], Some("正文")), None);
+        assert_eq!(publish_at([], Some("publish_at: 2026-11-01")), None);
+    }
+
+    #[test]
+    fn test_published() {
+        let now = datetime!(2026-11-01 0:00 UTC);
+        assert!(is_published(None, now));
+        assert!(is_published(Some(now), now));
+        assert!(!is_published(Some(datetime!(2026-11-01 0:00:01 UTC)), now));
+        let f = published_filter(now);
+        assert_eq!(
+            f["bool"]["should"][1]["range"]["publish_at"]["lte"],
+            "2026-11-01T00:00:00Z"
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use crate::blog::{self, Home, HomeSort, Post, FEATURED_LABEL, PINNED_LABEL, UNLISTED_LABEL};
+use crate::schedule;
+use crate::utils::unescape;
+use anyhow::Result;
+use elasticsearch::Elasticsearch;
+use lol_html::{rewrite_str, text, RewriteStrSettings};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::{info, warn};
+
+// 多久从 ES 刷新一次快照
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const SEARCH_SIZE: usize = 30;
+
+/// ES 不可用时用来只读展示的所有文章
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    #[serde(with = "time::serde::rfc3339")]
+    pub saved_at: OffsetDateTime,
+    pub posts: Vec<Post>,
+}
+
+impl Snapshot {
+    // 快照里可能有保存时还没到发布时间的文章
+    fn visible(&self, now: OffsetDateTime) -> impl Iterator<Item = &Post> {
+        self.posts
+            .iter()
+            .filter(move |p| schedule::is_published(p.publish_at, now))
+    }
+
+    fn listed(&self, now: OffsetDateTime) -> impl Iterator<Item = &Post> {
+        self.visible(now).filter(|p| !p.has_label(UNLISTED_LABEL))
+    }
+
+    pub fn get(&self, number: u64, now: OffsetDateTime) -> Option<Post> {
+        self.visible(now).find(|p| p.number == number).cloned()
+    }
+
+    /// 和 blog::get_home 一样的规则
+    pub fn home(&self, sort: HomeSort, now: OffsetDateTime) -> Home {
+        let pick = |f: &dyn Fn(&Post) -> bool| {
+            let mut posts: Vec<Post> = self.listed(now).filter(|p| f(p)).cloned().collect();
+            posts.sort_by_key(|p| std::cmp::Reverse(sort.key(p)));
+            posts.truncate(blog::HOME_SIZE);
+            posts
+        };
+        Home {
+            pinned: pick(&|p| p.has_label(PINNED_LABEL)),
+            featured: pick(&|p| p.has_label(FEATURED_LABEL)),
+            posts: pick(&|p| !p.has_label(PINNED_LABEL)),
+        }
+    }
+
+    /// 只按标题和正文的文字做简单的包含匹配，标签和属性不算，Protected 的文章只匹配标题
+    pub fn search(&self, filter: Option<&str>, now: OffsetDateTime) -> Vec<Post> {
+        let filter = filter.map(str::to_lowercase);
+        let mut posts: Vec<Post> = self
+            .listed(now)
+            .filter(|p| {
+                filter.as_deref().is_none_or(|f| {
+                    p.title.to_lowercase().contains(f)
+                        || (!p.is_protected() && plain_text(&p.html()).to_lowercase().contains(f))
+                })
+            })
+            .cloned()
+            .collect();
+        posts.sort_by_key(|p| std::cmp::Reverse(p.updated_at));
+        posts.truncate(SEARCH_SIZE);
+        posts
+    }
+}
+
+// 去掉 HTML 标签，只留下文字
+fn plain_text(html: &str) -> String {
+    let text = RefCell::new(String::new());
+    let _ = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![text!("*", |t| {
+                text.borrow_mut().push_str(t.as_str());
+                Ok(())
+            })],
+            ..RewriteStrSettings::new()
+        },
+    );
+    unescape(&text.take())
+}
+
+/// 内存里的快照和它在磁盘上的文件
+#[derive(Debug)]
+pub struct SnapshotStore {
+    path: PathBuf,
+    current: RwLock<Option<Arc<Snapshot>>>,
+}
+
+impl SnapshotStore {
+    /// 读取上次保存的快照，文件不存在或损坏时从空开始
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let current = match fs::read(&path) {
+            Ok(data) => match serde_json::from_slice::<Snapshot>(&data) {
+                Ok(s) => {
+                    info!("读取快照 {:?}：{} 篇文章", path, s.posts.len());
+                    Some(Arc::new(s))
+                }
+                Err(err) => {
+                    warn!("快照 {:?} 无法解析，忽略：{}", path, err);
+                    None
+                }
+            },
+            Err(_) => None,
+        };
+        SnapshotStore {
+            path,
+            current: RwLock::new(current),
+        }
+    }
+
+    pub fn current(&self) -> Option<Arc<Snapshot>> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// 从 ES 取出所有文章写到磁盘，返回文章数；
+    /// 取到空列表时多半是索引正在重建，保留旧的快照
+    pub async fn refresh(&self, es_client: &Elasticsearch) -> Result<usize> {
+        let posts = blog::all_posts(es_client).await?;
+        if posts.is_empty() {
+            return Ok(0);
+        }
+        self.save(Snapshot {
+            saved_at: OffsetDateTime::now_utc(),
+            posts,
+        })
+    }
+
+    fn save(&self, snapshot: Snapshot) -> Result<usize> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // 先写临时文件再改名，写到一半时进程退出也不会留下坏文件
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(&snapshot)?)?;
+        fs::rename(&tmp, &self.path)?;
+        let n = snapshot.posts.len();
+        *self.current.write().unwrap() = Some(Arc::new(snapshot));
+        Ok(n)
+    }
+}
+
+/// 查询失败是因为连不上 ES 或 ES 返回了错误，而不是文章不存在
+pub fn is_unavailable(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<elasticsearch::Error>().is_some()
+}
+
+/// 在后台定时刷新快照，启动时先刷新一次
+pub fn spawn_refresh(store: Arc<SnapshotStore>, es_client: Arc<Elasticsearch>) {
+    tokio::spawn(async move {
+        loop {
+            match store.refresh(&es_client).await {
+                Ok(n) => info!("快照已刷新：{} 篇文章", n),
+                Err(err) => warn!("刷新快照失败，继续使用旧的快照：{:?}", err),
+            }
+            tokio::time::sleep(REFRESH_INTERVAL).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blog::{PostLabel, PROTECTED_LABEL};
+    use crate::testing::{self, fake_es, temp_dir};
+    use time::format_description::well_known::Rfc3339;
+    use time::macros::datetime;
+
+    fn post(number: u64, labels: &[&str], updated_at: &str) -> Post {
+        let mut post = testing::post(number, None);
+        post.labels = labels
+            .iter()
+            .map(|l| PostLabel {
+                name: l.to_string(),
+                description: String::new(),
+            })
+            .collect();
+        post.updated_at = OffsetDateTime::parse(updated_at, &Rfc3339).unwrap();
+        post.rendered_html = format!("<p>Rust 正文 {number}</p>");
+        post
+    }
+
+    fn snapshot() -> Snapshot {
+        let mut scheduled = post(5, &[], "2023-01-05T00:00:00Z");
+        scheduled.publish_at = Some(datetime!(2023-06-01 0:00 UTC));
+        let mut featured = post(3, &[FEATURED_LABEL], "2023-01-03T00:00:00Z");
+        featured.rendered_html =
+            r#"<p>Rust 正文 3</p><pre><code class="language-rust">a &lt; b</code></pre>"#
+                .to_string();
+        Snapshot {
+            saved_at: datetime!(2023-02-01 0:00 UTC),
+            posts: vec![
+                post(1, &[], "2023-01-01T00:00:00Z"),
+                post(2, &[PINNED_LABEL], "2023-01-02T00:00:00Z"),
+                featured,
+                post(4, &[UNLISTED_LABEL], "2023-01-04T00:00:00Z"),
+                scheduled,
+                post(6, &[PROTECTED_LABEL], "2022-12-01T00:00:00Z"),
+            ],
+        }
+    }
+
+    fn numbers(posts: &[Post]) -> Vec<u64> {
+        posts.iter().map(|p| p.number).collect()
+    }
+
+    #[test]
+    fn test_queries() {
+        let s = snapshot();
+        let now = datetime!(2023-03-01 0:00 UTC);
+        assert_eq!(s.get(4, now).map(|p| p.number), Some(4));
+        assert!(s.get(5, now).is_none());
+        assert!(s.get(5, datetime!(2023-07-01 0:00 UTC)).is_some());
+
+        let home = s.home(HomeSort::UpdatedAt, now);
+        assert_eq!(numbers(&home.pinned), vec![2]);
+        assert_eq!(numbers(&home.featured), vec![3]);
+        assert_eq!(numbers(&home.posts), vec![3, 1, 6]);
+
+        assert_eq!(numbers(&s.search(None, now)), vec![3, 2, 1, 6]);
+        assert_eq!(numbers(&s.search(Some("第 2"), now)), vec![2]);
+        assert_eq!(numbers(&s.search(Some("rust 正文 1"), now)), vec![1]);
+        // 标签和属性里的内容搜不到
+        assert!(s.search(Some("<p>"), now).is_empty());
+        assert!(s.search(Some("code"), now).is_empty());
+        assert_eq!(numbers(&s.search(Some("a < b"), now)), vec![3]);
+        // Protected 的文章只能按标题搜到
+        assert_eq!(numbers(&s.search(Some("第 6"), now)), vec![6]);
+        assert!(s.search(Some("正文 6"), now).is_empty());
+    }
+
+    #[test]
+    fn test_store() {
+        let dir = temp_dir("snapshot");
+        let path = dir.join("snapshot.json");
+        let store = SnapshotStore::open(&path);
+        assert!(store.current().is_none());
+
+        assert_eq!(store.save(snapshot()).unwrap(), 6);
+        assert!(!path.with_extension("tmp").exists());
+        let reopened = SnapshotStore::open(&path);
+        let s = reopened.current().unwrap();
+        assert_eq!(s.saved_at, datetime!(2023-02-01 0:00 UTC));
+        assert_eq!(numbers(&s.posts), vec![1, 2, 3, 4, 5, 6]);
+
+        // 文件损坏时从空开始
+        fs::write(&path, "{").unwrap();
+        assert!(SnapshotStore::open(&path).current().is_none());
+    }
+
+    // 保存快照时还没到发布时间的文章也存下来，读取时按当时的时间决定是否展示
+    #[tokio::test]
+    async fn test_refresh_scheduled() {
+        let publish_at = datetime!(2023-06-01 0:00 UTC);
+        let es_client = fake_es(Some(vec![
+            testing::post(1, None),
+            testing::post(2, Some(publish_at)),
+        ]))
+        .await;
+        let dir = temp_dir("snapshot");
+        let store = SnapshotStore::open(dir.join("snapshot.json"));
+        assert_eq!(store.refresh(&es_client).await.unwrap(), 2);
+        let s = store.current().unwrap();
+
+        let before = datetime!(2023-05-31 23:59 UTC);
+        assert!(s.get(2, before).is_none());
+        assert_eq!(numbers(&s.home(HomeSort::UpdatedAt, before).posts), vec![1]);
+        assert_eq!(numbers(&s.search(None, before)), vec![1]);
+        assert_eq!(s.get(2, publish_at).map(|p| p.number), Some(2));
+        assert_eq!(
+            numbers(&s.home(HomeSort::CreatedAt, publish_at).posts).len(),
+            2
+        );
+        assert_eq!(numbers(&s.search(Some("第 2"), publish_at)), vec![2]);
+    }
+
+    #[tokio::test]
+    async fn test_unavailable() {
+        let es_client = crate::es::init("http://127.0.0.1:9").unwrap();
+        let err = blog::get_one_blog(&es_client, 1, OffsetDateTime::now_utc())
+            .await
+            .unwrap_err();
+        assert!(is_unavailable(&err));
+        assert!(!is_unavailable(&anyhow::anyhow!("没有找到")));
+
+        // ES 连不上时保留旧的快照
+        let dir = temp_dir("snapshot");
+        let store = SnapshotStore::open(dir.join("snapshot.json"));
+        store.save(snapshot()).unwrap();
+        assert!(store.refresh(&es_client).await.is_err());
+        assert_eq!(store.current().unwrap().posts.len(), 6);
+    }
+}
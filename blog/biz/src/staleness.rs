@@ -0,0 +1,235 @@
+use crate::blog::Post;
+use serde::{Deserialize, Serialize};
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// 不提示，用来给 Evergreen 之类的标签兜底
+    Fresh,
+    Info,
+    Warning,
+    Danger,
+}
+
+/// 一条过期规则，写了的条件必须全部满足；规则按顺序匹配，第一条命中的生效
+#[derive(Debug, Clone, Deserialize)]
+pub struct StalenessRule {
+    /// 文章带有这个标签
+    #[serde(default)]
+    pub label: Option<String>,
+    /// 距最后更新超过的天数
+    #[serde(default)]
+    pub updated_days: Option<i64>,
+    /// 距发布超过的天数
+    #[serde(default)]
+    pub created_days: Option<i64>,
+    pub severity: Severity,
+    /// 提示文案，可以使用 {created_at} {updated_at} {created_days} {updated_days}
+    #[serde(default)]
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Staleness {
+    Fresh,
+    Stale { severity: Severity, message: String },
+}
+
+pub fn default_rules() -> Vec<StalenessRule> {
+    vec![
+        StalenessRule {
+            label: Some("Outdated".to_string()),
+            updated_days: None,
+            created_days: None,
+            severity: Severity::Danger,
+            message: "警告：本文已被标记为过期存档，文中所描述的信息已发生改变，请不要使用。"
+                .to_string(),
+        },
+        StalenessRule {
+            label: Some("Evergreen".to_string()),
+            updated_days: None,
+            created_days: None,
+            severity: Severity::Fresh,
+            message: String::new(),
+        },
+        StalenessRule {
+            label: None,
+            updated_days: Some(365),
+            created_days: None,
+            severity: Severity::Warning,
+            message:
+                "提醒：本文最后更新于 {updated_at} ，文中所描述的信息可能已发生改变，请谨慎使用。"
+                    .to_string(),
+        },
+        StalenessRule {
+            label: None,
+            updated_days: None,
+            created_days: Some(500),
+            severity: Severity::Warning,
+            message: "提醒：本文发布于 {created_at} ，文中所描述的信息可能已发生改变，请谨慎使用。"
+                .to_string(),
+        },
+    ]
+}
+
+impl StalenessRule {
+    fn matches(&self, post: &Post, updated_days: i64, created_days: i64) -> bool {
+        self.label
+            .as_ref()
+            .is_none_or(|l| post.labels.iter().any(|pl| &pl.name == l))
+            && self.updated_days.is_none_or(|d| updated_days > d)
+            && self.created_days.is_none_or(|d| created_days > d)
+    }
+}
+
+pub fn evaluate(rules: &[StalenessRule], post: &Post, now: OffsetDateTime) -> Staleness {
+    let updated_days = (now - post.updated_at).whole_days().abs();
+    let created_days = (now - post.created_at).whole_days().abs();
+    let rule = rules
+        .iter()
+        .find(|r| r.matches(post, updated_days, created_days));
+    match rule {
+        None
+        | Some(StalenessRule {
+            severity: Severity::Fresh,
+            ..
+        }) => Staleness::Fresh,
+        Some(r) => {
+            let format = format_description!("[year]/[month]/[day]");
+            let message = r
+                .message
+                .replace(
+                    "{created_at}",
+                    &post.created_at.format(&format).unwrap_or_default(),
+                )
+                .replace(
+                    "{updated_at}",
+                    &post.updated_at.format(&format).unwrap_or_default(),
+                )
+                .replace("{created_days}", &created_days.to_string())
+                .replace("{updated_days}", &updated_days.to_string());
+            Staleness::Stale {
+                severity: r.severity,
+                message,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blog::PostLabel;
+    use time::macros::datetime;
+    use time::Duration;
+
+    fn post(labels: &[&str], created_days_ago: i64, updated_days_ago: i64) -> Post {
+        let now = datetime!(2023-08-01 0:00 UTC);
+        serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "number": 1,
+            "title": "t",
+            "labels": labels.iter().map(|l| PostLabel { name: l.to_string(), description: String::new() }).collect::<Vec<_>>(),
+            "state": "open",
+            "created_at": (now - Duration::days(created_days_ago)).format(&time::format_description::well_known::Iso8601::DEFAULT).unwrap(),
+            "updated_at": (now - Duration::days(updated_days_ago)).format(&time::format_description::well_known::Iso8601::DEFAULT).unwrap(),
+            "body_html": ""
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_default_rules() {
+        let now = datetime!(2023-08-01 0:00 UTC);
+        let rules = default_rules();
+        assert_eq!(evaluate(&rules, &post(&[], 10, 10), now), Staleness::Fresh);
+        assert_eq!(
+            evaluate(&rules, &post(&["Outdated"], 10, 10), now),
+            Staleness::Stale {
+                severity: Severity::Danger,
+                message: rules[0].message.clone()
+            }
+        );
+        assert_eq!(
+            evaluate(&rules, &post(&[], 400, 366), now),
+            Staleness::Stale {
+                severity: Severity::Warning,
+                message:
+                    "提醒：本文最后更新于 2022/07/31 ，文中所描述的信息可能已发生改变，请谨慎使用。"
+                        .to_string()
+            }
+        );
+        assert!(matches!(
+            evaluate(&rules, &post(&[], 501, 10), now),
+            Staleness::Stale { message, .. } if message.contains("发布于 2022/03/18")
+        ));
+        // Evergreen 不会因为时间过期，但 Outdated 优先
+        assert_eq!(
+            evaluate(&rules, &post(&["Evergreen"], 1000, 1000), now),
+            Staleness::Fresh
+        );
+        assert!(matches!(
+            evaluate(&rules, &post(&["Evergreen", "Outdated"], 1, 1), now),
+            Staleness::Stale {
+                severity: Severity::Danger,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_config_rules() {
+        let conf: crate::Config = toml::from_str(
+            r#"
+            github_token = ""
+            github_repo = ""
+            github_owner = ""
+            es_url = ""
+
+            [[staleness]]
+            label = "Draft"
+            severity = "info"
+            message = "草稿，写了 {created_days} 天"
+
+            [[staleness]]
+            label = "Legacy"
+            updated_days = 30
+            created_days = 60
+            severity = "danger"
+            message = "{updated_days}"
+            "#,
+        )
+        .unwrap();
+        let now = datetime!(2023-08-01 0:00 UTC);
+        assert_eq!(
+            evaluate(&conf.staleness, &post(&["Draft"], 3, 1), now),
+            Staleness::Stale {
+                severity: Severity::Info,
+                message: "草稿，写了 3 天".to_string()
+            }
+        );
+        assert_eq!(
+            evaluate(&conf.staleness, &post(&["Legacy"], 50, 40), now),
+            Staleness::Fresh
+        );
+        assert_eq!(
+            evaluate(&conf.staleness, &post(&["Legacy"], 61, 40), now),
+            Staleness::Stale {
+                severity: Severity::Danger,
+                message: "40".to_string()
+            }
+        );
+        // 配置了规则就完全替换默认规则
+        assert_eq!(
+            evaluate(&conf.staleness, &post(&[], 1000, 1000), now),
+            Staleness::Fresh
+        );
+        let conf: crate::Config = toml::from_str(
+            "github_token = \"\"\ngithub_repo = \"\"\ngithub_owner = \"\"\nes_url = \"\"",
+        )
+        .unwrap();
+        assert_eq!(conf.staleness.len(), default_rules().len());
+    }
+}
@@ -0,0 +1,377 @@
+use crate::github_issues::{self, SyncReport};
+use crate::{metrics, Config};
+use anyhow::Result;
+use elasticsearch::Elasticsearch;
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use tracing::{info, info_span, warn, Instrument};
+
+// 失败后最长的等待时间
+const MAX_BACKOFF: Duration = Duration::from_secs(6 * 3600);
+// 随机多等最多 10% 的间隔，避免多个实例同时请求 GitHub
+const JITTER: f64 = 0.1;
+// 启动时的同步失败后，第一次重试前等待的时间，之后翻倍
+const STARTUP_RETRY: Duration = Duration::from_secs(10);
+// 启动时的同步重试间隔的上限
+const STARTUP_MAX_RETRY: Duration = Duration::from_secs(5 * 60);
+// 增量同步往前多取一段，抵消本机和 GitHub 的时钟误差
+const WATERMARK_OVERLAP: time::Duration = time::Duration::minutes(5);
+
+/// 最近一次同步的情况，健康检查和日志都从这里读
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncStatus {
+    pub running: bool,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_started_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_finished_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_success_at: Option<OffsetDateTime>,
+    /// 最近一次同步的结果
+    pub last_report: Option<String>,
+    /// 最近一次失败的原因，成功后清空
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+    /// 最近一次完成的同步里没同步成功的文章；个别文章失败不影响这次同步算作成功
+    pub failed_issues: Vec<u64>,
+    /// 启动时用 --skip-sync 跳过了首次同步，直接用索引里已有的数据
+    pub initial_sync_skipped: bool,
+}
+
+/// 所有同步都经过这里：同一时间只有一个同步在跑，后台定时同步和 webhook 触发的同步不会重叠
+#[derive(Debug, Default)]
+pub struct Syncer {
+    lock: Mutex<()>,
+    status: RwLock<SyncStatus>,
+    // 下次增量同步的 since：上次全部成功的全量或增量同步的开始时间减去 WATERMARK_OVERLAP。
+    // 不用索引里最新的 updated_at，webhook 单独同步的文章会把它推到丢了 webhook 的修改之后
+    watermark: RwLock<Option<OffsetDateTime>>,
+}
+
+impl Syncer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn status(&self) -> SyncStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    /// 全量同步，已经有同步在跑时等它结束再开始
+    pub async fn sync_all(&self, conf: &Config, es_client: &Elasticsearch) -> Result<SyncReport> {
+        let _guard = self.lock.lock().await;
+        self.run(conf, es_client, false).await
+    }
+
+    /// 只同步一篇文章，和其他同步一样排队；不影响 status
+    pub async fn sync_one(
+        &self,
+        conf: &Config,
+        es_client: &Elasticsearch,
+        number: u64,
+    ) -> Result<SyncReport> {
+        let _guard = self.lock.lock().await;
+        let started = std::time::Instant::now();
+        let result = github_issues::sync_one(conf, es_client, number)
+            .instrument(info_span!("sync", kind = "issue", number))
+            .await;
+        record_metrics("issue", &result, started);
+        result
+    }
+
+    /// 增量同步；已经有同步在跑时直接跳过，返回 None。
+    /// 本进程还没有全部成功的同步时（比如 --skip-sync 启动）做全量同步
+    pub async fn try_sync_incremental(
+        &self,
+        conf: &Config,
+        es_client: &Elasticsearch,
+    ) -> Option<Result<SyncReport>> {
+        let _guard = self.lock.try_lock().ok()?;
+        Some(self.run(conf, es_client, true).await)
+    }
+
+    async fn run(
+        &self,
+        conf: &Config,
+        es_client: &Elasticsearch,
+        incremental: bool,
+    ) -> Result<SyncReport> {
+        let started_at = OffsetDateTime::now_utc();
+        {
+            let mut status = self.status.write().unwrap();
+            status.running = true;
+            status.last_started_at = Some(started_at);
+        }
+        let since = if incremental {
+            *self.watermark.read().unwrap()
+        } else {
+            None
+        };
+        let kind = if since.is_some() {
+            "incremental"
+        } else {
+            "full"
+        };
+        let started = std::time::Instant::now();
+        let result = github_issues::sync_issues(conf, es_client, since)
+            .instrument(info_span!("sync", kind))
+            .await;
+        // 有文章失败时不前移，下次增量同步还会取到它们
+        if result.as_ref().is_ok_and(|r| r.failed.is_empty()) {
+            *self.watermark.write().unwrap() = Some(started_at - WATERMARK_OVERLAP);
+        }
+        self.finish(&result);
+        record_metrics(kind, &result, started);
+        result
+    }
+
+    // 同步跑完就算成功，个别文章失败单独记在 failed_issues 里，不然一篇文章一直失败
+    // 就永远不会就绪；整个同步出错（ES、GitHub 不可用）才算失败
+    fn finish(&self, result: &Result<SyncReport>) {
+        let now = OffsetDateTime::now_utc();
+        let mut status = self.status.write().unwrap();
+        status.running = false;
+        status.last_finished_at = Some(now);
+        match result {
+            Ok(report) => {
+                status.last_report = Some(report.to_string());
+                status.last_success_at = Some(now);
+                status.last_error = None;
+                status.consecutive_failures = 0;
+                status.failed_issues = report.failed.iter().map(|(n, _)| *n).collect();
+            }
+            Err(err) => {
+                status.last_error = Some(format!("{err:#}"));
+                status.consecutive_failures += 1;
+            }
+        }
+    }
+
+    fn skip_initial_sync(&self) {
+        self.status.write().unwrap().initial_sync_skipped = true;
+    }
+}
+
+fn record_metrics(kind: &str, result: &Result<SyncReport>, started: std::time::Instant) {
+    let (ok, issues) = match result {
+        Ok(report) => (report.failed.is_empty(), report.issues),
+        Err(_) => (false, 0),
+    };
+    metrics::sync_finished(kind, ok, started.elapsed().as_secs_f64(), issues);
+}
+
+/// 在后台同步：sync_now 时先做一次全量同步，失败了按退避重试直到成功；
+/// 之后按配置的间隔做增量同步，interval 为 0 时不做定时同步
+pub fn spawn_periodic(
+    syncer: Arc<Syncer>,
+    conf: Arc<Config>,
+    es_client: Arc<Elasticsearch>,
+    sync_now: bool,
+) {
+    if !sync_now {
+        syncer.skip_initial_sync();
+    }
+    tokio::spawn(async move {
+        if sync_now {
+            initial_sync(&syncer, &conf, &es_client).await;
+        }
+        if conf.sync_interval == 0 {
+            info!("sync_interval 为 0，不做定时同步");
+            return;
+        }
+        let interval = Duration::from_secs(conf.sync_interval);
+        loop {
+            let failures = syncer.status().consecutive_failures;
+            tokio::time::sleep(next_delay(interval, failures, fastrand::f64())).await;
+            match syncer.try_sync_incremental(&conf, &es_client).await {
+                None => info!("已经有同步在进行，跳过这次定时同步"),
+                Some(Ok(report)) if report.failed.is_empty() => info!("定时同步完成：{}", report),
+                Some(Ok(report)) => warn!("定时同步部分失败：{}", report),
+                Some(Err(err)) => warn!(
+                    "定时同步失败，第 {} 次：{:?}",
+                    syncer.status().consecutive_failures,
+                    err
+                ),
+            }
+        }
+    });
+}
+
+// ES 或 GitHub 还没准备好时不停重试；部分文章失败不重试，留给之后的定时同步
+async fn initial_sync(syncer: &Syncer, conf: &Config, es_client: &Elasticsearch) {
+    loop {
+        match syncer.sync_all(conf, es_client).await {
+            Ok(report) => {
+                info!("启动同步完成：{}", report);
+                return;
+            }
+            Err(err) => {
+                let failures = syncer.status().consecutive_failures;
+                let delay = next_delay(STARTUP_RETRY, failures.saturating_sub(1), fastrand::f64())
+                    .min(STARTUP_MAX_RETRY);
+                warn!(
+                    "启动同步失败，第 {} 次，{:?} 后重试：{:?}",
+                    failures, delay, err
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+/// 下一次同步前等待的时间：连续失败时间隔翻倍，最多等 MAX_BACKOFF（间隔本身更长时按间隔）；
+/// random 取 [0, 1)，用来加上抖动
+fn next_delay(interval: Duration, failures: u32, random: f64) -> Duration {
+    let base = interval
+        .saturating_mul(2u32.saturating_pow(failures.min(16)))
+        .min(MAX_BACKOFF.max(interval));
+    base + base.mul_f64(JITTER * random)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::format_description::well_known::Rfc3339;
+
+    #[test]
+    fn test_next_delay() {
+        let minute = Duration::from_secs(60);
+        assert_eq!(next_delay(minute * 15, 0, 0.0), minute * 15);
+        assert_eq!(
+            next_delay(minute * 15, 0, 0.5),
+            minute * 15 + minute * 3 / 4
+        );
+        assert_eq!(next_delay(minute * 15, 1, 0.0), minute * 30);
+        assert_eq!(next_delay(minute * 15, 3, 0.0), minute * 120);
+        assert_eq!(next_delay(minute * 15, 10, 0.0), MAX_BACKOFF);
+        assert_eq!(next_delay(minute * 15, u32::MAX, 0.0), MAX_BACKOFF);
+        // 间隔比最长退避还长时不会缩短
+        assert_eq!(next_delay(MAX_BACKOFF * 2, 3, 0.0), MAX_BACKOFF * 2);
+    }
+
+    #[test]
+    fn test_status() {
+        let syncer = Syncer::new();
+        let report = SyncReport {
+            issues: 2,
+            ..Default::default()
+        };
+        syncer.finish(&Ok(report));
+        let status = syncer.status();
+        assert!(!status.running);
+        assert!(status.last_success_at.is_some());
+        assert_eq!(status.consecutive_failures, 0);
+        let last_success_at = status.last_success_at;
+
+        syncer.finish(&Err(anyhow::anyhow!("GitHub 超时")));
+        syncer.finish(&Err(anyhow::anyhow!("GitHub 超时")));
+        let status = syncer.status();
+        assert_eq!(status.consecutive_failures, 2);
+        assert_eq!(status.last_error.as_deref(), Some("GitHub 超时"));
+        assert_eq!(status.last_success_at, last_success_at);
+
+        // 个别文章失败也算同步成功，失败的文章单独记下来
+        syncer.finish(&Ok(SyncReport {
+            failed: vec![(1, "x".to_string())],
+            ..Default::default()
+        }));
+        let status = syncer.status();
+        assert_eq!(status.consecutive_failures, 0);
+        assert_eq!(status.last_error, None);
+        assert_ne!(status.last_success_at, last_success_at);
+        assert_eq!(status.failed_issues, vec![1]);
+
+        syncer.finish(&Ok(SyncReport::default()));
+        assert!(syncer.status().failed_issues.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_single_flight() {
+        let syncer = Syncer::new();
+        let conf = Config::default();
+        let es_client = crate::es::init("http://127.0.0.1:9").unwrap();
+        // 模拟 webhook 触发的同步正在进行
+        let guard = syncer.lock.lock().await;
+        assert!(syncer
+            .try_sync_incremental(&conf, &es_client)
+            .await
+            .is_none());
+        drop(guard);
+        // ES 连不上，同步失败但会记录下来
+        assert!(syncer
+            .try_sync_incremental(&conf, &es_client)
+            .await
+            .unwrap()
+            .is_err());
+        assert_eq!(syncer.status().consecutive_failures, 1);
+    }
+
+    #[tokio::test]
+    async fn test_watermark() {
+        use axum::extract::Query;
+        use axum::routing::get;
+        use axum::{Json, Router};
+        use serde_json::json;
+        use std::collections::HashMap;
+
+        // 假 GitHub：记下每次列表请求的 since，第 2 篇文章是刚刚改过的
+        let requested = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let r = requested.clone();
+        let app = Router::new()
+            .route(
+                "/repos/o/r/issues",
+                get(move |Query(q): Query<HashMap<String, String>>| async move {
+                    r.lock().unwrap().push(q.get("since").cloned());
+                    Json(json!([]))
+                }),
+            )
+            .route(
+                "/repos/o/r/issues/2",
+                get(|| async {
+                    Json(json!({
+                        "comments_url": "", "id": 2, "node_id": "", "number": 2, "title": "新文章",
+                        "user": {"login": "hjin-me", "id": 1, "node_id": "x", "avatar_url": "a"},
+                        "labels": [], "state": "open", "comments": 0,
+                        "created_at": "2099-01-01T00:00:00Z", "updated_at": "2099-01-01T00:00:00Z",
+                        "body": "正文", "body_text": "正文", "body_html": "<p>正文</p>",
+                        "reactions": {"total_count": 0}
+                    }))
+                }),
+            );
+        let addr = crate::testing::serve(app).await;
+        let conf = Config {
+            github_api_url: format!("http://{addr}"),
+            github_owner: "o".to_string(),
+            github_repo: "r".to_string(),
+            ..Default::default()
+        };
+        let es_client = crate::testing::fake_es(Some(vec![])).await;
+        let syncer = Syncer::new();
+
+        // 还没有成功同步过时，定时同步也是全量的
+        let before = OffsetDateTime::now_utc();
+        syncer
+            .try_sync_incremental(&conf, &es_client)
+            .await
+            .unwrap()
+            .unwrap();
+        syncer.sync_one(&conf, &es_client, 2).await.unwrap();
+        syncer
+            .try_sync_incremental(&conf, &es_client)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let requested = requested.lock().unwrap();
+        assert_eq!(requested.len(), 2);
+        assert_eq!(requested[0], None);
+        // webhook 同步的新文章不影响下次增量同步的起点
+        let since = requested[1].as_deref().unwrap();
+        let since = OffsetDateTime::parse(since, &Rfc3339).unwrap();
+        assert!(since <= before - WATERMARK_OVERLAP + time::Duration::seconds(1));
+        assert!(since > before - WATERMARK_OVERLAP - time::Duration::seconds(10));
+    }
+}
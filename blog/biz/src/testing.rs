@@ -0,0 +1,149 @@
+//! 测试用的本地 HTTP 服务、假 ES 和临时目录。biz 自己的测试直接用，serv 的测试通过 testing feature 使用
+use crate::blog::Post;
+use crate::schedule;
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::routing::{any, get};
+use axum::{Json, Router};
+use elasticsearch::Elasticsearch;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// 在随机端口上启动 app，返回监听的地址
+pub async fn serve(app: Router) -> SocketAddr {
+    let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+/// 系统临时目录下新建一个不会重名的目录
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "blog_test_{}_{}",
+        name,
+        OffsetDateTime::now_utc().unix_timestamp_nanos()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// 第 number 篇文章，标题是“第 number 篇”，没有标签和正文
+pub fn post(number: u64, publish_at: Option<OffsetDateTime>) -> Post {
+    let mut post: Post = serde_json::from_value(json!({
+        "id": number,
+        "number": number,
+        "title": format!("第 {number} 篇"),
+        "labels": [],
+        "state": "open",
+        "created_at": "2023-01-01T00:00:00Z",
+        "updated_at": "2023-01-01T00:00:00Z",
+        "body_html": ""
+    }))
+    .unwrap();
+    post.publish_at = publish_at;
+    post
+}
+
+fn find<'a>(v: &'a Value, key: &str) -> Option<&'a Value> {
+    match v {
+        Value::Object(m) => m.get(key).or_else(|| m.values().find_map(|v| find(v, key))),
+        Value::Array(a) => a.iter().find_map(|v| find(v, key)),
+        _ => None,
+    }
+}
+
+// 查询里 labels.name 的 term，比如 has_label 生成的条件
+fn labels(v: &Value) -> Vec<String> {
+    match v {
+        Value::Object(m) => m
+            .get("labels.name")
+            .and_then(|l| l.as_str())
+            .map(|l| vec![l.to_string()])
+            .unwrap_or_else(|| m.values().flat_map(labels).collect()),
+        Value::Array(a) => a.iter().flat_map(labels).collect(),
+        _ => vec![],
+    }
+}
+
+// 只看最外层 bool 查询的 filter（必须有的标签）和 must_not（不能有的标签），
+// 以及文章编号和发布时间，其他条件都忽略
+fn matches(q: &Value, post: &Post) -> bool {
+    let number = find(q, "match").and_then(|m| m["number"].as_u64());
+    let now = find(q, "publish_at")
+        .and_then(|r| r["lte"].as_str())
+        .and_then(|t| OffsetDateTime::parse(t, &Rfc3339).ok());
+    let query = &q["query"]["bool"];
+    number.is_none_or(|n| post.number == n)
+        && now.is_none_or(|now| schedule::is_published(post.publish_at, now))
+        && labels(&query["filter"]).iter().all(|l| post.has_label(l))
+        && !labels(&query["must_not"]).iter().any(|l| post.has_label(l))
+}
+
+/// 假 ES，blog 别名指向的索引里是 posts，其他索引都是空的；posts 为 None 时 blog 索引不存在。
+/// 建索引、写文档之类的其他请求都直接返回成功
+pub async fn fake_es_addr(posts: Option<Vec<Post>>) -> SocketAddr {
+    let posts = Arc::new(posts);
+    let search_posts = posts.clone();
+    let alias_posts = posts.clone();
+    let app = Router::new()
+        .route("/", get(|| async { "{}" }))
+        .route(
+            "/:index/_search",
+            any(
+                move |Path(index): Path<String>, Json(q): Json<Value>| async move {
+                    let hits: Vec<Value> = match (&*search_posts, index.as_str()) {
+                        (None, "blog") => {
+                            return (StatusCode::NOT_FOUND, Json(index_not_found()));
+                        }
+                        (Some(posts), "blog") => posts
+                            .iter()
+                            .filter(|p| matches(&q, p))
+                            .map(|p| json!({ "_id": p.id, "_source": p }))
+                            .collect(),
+                        _ => vec![],
+                    };
+                    let body =
+                        json!({ "hits": { "total": { "value": hits.len() }, "hits": hits } });
+                    (StatusCode::OK, Json(body))
+                },
+            ),
+        )
+        .route(
+            "/:index/_alias",
+            get(move |Path(index): Path<String>| async move {
+                match (&*alias_posts, index.as_str()) {
+                    (Some(_), "blog") => (
+                        StatusCode::OK,
+                        Json(json!({ "blog_1": { "aliases": { "blog": {} } } })),
+                    ),
+                    _ => (StatusCode::NOT_FOUND, Json(index_not_found())),
+                }
+            }),
+        )
+        .route(
+            "/:index/_count",
+            any(move || async move {
+                match &*posts {
+                    Some(posts) => (StatusCode::OK, Json(json!({ "count": posts.len() }))),
+                    None => (StatusCode::NOT_FOUND, Json(index_not_found())),
+                }
+            }),
+        )
+        .fallback(|| async { Json(json!({})) });
+    serve(app).await
+}
+
+fn index_not_found() -> Value {
+    json!({ "error": { "type": "index_not_found_exception" }, "status": 404 })
+}
+
+/// 连到 fake_es_addr 的客户端
+pub async fn fake_es(posts: Option<Vec<Post>>) -> Elasticsearch {
+    let addr = fake_es_addr(posts).await;
+    crate::es::init(&format!("http://{addr}")).unwrap()
+}
@@ -0,0 +1,200 @@
+use crate::sanitize::ID_PREFIX;
+use crate::utils::{escape, unescape};
+use lol_html::html_content::ContentType;
+use lol_html::{element, end_tag, rewrite_str, text, RewriteStrSettings};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+const HEADINGS: &str = "h1, h2, h3, h4";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TocEntry {
+    pub level: u8,
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub children: Vec<TocEntry>,
+}
+
+/// 给 h1-h4 加上稳定的 id 和悬停锚点，并返回按层级嵌套的目录；id 和清理 HTML 时一样带 user-content- 前缀
+pub fn anchor_headings(html: &str) -> (String, Vec<TocEntry>) {
+    // lol_html 是流式的，设置 id 时还没读到标题文字，所以先扫一遍取出所有标题
+    let headings = collect_headings(html);
+    if headings.is_empty() {
+        return (html.to_string(), vec![]);
+    }
+    let mut used = HashMap::new();
+    let flat: Vec<TocEntry> = headings
+        .into_iter()
+        .map(|(level, title)| TocEntry {
+            level,
+            id: format!("{ID_PREFIX}{}", unique_slug(&title, &mut used)),
+            title,
+            children: vec![],
+        })
+        .collect();
+
+    let mut ids = flat.iter().map(|e| e.id.clone());
+    let rewritten = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                // GitHub 自带的锚点指向 user-content-* ，和我们的 id 对不上
+                element!("a.anchor", |el| {
+                    el.remove();
+                    Ok(())
+                }),
+                element!(HEADINGS, |el| {
+                    if let Some(id) = ids.next() {
+                        el.set_attribute("id", &id)?;
+                        el.prepend(
+                            &format!(
+                                r##"<a class="heading-anchor" href="#{}" aria-hidden="true">#</a>"##,
+                                escape(&id)
+                            ),
+                            ContentType::Html,
+                        );
+                    }
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    );
+    match rewritten {
+        Ok(h) => (h, nest(flat)),
+        Err(_) => (html.to_string(), vec![]),
+    }
+}
+
+fn collect_headings(html: &str) -> Vec<(u8, String)> {
+    let headings = Rc::new(RefCell::new(Vec::new()));
+    let current = Rc::new(RefCell::new(String::new()));
+    let _ = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                text!(HEADINGS, |t| {
+                    current.borrow_mut().push_str(t.as_str());
+                    Ok(())
+                }),
+                element!(HEADINGS, |el| {
+                    let level = el.tag_name()[1..].parse::<u8>().unwrap_or(1);
+                    let headings = headings.clone();
+                    let current = current.clone();
+                    current.borrow_mut().clear();
+                    el.on_end_tag(end_tag!(move |_| {
+                        let title = unescape(current.take().trim());
+                        headings.borrow_mut().push((level, title));
+                        Ok(())
+                    }))
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    );
+    headings.take()
+}
+
+/// 和 GitHub 的规则一致：转小写，空白变成 -，去掉标点，中文等字符原样保留
+pub fn slugify(title: &str) -> String {
+    title
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                Some(c)
+            } else if c.is_whitespace() {
+                Some('-')
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn unique_slug(title: &str, used: &mut HashMap<String, usize>) -> String {
+    let mut slug = slugify(title);
+    if slug.is_empty() {
+        slug = "section".to_string();
+    }
+    let n = used.entry(slug.clone()).or_insert(0);
+    *n += 1;
+    if *n == 1 {
+        slug
+    } else {
+        format!("{}-{}", slug, *n - 1)
+    }
+}
+
+// 把平铺的标题按层级挂到上一个更高级的标题下面
+fn nest(flat: Vec<TocEntry>) -> Vec<TocEntry> {
+    let mut roots: Vec<TocEntry> = Vec::new();
+    let mut stack: Vec<TocEntry> = Vec::new();
+    for entry in flat {
+        while stack.last().is_some_and(|top| top.level >= entry.level) {
+            pop_into(&mut stack, &mut roots);
+        }
+        stack.push(entry);
+    }
+    while !stack.is_empty() {
+        pop_into(&mut stack, &mut roots);
+    }
+    roots
+}
+
+fn pop_into(stack: &mut Vec<TocEntry>, roots: &mut Vec<TocEntry>) {
+    if let Some(done) = stack.pop() {
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(done),
+            None => roots.push(done),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("如何使用 Rust 写博客？"), "如何使用-rust-写博客");
+        assert_eq!(slugify("  a_b-c  "), "a_b-c");
+    }
+
+    #[test]
+    fn test_anchor_headings() {
+        let html = "<h1>标题</h1><p>x</p><h2>安装 &amp; 配置</h2><h3>Linux</h3><h2>安装 &amp; 配置</h2><h4>细节</h4><h5>忽略</h5>";
+        let (out, toc) = anchor_headings(html);
+        assert!(out.starts_with(
+            r##"<h1 id="user-content-标题"><a class="heading-anchor" href="#user-content-标题" aria-hidden="true">#</a>标题</h1>"##
+        ));
+        assert!(out.contains(r#"<h2 id="user-content-安装--配置-1">"#));
+        assert!(!out.contains("<h5 id"));
+
+        assert_eq!(toc.len(), 1);
+        let root = &toc[0];
+        assert_eq!(root.id, "user-content-标题");
+        assert_eq!(root.children.len(), 2);
+        assert_eq!(root.children[0].title, "安装 & 配置");
+        assert_eq!(root.children[0].children[0].id, "user-content-linux");
+        assert_eq!(root.children[1].id, "user-content-安装--配置-1");
+        assert_eq!(root.children[1].children[0].level, 4);
+    }
+
+    #[test]
+    fn test_github_anchor() {
+        let html = r##"<h2><a id="user-content-intro" class="anchor" aria-hidden="true" href="#intro"><span class="octicon octicon-link"></span></a>Intro <code>x</code></h2>"##;
+        let (out, toc) = anchor_headings(html);
+        assert_eq!(
+            out,
+            r##"<h2 id="user-content-intro-x"><a class="heading-anchor" href="#user-content-intro-x" aria-hidden="true">#</a>Intro <code>x</code></h2>"##
+        );
+        assert_eq!(toc[0].title, "Intro x");
+        assert_eq!(anchor_headings("<p>x</p>").1, vec![]);
+    }
+}
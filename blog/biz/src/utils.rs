@@ -0,0 +1,29 @@
+/// 还原 HTML 文本里常见的实体，用于从渲染后的 HTML 中取回原文
+pub fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&nbsp;", "\u{a0}")
+        .replace("&amp;", "&")
+}
+
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        let s = r#"<a href="x">&amp;</a>"#;
+        assert_eq!(unescape(&escape(s)), s);
+        assert_eq!(unescape("&amp;lt;"), "&lt;");
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::Context;
+use axum::http::header::CONTENT_SECURITY_POLICY;
+use axum::http::HeaderValue;
+use leptos::nonce::use_nonce;
+use leptos::{use_context, Scope};
+use leptos_axum::ResponseOptions;
+
+// 策略里的占位符，渲染页面时替换成本次响应的 nonce
+const NONCE: &str = "{nonce}";
+
+// 文章 HTML 在同步时已经清理过，这里再加一层 CSP 兜底。
+// hydration、统计脚本和流式渲染的内联脚本都带 nonce，不需要 'unsafe-inline'；
+// wasm 需要 'wasm-unsafe-eval'，leptos 的样式是内联的，style-src 仍然需要 'unsafe-inline'
+pub const DEFAULT_CSP: &str = "default-src 'self'; \
+    script-src 'self' 'nonce-{nonce}' 'wasm-unsafe-eval' https://www.googletagmanager.com; \
+    style-src 'self' 'unsafe-inline'; \
+    img-src 'self' https: data:; \
+    connect-src 'self' https://*.google-analytics.com https://*.analytics.google.com https://www.googletagmanager.com; \
+    object-src 'none'; base-uri 'self'; form-action 'self'; frame-ancestors 'none'";
+
+/// Content-Security-Policy 响应头，策略里的 {nonce} 每次渲染页面时替换
+#[derive(Debug, Clone)]
+pub struct Csp {
+    policy: String,
+    fallback: HeaderValue,
+}
+
+impl Csp {
+    /// policy 为 None 时用默认策略，策略不是合法的响应头时返回错误
+    pub fn new(policy: Option<&str>) -> anyhow::Result<Self> {
+        let policy = policy.unwrap_or(DEFAULT_CSP).to_string();
+        // 静态文件、接口这些不渲染页面的响应没有 nonce，去掉这一项，也就不允许任何内联脚本
+        let fallback = HeaderValue::from_str(&policy.replace(&format!("'nonce-{NONCE}'"), ""))
+            .context("content_security_policy 配置不合法")?;
+        let csp = Csp { policy, fallback };
+        csp.with_nonce("nonce")
+            .context("content_security_policy 配置不合法")?;
+        Ok(csp)
+    }
+
+    pub fn fallback(&self) -> HeaderValue {
+        self.fallback.clone()
+    }
+
+    fn with_nonce(&self, nonce: &str) -> anyhow::Result<HeaderValue> {
+        Ok(HeaderValue::from_str(&self.policy.replace(NONCE, nonce))?)
+    }
+
+    /// 在渲染页面的 app 里调用，把带本次 nonce 的策略写到响应头
+    pub fn provide(&self, cx: Scope) {
+        let (Some(nonce), Some(res)) = (use_nonce(cx), use_context::<ResponseOptions>(cx)) else {
+            return;
+        };
+        if let Ok(value) = self.with_nonce(&nonce) {
+            res.insert_header(CONTENT_SECURITY_POLICY, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_csp() {
+        let csp = Csp::new(None).unwrap();
+        let fallback = csp.fallback();
+        let fallback = fallback.to_str().unwrap();
+        assert!(!fallback.contains("nonce"));
+        assert!(!fallback.contains("'unsafe-inline' 'wasm"));
+        assert!(csp
+            .with_nonce("abc")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .contains("script-src 'self' 'nonce-abc' 'wasm-unsafe-eval'"));
+
+        // 自定义的策略可以不用 nonce
+        let csp = Csp::new(Some("default-src 'self'")).unwrap();
+        assert_eq!(csp.fallback(), "default-src 'self'");
+        assert_eq!(csp.with_nonce("abc").unwrap(), "default-src 'self'");
+
+        assert!(Csp::new(Some("default-src 'self'\n")).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use anyhow::{bail, Result};
+use axum::body::{Body, HttpBody};
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use biz::feed::{self, FEED_FILE, SITEMAP_FILE};
+use elasticsearch::Elasticsearch;
+use leptos::LeptosOptions;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tower::ServiceExt;
+use tracing::{info, warn};
+use ui::home::SITE_TITLE;
+
+// 静态托管找不到页面时返回的文件
+const NOT_FOUND_PAGE: &str = "404.html";
+// 去掉 hydration 时要删掉的 <script> 和 <link>
+const HYDRATION_MARKERS: [&str; 4] = [
+    "__LEPTOS",
+    "{ hydrate }",
+    "modulepreload",
+    "application/wasm",
+];
+
+/// 用和线上一样的路由逐个渲染页面，写到 out 目录，配置了 site_url 时再生成 sitemap 和 feed，
+/// 最后把 site_root 下的静态资源和镜像的图片复制过去。返回导出的页面数
+pub async fn export(
+    app: Router,
+    es_client: &Elasticsearch,
+    options: &LeptosOptions,
+    conf: &biz::Config,
+    out: &Path,
+    no_hydrate: bool,
+) -> Result<usize> {
+    let now = time::OffsetDateTime::now_utc();
+    let mut pages = vec!["/".to_string()];
+    for n in biz::blog::published_numbers(es_client, now).await? {
+        pages.push(format!("/blog/{n}"));
+        pages.push(format!("/blog/{n}/history"));
+    }
+    fs::create_dir_all(out)?;
+    for page in &pages {
+        let (status, html) = render(&app, page).await?;
+        if status != StatusCode::OK {
+            bail!("渲染 {} 失败：{}", page, status);
+        }
+        let file = page_file(out, page);
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&file, finish(html, no_hydrate))?;
+        info!("导出 {} -> {:?}", page, file);
+    }
+    // 请求一个不存在的地址拿到 404 页面
+    let (_, html) = render(&app, "/404").await?;
+    fs::write(out.join(NOT_FOUND_PAGE), finish(html, no_hydrate))?;
+
+    // sitemap 和 feed 里只有列表里能看到的文章，Unlisted 的页面导出了但不公开地址
+    if conf.site_url.is_empty() {
+        warn!(
+            "没有配置 site_url，不生成 {} 和 {}",
+            SITEMAP_FILE, FEED_FILE
+        );
+    } else {
+        let posts = biz::blog::listed_posts(es_client, now).await?;
+        fs::write(
+            out.join(SITEMAP_FILE),
+            feed::sitemap(&conf.site_url, &posts),
+        )?;
+        fs::write(
+            out.join(FEED_FILE),
+            feed::atom(&conf.site_url, SITE_TITLE, &posts),
+        )?;
+        info!(
+            "导出 {} 和 {}，共 {} 篇文章",
+            SITEMAP_FILE,
+            FEED_FILE,
+            posts.len()
+        );
+    }
+
+    copy_dir(Path::new(&options.site_root), out, no_hydrate)?;
+    copy_dir(
+        Path::new(&conf.media_dir),
+        &out.join(biz::media::MEDIA_PATH.trim_start_matches('/')),
+        false,
+    )?;
+    Ok(pages.len())
+}
+
+async fn render(app: &Router, path: &str) -> Result<(StatusCode, String)> {
+    let req = Request::builder().uri(path).body(Body::empty())?;
+    let res = app.clone().oneshot(req).await?;
+    let status = res.status();
+    let mut body = res.into_body();
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+        buf.extend_from_slice(&chunk?);
+    }
+    Ok((status, String::from_utf8(buf)?))
+}
+
+// /blog/1 写到 blog/1/index.html，静态托管按目录默认页访问
+fn page_file(out: &Path, page: &str) -> PathBuf {
+    let dir = page.trim_matches('/');
+    if dir.is_empty() {
+        out.join("index.html")
+    } else {
+        out.join(dir).join("index.html")
+    }
+}
+
+fn finish(html: String, no_hydrate: bool) -> String {
+    if no_hydrate {
+        strip_hydration(&html)
+    } else {
+        html
+    }
+}
+
+/// 去掉 hydration 的脚本、资源数据和 wasm 预加载，统计之类的其他脚本保留
+fn strip_hydration(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    loop {
+        let next = ["<script", "<link"]
+            .into_iter()
+            .filter_map(|tag| rest.find(tag).map(|i| (i, tag)))
+            .min();
+        let Some((start, tag)) = next else {
+            break;
+        };
+        let close = if tag == "<script" { "</script>" } else { ">" };
+        let Some(len) = rest[start..].find(close).map(|i| i + close.len()) else {
+            break;
+        };
+        let element = &rest[start..start + len];
+        out.push_str(&rest[..start]);
+        if !HYDRATION_MARKERS.iter().any(|m| element.contains(m)) {
+            out.push_str(element);
+        }
+        rest = &rest[start + len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// 不需要 hydration 时 js 和 wasm 也不用复制
+fn copy_dir(from: &Path, to: &Path, no_hydrate: bool) -> Result<()> {
+    if !from.is_dir() {
+        warn!("目录 {:?} 不存在，跳过", from);
+        return Ok(());
+    }
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let path = entry?.path();
+        let target = to.join(path.file_name().unwrap_or_default());
+        if path.is_dir() {
+            // 输出目录放在 site_root 里面时不要把自己复制进去
+            if target.starts_with(&path) || path == to {
+                continue;
+            }
+            copy_dir(&path, &target, no_hydrate)?;
+        } else if !(no_hydrate
+            && path
+                .extension()
+                .is_some_and(|ext| ext == "js" || ext == "wasm"))
+        {
+            fs::copy(&path, &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::serv::test::Fixture;
+    use biz::testing::temp_dir;
+
+    #[test]
+    fn test_strip_hydration() {
+        let html = r#"<head><link rel="modulepreload" href="/pkg/hj.js"><link rel="preload" href="/pkg/hj_bg.wasm" as="fetch" type="application/wasm" crossorigin=""><script type="module">import init, { hydrate } from '/pkg/hj.js'; init('/pkg/hj_bg.wasm').then(hydrate);</script><link rel="stylesheet" href="/pkg/hj.css"><script async src="https://www.googletagmanager.com/gtag/js"></script></head><body><p>正文</p><script>__LEPTOS_PENDING_RESOURCES = [];</script></body>"#;
+        assert_eq!(
+            strip_hydration(html),
+            r#"<head><link rel="stylesheet" href="/pkg/hj.css"><script async src="https://www.googletagmanager.com/gtag/js"></script></head><body><p>正文</p></body>"#
+        );
+        assert_eq!(strip_hydration("<p>没有脚本</p>"), "<p>没有脚本</p>");
+    }
+
+    #[test]
+    fn test_page_file() {
+        let out = Path::new("dist");
+        assert_eq!(page_file(out, "/"), out.join("index.html"));
+        assert_eq!(
+            page_file(out, "/blog/1/history"),
+            out.join("blog/1/history/index.html")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export() {
+        let f = Fixture::with_conf("site_url = \"https://example.com\"").await;
+        let (es_client, options, conf) = (&f.es_client, &f.options, &f.conf);
+        for no_hydrate in [false, true] {
+            let out = temp_dir("out");
+            let pages = export(f.app(), es_client, options, conf, &out, no_hydrate)
+                .await
+                .unwrap();
+            assert_eq!(pages, 5);
+
+            let home = fs::read_to_string(out.join("index.html")).unwrap();
+            let blog = fs::read_to_string(out.join("blog/2/index.html")).unwrap();
+            assert!(out.join("blog/1/history/index.html").is_file());
+            assert!(out.join(NOT_FOUND_PAGE).is_file());
+            assert!(out.join("pkg/hj.css").is_file());
+            assert!(out.join("favicon.ico").is_file());
+            assert!(out.join("media/a.png").is_file());
+
+            assert_eq!(home.contains("{ hydrate }"), !no_hydrate);
+            assert_eq!(blog.contains("{ hydrate }"), !no_hydrate);
+            assert_eq!(out.join("pkg/hj_bg.wasm").is_file(), !no_hydrate);
+
+            assert!(!home.contains("Loading..."), "{home}");
+            assert!(home.contains("第一篇"));
+            // Unlisted 的文章不在列表里，但页面照样导出
+            assert!(!home.contains("第二篇"));
+            assert!(blog.contains("第二篇的正文"));
+            assert_eq!(blog.contains("__LEPTOS"), !no_hydrate);
+
+            // Unlisted 的文章不出现在 sitemap 和 feed 里
+            let sitemap = fs::read_to_string(out.join(SITEMAP_FILE)).unwrap();
+            assert!(sitemap.contains("<loc>https://example.com/blog/1</loc>"));
+            assert!(!sitemap.contains("/blog/2"));
+            let feed = fs::read_to_string(out.join(FEED_FILE)).unwrap();
+            assert!(feed.contains("<title>第一篇</title>"));
+            assert!(!feed.contains("第二篇"));
+        }
+
+        // 没有配置 site_url 时不生成
+        let f = Fixture::new().await;
+        let out = temp_dir("out");
+        export(f.app(), &f.es_client, &f.options, &f.conf, &out, true)
+            .await
+            .unwrap();
+        assert!(out.join("index.html").is_file());
+        assert!(!out.join(SITEMAP_FILE).exists());
+        assert!(!out.join(FEED_FILE).exists());
+    }
+}
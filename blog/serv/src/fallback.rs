@@ -1,3 +1,4 @@
+use crate::csp::Csp;
 use axum::response::Response as AxumResponse;
 use axum::{
     body::{boxed, Body, BoxBody},
@@ -15,6 +16,7 @@ use ui::errors::TodoAppError;
 pub async fn file_and_error_handler(
     uri: Uri,
     Extension(options): Extension<Arc<LeptosOptions>>,
+    Extension(csp): Extension<Arc<Csp>>,
     req: Request<Body>,
 ) -> AxumResponse {
     let options = &*options;
@@ -26,10 +28,10 @@ pub async fn file_and_error_handler(
     } else {
         let mut errors = Errors::default();
         errors.insert_with_default_key(TodoAppError::NotFound);
-        let handler = leptos_axum::render_app_to_stream(
-            options.to_owned(),
-            move |cx| view! {cx, <ErrorTemplate outside_errors=errors.clone()/>},
-        );
+        let handler = leptos_axum::render_app_to_stream(options.to_owned(), move |cx| {
+            csp.provide(cx);
+            view! {cx, <ErrorTemplate outside_errors=errors.clone()/>}
+        });
         handler(req).await.into_response()
     }
 }
@@ -1,7 +1,10 @@
+mod csp;
+mod export;
 mod fallback;
 mod serv;
+mod telemetry;
 
 #[tokio::main]
-async fn main() {
-    serv::serv().await;
+async fn main() -> anyhow::Result<()> {
+    serv::serv().await
 }
@@ -1,24 +1,40 @@
+use crate::csp::Csp;
+use crate::export;
 use crate::fallback::file_and_error_handler;
+use crate::telemetry::{self, LogFormat};
+use anyhow::{bail, Context};
 use axum::extract::{Path, RawQuery};
 use axum::response::IntoResponse;
 use axum::{
     body::Body as AxumBody,
     extract::Extension,
-    http::{header::HeaderMap, Request},
+    http::{
+        header::{HeaderMap, ACCEPT, CACHE_CONTROL, CONTENT_SECURITY_POLICY, VARY},
+        HeaderValue, Request, Response,
+    },
+    middleware,
     routing::{any, get},
     Router,
 };
-use biz::github_hook;
-use clap::Parser;
+use biz::github_hook::{self, HookQueue, HOOK_LOG_PATH, HOOK_PATH};
+use biz::github_issues;
+use biz::snapshot::{self, SnapshotStore};
+use biz::syncer::{self, Syncer};
+use biz::{health, metrics};
+use clap::{Parser, Subcommand};
 use elasticsearch::Elasticsearch;
 use leptos::*;
 use leptos_axum::{generate_route_list, handle_server_fns_with_context, LeptosRoutes};
+use leptos_router::RouteListing;
 use std::fs;
+use std::path::{Path as FsPath, PathBuf};
 use std::sync::Arc;
+use time::{Duration, OffsetDateTime};
 use tower::ServiceBuilder;
 use tower_http::compression::CompressionLayer;
-use tower_http::trace::TraceLayer;
-use tracing::{info, Level};
+use tower_http::services::ServeDir;
+use tower_http::set_header::SetResponseHeaderLayer;
+use tracing::{info, warn};
 use ui::home::BlogApp;
 
 #[derive(Parser, Debug)]
@@ -27,47 +43,269 @@ struct Args {
     /// Number of times to greet
     #[arg(short, long, default_value = "./config.toml")]
     config: String,
+    /// 日志级别，也可以是 biz=debug,info 这样的过滤规则；设置了 RUST_LOG 时以它为准
     #[arg(short, long, default_value = "info")]
     log: String,
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-pub async fn serv() {
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// 启动服务，不带子命令时的默认行为
+    Serve {
+        /// 跳过启动时的同步，直接使用 ES 里已有的数据
+        #[arg(long)]
+        skip_sync: bool,
+    },
+    /// 从 GitHub 同步文章，默认只同步上次同步之后有更新的
+    Sync {
+        /// 同步全部文章
+        #[arg(long, conflicts_with = "since")]
+        full: bool,
+        /// 只同步这个时间之后有更新的文章，如 2023-08-01 或 2023-08-01T08:00:00+08:00
+        #[arg(long, value_parser = parse_since)]
+        since: Option<OffsetDateTime>,
+    },
+    /// 按当前的 mapping 建新的文章和评论索引并全量同步，成功后再把 blog 别名切过去
+    Reindex,
+    /// 检查配置、ES 连接、GitHub token 和索引的 mapping，有问题时以非零状态退出
+    Check,
+    /// 生成草稿预览链接
+    Preview {
+        /// issue 编号
+        number: u64,
+        /// 链接有效期，单位小时
+        #[arg(long, default_value_t = 24)]
+        hours: i64,
+    },
+    /// 把所有页面渲染成静态文件
+    Export {
+        /// 输出目录
+        #[arg(short, long, default_value = "./dist")]
+        out: PathBuf,
+        /// 去掉 hydration 脚本，只输出纯 HTML
+        #[arg(long)]
+        no_hydrate: bool,
+    },
+}
+
+fn parse_since(s: &str) -> Result<OffsetDateTime, String> {
+    biz::schedule::parse_time(s).ok_or_else(|| format!("无法识别的时间：{s}"))
+}
+
+pub async fn serv() -> anyhow::Result<()> {
     let args = Args::parse();
-    // a builder for `FmtSubscriber`.
-    let subscriber = tracing_subscriber::FmtSubscriber::builder()
-        // all spans/events with a level higher than TRACE (e.g, debug, info, warn, etc.)
-        // will be written to stdout.
-        .with_max_level(args.log.parse::<Level>().unwrap_or(Level::INFO))
-        // completes the builder.
-        .finish();
-
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    // 先读配置，日志要用配置里的密钥做脱敏
+    let contents = fs::read_to_string(&args.config)
+        .with_context(|| format!("无法读取配置文件 {}", args.config))?;
+    let serv_conf: Arc<biz::Config> =
+        Arc::new(toml::from_str(contents.as_str()).context("配置文件格式不正确")?);
+    telemetry::init(args.log_format, &args.log, &serv_conf).context("初始化日志失败")?;
     // get pwd
-    let pwd = std::env::current_dir().unwrap();
+    let pwd = std::env::current_dir()?;
     info!("Starting up {}, {:?}", &args.config, pwd);
-    let contents =
-        fs::read_to_string(&args.config).expect("Should have been able to read the file");
-    let serv_conf: biz::Config = toml::from_str(contents.as_str()).unwrap();
+    let es_client = Arc::new(biz::es::init(&serv_conf.es_url).context("初始化ES失败")?);
 
-    let es_client = Arc::new(biz::es::init(&serv_conf.es_url).expect("初始化ES失败"));
+    let result = run(args.command, es_client, serv_conf).await;
+    telemetry::shutdown().await;
+    result
+}
 
-    biz::serv(&es_client, &serv_conf)
-        .await
-        .expect("同步文章数据失败");
+async fn run(
+    command: Option<Command>,
+    es_client: Arc<Elasticsearch>,
+    serv_conf: Arc<biz::Config>,
+) -> anyhow::Result<()> {
+    match command.unwrap_or(Command::Serve { skip_sync: false }) {
+        Command::Serve { skip_sync } => serve(es_client, serv_conf, skip_sync).await,
+        Command::Sync { full, since } => {
+            let since = match since {
+                Some(t) => Some(t),
+                None if full => None,
+                None => github_issues::last_updated_at(&es_client).await?,
+            };
+            let report = github_issues::sync_issues(&serv_conf, &es_client, since).await?;
+            println!("{report}");
+            if !report.failed.is_empty() {
+                bail!("{} 篇文章同步失败", report.failed.len());
+            }
+            Ok(())
+        }
+        Command::Reindex => {
+            let report = github_issues::reindex(&serv_conf, &es_client).await?;
+            println!("{report}");
+            if !report.failed.is_empty() {
+                bail!("{} 篇文章同步失败", report.failed.len());
+            }
+            Ok(())
+        }
+        Command::Check => {
+            let items = biz::check::run(&serv_conf, &es_client).await;
+            for item in &items {
+                println!("{item}");
+            }
+            let failed = items.iter().filter(|i| !i.ok()).count();
+            if failed > 0 {
+                bail!("{failed} 项检查没有通过");
+            }
+            Ok(())
+        }
+        Command::Preview { number, hours } => {
+            let expires = OffsetDateTime::now_utc() + Duration::hours(hours);
+            let token = biz::protect::preview_token(&serv_conf.secret_key(), number, expires);
+            println!("/preview/{number}?token={token}");
+            Ok(())
+        }
+        Command::Export { out, no_hydrate } => {
+            // Setting this to None means we'll be using cargo-leptos and its env vars
+            let conf = get_configuration(None).await?;
+            let leptos_options = conf.leptos_options;
+            let routes = generate_route_list(|cx| view! { cx, <BlogApp/> }).await;
+            // 导出时不会收到 webhook，队列只是为了构造路由
+            let syncer = Arc::new(Syncer::new());
+            let hook_queue = HookQueue::start(
+                syncer.clone(),
+                serv_conf.clone(),
+                es_client.clone(),
+                std::time::Duration::ZERO,
+            );
+            let app = app(
+                leptos_options.clone(),
+                routes,
+                es_client.clone(),
+                serv_conf.clone(),
+                syncer,
+                hook_queue,
+                None,
+            )?;
+            let pages = export::export(
+                app,
+                &es_client,
+                &leptos_options,
+                &serv_conf,
+                &out,
+                no_hydrate,
+            )
+            .await
+            .context("导出静态页面失败")?;
+            info!("导出了 {} 个页面到 {:?}", pages, out);
+            Ok(())
+        }
+    }
+}
+
+async fn serve(
+    es_client: Arc<Elasticsearch>,
+    serv_conf: Arc<biz::Config>,
+    skip_sync: bool,
+) -> anyhow::Result<()> {
+    health::spawn_watchdog();
+    // 不等同步完成就开始服务，同步失败时用 ES 里已有的数据，ES 也不可用时用快照
+    let syncer = Arc::new(Syncer::new());
+    syncer::spawn_periodic(
+        syncer.clone(),
+        serv_conf.clone(),
+        es_client.clone(),
+        !skip_sync,
+    );
+    let snapshot = (!serv_conf.snapshot_path.is_empty()).then(|| {
+        let store = Arc::new(SnapshotStore::open(&serv_conf.snapshot_path));
+        snapshot::spawn_refresh(store.clone(), es_client.clone());
+        store
+    });
+    let hook_queue = HookQueue::start(
+        syncer.clone(),
+        serv_conf.clone(),
+        es_client.clone(),
+        std::time::Duration::from_secs(serv_conf.webhook_debounce),
+    );
 
     // Setting this to None means we'll be using cargo-leptos and its env vars
-    let conf = get_configuration(None).await.unwrap();
-    let leptos_options = conf.leptos_options.clone();
+    let conf = get_configuration(None).await?;
+    let leptos_options = conf.leptos_options;
     let addr = leptos_options.site_addr;
     let routes = generate_route_list(|cx| view! { cx, <BlogApp/> }).await;
+    let metrics_addr = serv_conf.metrics_addr.clone();
+    if serv_conf.webhook_secret.is_empty() {
+        warn!("webhook_secret 未配置，不接收 GitHub webhook");
+    }
+    let app = app(
+        leptos_options,
+        routes,
+        es_client,
+        serv_conf.clone(),
+        syncer,
+        hook_queue.clone(),
+        snapshot,
+    )?;
+    if !metrics_addr.is_empty() {
+        let addr: std::net::SocketAddr = metrics_addr
+            .parse()
+            .with_context(|| format!("metrics_addr 不合法：{metrics_addr}"))?;
+        let metrics_app = Router::new()
+            .route("/metrics", get(metrics::metrics))
+            .route(HOOK_LOG_PATH, get(github_hook::hook_log))
+            .layer(Extension(hook_queue))
+            .layer(Extension(serv_conf));
+        let server = axum::Server::try_bind(&addr)?.serve(metrics_app.into_make_service());
+        info!("metrics listening on http://{}", addr);
+        tokio::spawn(server);
+    }
 
+    // run our app with hyper
+    // `axum::Server` is a re-export of `hyper::Server`
+    log!("listening on http://{}", &addr);
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await?;
+    Ok(())
+}
+
+pub fn app(
+    leptos_options: LeptosOptions,
+    routes: Vec<RouteListing>,
+    es_client: Arc<Elasticsearch>,
+    serv_conf: Arc<biz::Config>,
+    syncer: Arc<Syncer>,
+    hook_queue: Arc<HookQueue>,
+    snapshot: Option<Arc<SnapshotStore>>,
+) -> anyhow::Result<Router> {
+    let csp = Arc::new(Csp::new(serv_conf.content_security_policy.as_deref())?);
+
+    // 镜像的图片按内容 hash 命名，可以永久缓存；同一地址可能返回 webp，需要 Vary: Accept。
+    // 404 不能永久缓存，图片可能是稍后才镜像的
+    let media_dir = PathBuf::from(&serv_conf.media_dir);
+    let media_service = ServiceBuilder::new()
+        .layer(SetResponseHeaderLayer::overriding(
+            CACHE_CONTROL,
+            |res: &Response<_>| {
+                res.status()
+                    .is_success()
+                    .then(|| HeaderValue::from_static("public, max-age=31536000, immutable"))
+            },
+        ))
+        .layer(SetResponseHeaderLayer::overriding(
+            VARY,
+            HeaderValue::from_static("Accept"),
+        ))
+        .map_request(move |req| negotiate_media(&media_dir, req))
+        .service(ServeDir::new(&serv_conf.media_dir));
+
+    // 单独配置了端口时主端口上不提供 /metrics；webhook 的处理记录只在 metrics_addr 上提供
+    let public_metrics = serv_conf.metrics_addr.is_empty();
     let leptos_es_client = es_client.clone();
+    let leptos_conf = serv_conf.clone();
+    let leptos_snapshot = snapshot.clone();
+    let leptos_csp = csp.clone();
     // build our application with a route
-    let app = Router::new()
+    let router = Router::new()
         .layer(CompressionLayer::new())
-        .route("/liveness", get(|| async { "I'm alive!" }))
-        .route("/readiness", get(|| async { "I'm ready!" }))
-        .route("/hook/github", any(github_hook::github_hook))
+        .route("/liveness", get(health::liveness))
+        .route("/readiness", get(health::readiness))
+        .nest_service(biz::media::MEDIA_PATH, media_service)
         .route(
             "/api/*fn_name",
             get(server_fn_handler).post(server_fn_handler),
@@ -77,31 +315,69 @@ pub async fn serv() {
             routes,
             move |cx| {
                 provide_context(cx, leptos_es_client.clone());
+                provide_context(cx, leptos_conf.clone());
+                if let Some(s) = &leptos_snapshot {
+                    provide_context(cx, s.clone());
+                }
             },
-            |cx| view! { cx, <BlogApp/> },
-        )
+            move |cx| {
+                leptos_csp.provide(cx);
+                view! { cx, <BlogApp/> }
+            },
+        );
+    let router = if public_metrics {
+        router.route("/metrics", get(metrics::metrics))
+    } else {
+        router
+    };
+    // 没有 secret 就没法校验请求来自 GitHub，不提供 webhook
+    let router = if serv_conf.webhook_secret.is_empty() {
+        router
+    } else {
+        router.route(HOOK_PATH, any(github_hook::github_hook))
+    };
+    let router = router
+        .route_layer(middleware::from_fn(metrics::track))
         .fallback(file_and_error_handler)
         .with_state(leptos_options.clone())
         .layer(Extension(Arc::new(leptos_options)))
-        .layer(Extension(Arc::new(serv_conf)))
+        .layer(Extension(serv_conf))
+        .layer(Extension(syncer))
+        .layer(Extension(hook_queue))
+        .layer(Extension(snapshot))
         .layer(Extension(es_client))
+        .layer(Extension(csp.clone()))
         .layer(
             ServiceBuilder::new()
-                .layer(TraceLayer::new_for_http())
+                .layer(SetResponseHeaderLayer::if_not_present(
+                    CONTENT_SECURITY_POLICY,
+                    csp.fallback(),
+                ))
                 .layer(CompressionLayer::new()),
         );
+    Ok(telemetry::request_layers(router))
+}
 
-    // run our app with hyper
-    // `axum::Server` is a re-export of `hyper::Server`
-    log!("listening on http://{}", &addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+// 浏览器支持 webp 时改写请求路径，读取同名的 .webp 文件
+fn negotiate_media(dir: &FsPath, mut req: Request<AxumBody>) -> Request<AxumBody> {
+    let accept = req
+        .headers()
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let file = req.uri().path().trim_start_matches('/');
+    if let Some(webp) = biz::media::negotiate(dir, file, accept) {
+        if let Ok(uri) = format!("/{webp}").parse() {
+            *req.uri_mut() = uri;
+        }
+    }
+    req
 }
 
 async fn server_fn_handler(
     Extension(es_client): Extension<Arc<Elasticsearch>>,
+    Extension(conf): Extension<Arc<biz::Config>>,
+    Extension(snapshot): Extension<Option<Arc<SnapshotStore>>>,
     path: Path<String>,
     headers: HeaderMap,
     raw_query: RawQuery,
@@ -113,8 +389,244 @@ async fn server_fn_handler(
         raw_query,
         move |cx| {
             provide_context(cx, es_client.clone());
+            provide_context(cx, conf.clone());
+            if let Some(s) = &snapshot {
+                provide_context(cx, s.clone());
+            }
         },
         request,
     )
     .await
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use axum::body::{Body, HttpBody};
+    use axum::http::StatusCode;
+    use biz::blog::{Post, PostLabel, UNLISTED_LABEL};
+    use biz::testing::temp_dir;
+    use tower::ServiceExt;
+
+    fn post(number: u64, title: &str, labels: &[&str]) -> Post {
+        let mut post = biz::testing::post(number, None);
+        post.title = title.to_string();
+        post.labels = labels
+            .iter()
+            .map(|l| PostLabel {
+                name: l.to_string(),
+                description: String::new(),
+            })
+            .collect();
+        post.created_at += time::Duration::days(number as i64);
+        post.updated_at = post.created_at;
+        post.body_html = format!("<p>{title}的正文</p>");
+        post.rendered_html = post.body_html.clone();
+        post
+    }
+
+    // 假 ES：blog 索引有两篇文章，第二篇是 Unlisted 的
+    async fn fake_es() -> std::net::SocketAddr {
+        biz::testing::fake_es_addr(Some(vec![
+            post(1, "第一篇", &[]),
+            post(2, "第二篇", &[UNLISTED_LABEL]),
+        ]))
+        .await
+    }
+
+    /// 用假 ES 和临时目录搭起来的完整应用
+    pub(crate) struct Fixture {
+        pub(crate) options: LeptosOptions,
+        pub(crate) routes: Vec<RouteListing>,
+        pub(crate) es_client: Arc<Elasticsearch>,
+        pub(crate) conf: Arc<biz::Config>,
+    }
+
+    impl Fixture {
+        pub(crate) async fn new() -> Self {
+            Self::with_conf("").await
+        }
+
+        // extra 是追加到配置文件末尾的内容
+        pub(crate) async fn with_conf(extra: &str) -> Self {
+            let addr = fake_es().await;
+            let site = temp_dir("site");
+            fs::create_dir_all(site.join("pkg")).unwrap();
+            fs::write(site.join("pkg/hj.css"), "body {}").unwrap();
+            fs::write(site.join("pkg/hj.js"), "").unwrap();
+            fs::write(site.join("pkg/hj_bg.wasm"), "").unwrap();
+            fs::write(site.join("favicon.ico"), "").unwrap();
+            let media = temp_dir("media");
+            fs::write(media.join("a.png"), "").unwrap();
+
+            let conf: Arc<biz::Config> = Arc::new(
+                toml::from_str(&format!(
+                    "github_token = \"\"\ngithub_repo = \"\"\ngithub_owner = \"\"\n\
+                 es_url = \"http://{addr}\"\nmedia_dir = \"{}\"\n{extra}",
+                    media.display()
+                ))
+                .unwrap(),
+            );
+            let es_client = Arc::new(biz::es::init(&conf.es_url).unwrap());
+            let options = LeptosOptions::builder()
+                .output_name("hj")
+                .site_root(site.display().to_string())
+                .build();
+            let routes = generate_route_list(|cx| view! { cx, <BlogApp/> }).await;
+            Fixture {
+                options,
+                routes,
+                es_client,
+                conf,
+            }
+        }
+
+        pub(crate) fn app(&self) -> Router {
+            let syncer = Arc::new(Syncer::new());
+            let hook_queue = HookQueue::start(
+                syncer.clone(),
+                self.conf.clone(),
+                self.es_client.clone(),
+                std::time::Duration::ZERO,
+            );
+            app(
+                self.options.clone(),
+                self.routes.clone(),
+                self.es_client.clone(),
+                self.conf.clone(),
+                syncer,
+                hook_queue,
+                None,
+            )
+            .unwrap()
+        }
+    }
+
+    // 页面的 CSP 带本次渲染的 nonce，和页面里内联脚本的 nonce 一致；其他响应不允许内联脚本
+    #[tokio::test]
+    async fn test_csp_nonce() {
+        let app = Fixture::new().await.app();
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let csp = res.headers()[CONTENT_SECURITY_POLICY]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let nonce = csp
+            .split("'nonce-")
+            .nth(1)
+            .and_then(|s| s.split('\'').next())
+            .unwrap();
+        assert!(!nonce.is_empty());
+        assert!(!csp.contains("script-src 'self' 'unsafe-inline'"));
+        let html = read_body(res).await;
+        assert!(html.contains(&format!(r#"<script type="module" nonce="{nonce}">"#)));
+        assert!(!html.contains("<script>"), "内联脚本都要带 nonce");
+
+        let req = Request::builder()
+            .uri("/pkg/hj.css")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert!(!res.headers()[CONTENT_SECURITY_POLICY]
+            .to_str()
+            .unwrap()
+            .contains("nonce"));
+    }
+
+    async fn read_body(res: axum::response::Response) -> String {
+        let mut body = res.into_body();
+        let mut html = Vec::new();
+        while let Some(chunk) = body.data().await {
+            html.extend_from_slice(&chunk.unwrap());
+        }
+        String::from_utf8(html).unwrap()
+    }
+
+    // 文章不存在、ES 不可用又没有快照时页面显示错误，不能 panic
+    #[tokio::test]
+    async fn test_error_pages() {
+        let mut f = Fixture::new().await;
+        let get = |app: Router, uri: &'static str| async move {
+            let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            read_body(app.oneshot(req).await.unwrap()).await
+        };
+        let html = get(f.app(), "/blog/999").await;
+        assert!(html.contains("没有找到"), "{html}");
+
+        f.es_client = Arc::new(biz::es::init("http://127.0.0.1:9").unwrap());
+        for uri in ["/", "/blog/1", "/search?query=rust"] {
+            let html = get(f.app(), uri).await;
+            assert!(html.contains("alert-danger"), "{uri}: {html}");
+        }
+    }
+
+    // 没有配置 webhook_secret 时不提供 webhook，配置了以后必须带正确的签名
+    #[tokio::test]
+    async fn test_webhook_route() {
+        let post = || {
+            Request::builder()
+                .method("POST")
+                .uri(biz::github_hook::HOOK_PATH)
+                .header("x-github-event", "label")
+                .header("x-github-delivery", "d1")
+                .header("x-hub-signature", "sha1=x")
+                .body(Body::from("{}"))
+                .unwrap()
+        };
+        let app = Fixture::new().await.app();
+        let res = app.oneshot(post()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let app = Fixture::with_conf("webhook_secret = \"s3cret\"")
+            .await
+            .app();
+        let res = app.clone().oneshot(post()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        // 处理记录只在 metrics_addr 上提供，没配置时主端口上也没有
+        let req = Request::builder()
+            .uri(biz::github_hook::HOOK_LOG_PATH)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_media_cache() {
+        let app = Fixture::new().await.app();
+        for (uri, status, immutable) in [
+            ("/media/a.png", StatusCode::OK, true),
+            ("/media/missing.png", StatusCode::NOT_FOUND, false),
+        ] {
+            let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), status);
+            assert_eq!(
+                res.headers()
+                    .get(CACHE_CONTROL)
+                    .is_some_and(|v| v.to_str().unwrap().contains("immutable")),
+                immutable,
+                "{uri}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_media_index() {
+        let f = Fixture::new().await;
+        let media = PathBuf::from(&f.conf.media_dir);
+        fs::write(media.join(".index.json"), "{}").unwrap();
+        // 同步时打开 Mirror 会把旧索引挪出 media_dir
+        let index_file = temp_dir("index").join("media_index.json");
+        biz::media::Mirror::new(&media, Some(index_file.clone())).unwrap();
+        assert!(index_file.is_file());
+
+        let req = Request::builder()
+            .uri("/media/.index.json")
+            .body(Body::empty())
+            .unwrap();
+        let res = f.app().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
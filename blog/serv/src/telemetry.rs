@@ -0,0 +1,388 @@
+use axum::http::Request;
+use axum::Router;
+use biz::redact::{self, Redactor};
+use clap::ValueEnum;
+use std::io::{self, Write};
+use std::sync::Arc;
+use tower::ServiceBuilder;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::TraceLayer;
+use tracing::{info_span, Span};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// 一行一个 JSON 对象，方便日志系统解析
+    Json,
+}
+
+/// 安装全局的 subscriber。level 可以是 info，也可以是 biz=debug,info 这样的过滤规则，
+/// 设置了 RUST_LOG 时以 RUST_LOG 为准。日志和导出的 trace 都经过 Redactor
+pub fn init(format: LogFormat, level: &str, conf: &biz::Config) -> anyhow::Result<()> {
+    let filter = EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(level))?;
+    let redactor = Arc::new(Redactor::new(conf));
+    let writer = RedactingMakeWriter {
+        redactor: redactor.clone(),
+        make: io::stdout,
+    };
+    let fmt = tracing_subscriber::fmt::layer().with_writer(writer);
+    let fmt: Box<dyn Layer<Registry> + Send + Sync> = match format {
+        LogFormat::Text => Box::new(fmt),
+        LogFormat::Json => Box::new(fmt.json().flatten_event(true).with_span_list(false)),
+    };
+    let registry = tracing_subscriber::registry().with(fmt);
+    #[cfg(feature = "otlp")]
+    let registry = registry.with(otlp::layer(&conf.otlp_endpoint, redactor)?);
+    #[cfg(not(feature = "otlp"))]
+    if !conf.otlp_endpoint.is_empty() {
+        eprintln!("配置了 otlp_endpoint，但编译时没有启用 otlp feature，不会导出 trace");
+    }
+    registry.with(filter).try_init()?;
+    Ok(())
+}
+
+/// 退出前把还没导出的 span 发出去
+pub async fn shutdown() {
+    #[cfg(feature = "otlp")]
+    let _ = tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await;
+}
+
+/// 没有 X-Request-Id 的请求生成一个，记到请求的 span 里，并在响应里原样返回
+pub fn request_layers(router: Router) -> Router {
+    router.layer(
+        ServiceBuilder::new()
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+            .layer(TraceLayer::new_for_http().make_span_with(make_span))
+            .layer(PropagateRequestIdLayer::x_request_id()),
+    )
+}
+
+/// 每个请求一个 span，带上 request id；URI 里的 token 之类的参数不记录
+pub fn make_span<B>(req: &Request<B>) -> Span {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    info_span!(
+        "request",
+        method = %req.method(),
+        uri = %redact::query(&req.uri().to_string()),
+        request_id,
+    )
+}
+
+#[derive(Clone)]
+struct RedactingMakeWriter<F> {
+    redactor: Arc<Redactor>,
+    make: F,
+}
+
+impl<'a, F, W> MakeWriter<'a> for RedactingMakeWriter<F>
+where
+    F: Fn() -> W + 'static,
+    W: Write,
+{
+    type Writer = RedactingWriter<W>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter {
+            redactor: self.redactor.clone(),
+            inner: (self.make)(),
+        }
+    }
+}
+
+/// fmt 每条日志只调用一次 write，所以可以按次替换
+struct RedactingWriter<W> {
+    redactor: Arc<Redactor>,
+    inner: W,
+}
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let s = String::from_utf8_lossy(buf);
+        self.inner.write_all(self.redactor.redact(&s).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use biz::redact::Redactor;
+    use opentelemetry::sdk::export::trace::SpanData;
+    use opentelemetry::sdk::trace::{
+        self, BatchSpanProcessor, EvictedQueue, Span, SpanLimits, SpanProcessor, TracerProvider,
+    };
+    use opentelemetry::sdk::Resource;
+    use opentelemetry::trace::{Status, TraceResult, TracerProvider as _};
+    use opentelemetry::{Context, KeyValue, Value};
+    use opentelemetry_otlp::{SpanExporterBuilder, WithExportConfig};
+    use std::borrow::Cow;
+    use std::fmt;
+    use std::sync::Arc;
+    use tracing_subscriber::registry::LookupSpan;
+
+    /// endpoint 为空时返回 None，不导出
+    pub fn layer<S>(
+        endpoint: &str,
+        redactor: Arc<Redactor>,
+    ) -> anyhow::Result<Option<tracing_opentelemetry::OpenTelemetryLayer<S, trace::Tracer>>>
+    where
+        S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    {
+        if endpoint.is_empty() {
+            return Ok(None);
+        }
+        let exporter = SpanExporterBuilder::from(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint),
+        )
+        .build_span_exporter()?;
+        let provider = TracerProvider::builder()
+            .with_span_processor(RedactingProcessor {
+                redactor,
+                inner: BatchSpanProcessor::builder(exporter, opentelemetry::runtime::Tokio).build(),
+            })
+            .with_config(
+                trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    env!("CARGO_PKG_NAME"),
+                )])),
+            )
+            .build();
+        let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+        let _ = opentelemetry::global::set_tracer_provider(provider);
+        Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+    }
+
+    /// span 结束、交给 BatchSpanProcessor 导出之前替换掉属性、事件和错误信息里的密钥
+    struct RedactingProcessor<P> {
+        redactor: Arc<Redactor>,
+        inner: P,
+    }
+
+    impl<P> RedactingProcessor<P> {
+        fn redact(&self, s: Cow<'static, str>) -> Cow<'static, str> {
+            let redacted = match self.redactor.redact(&s) {
+                Cow::Owned(r) => Some(r),
+                Cow::Borrowed(_) => None,
+            };
+            redacted.map_or(s, Cow::Owned)
+        }
+
+        fn redact_value(&self, value: &Value) -> Option<Value> {
+            let Value::String(s) = value else {
+                return None;
+            };
+            match self.redactor.redact(s.as_str()) {
+                Cow::Owned(r) => Some(Value::String(r.into())),
+                Cow::Borrowed(_) => None,
+            }
+        }
+
+        fn redact_span(&self, mut span: SpanData) -> SpanData {
+            let changed: Vec<KeyValue> = span
+                .attributes
+                .iter()
+                .filter_map(|(k, v)| Some(KeyValue::new(k.clone(), self.redact_value(v)?)))
+                .collect();
+            for kv in changed {
+                span.attributes.insert(kv);
+            }
+            // tracing 的事件在这里，message 是事件名，其他字段是属性
+            let limit = SpanLimits::default().max_events_per_span;
+            let events = std::mem::replace(&mut span.events, EvictedQueue::new(limit));
+            let mut events = events
+                .into_iter()
+                .map(|mut event| {
+                    event.name = self.redact(event.name);
+                    for kv in &mut event.attributes {
+                        if let Some(v) = self.redact_value(&kv.value) {
+                            kv.value = v;
+                        }
+                    }
+                    event
+                })
+                .collect();
+            span.events.append_vec(&mut events);
+            if let Status::Error { description } = span.status {
+                span.status = Status::Error {
+                    description: self.redact(description),
+                };
+            }
+            span
+        }
+    }
+
+    // Redactor 里是密钥，不打印
+    impl<P> fmt::Debug for RedactingProcessor<P> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("RedactingProcessor").finish_non_exhaustive()
+        }
+    }
+
+    impl<P: SpanProcessor> SpanProcessor for RedactingProcessor<P> {
+        fn on_start(&self, span: &mut Span, cx: &Context) {
+            self.inner.on_start(span, cx);
+        }
+
+        fn on_end(&self, span: SpanData) {
+            self.inner.on_end(self.redact_span(span));
+        }
+
+        fn force_flush(&self) -> TraceResult<()> {
+            self.inner.force_flush()
+        }
+
+        fn shutdown(&mut self) -> TraceResult<()> {
+            self.inner.shutdown()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct Buf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_redacting_writer() {
+        let conf = biz::Config {
+            github_token: "ghp_abcdef123456".to_string(),
+            ..Default::default()
+        };
+        let buf = Buf::default();
+        let out = buf.clone();
+        let writer = RedactingMakeWriter {
+            redactor: Arc::new(Redactor::new(&conf)),
+            make: move || out.clone(),
+        };
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_writer(writer)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let req = Request::builder()
+                .uri("/preview/1?token=signed")
+                .header(REQUEST_ID_HEADER, "req-1")
+                .body(())
+                .unwrap();
+            let _span = make_span(&req).entered();
+            tracing::info!("github_token: {}", conf.github_token);
+            tracing::info!(conf = ?conf, "配置");
+        });
+        let logs = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        assert!(!logs.contains("ghp_abcdef123456"), "{logs}");
+        assert!(!logs.contains("signed"), "{logs}");
+        assert!(logs.contains(r#""request_id":"req-1""#), "{logs}");
+        // 每行都还是合法的 JSON
+        for line in logs.lines() {
+            serde_json::from_str::<serde_json::Value>(line).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_id() {
+        use axum::body::{Body, HttpBody};
+        use axum::routing::get;
+        use tower::ServiceExt;
+
+        let app = request_layers(Router::new().route(
+            "/",
+            get(|req: Request<Body>| async move {
+                req.headers()[REQUEST_ID_HEADER]
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            }),
+        ));
+        let req = Request::builder()
+            .uri("/")
+            .header(REQUEST_ID_HEADER, "from-proxy")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.headers()[REQUEST_ID_HEADER], "from-proxy");
+
+        // 没有时生成一个，handler 看到的和响应里的是同一个
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        let id = res.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(id.len(), 36);
+        let body = res.into_body().data().await.unwrap().unwrap();
+        assert_eq!(body, id.as_bytes());
+    }
+
+    // 用一个假的 collector 接收 OTLP/HTTP 请求：cargo test -p serv --features otlp
+    #[cfg(feature = "otlp")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_otlp() {
+        use axum::routing::post;
+        use axum::Router;
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let r = received.clone();
+        let app = Router::new().route(
+            "/v1/traces",
+            post(move |body: axum::body::Bytes| async move {
+                r.lock().unwrap().push(body);
+            }),
+        );
+        let addr = biz::testing::serve(app).await;
+
+        let conf = biz::Config {
+            github_token: "ghp_abcdef123456".to_string(),
+            ..Default::default()
+        };
+        let redactor = Arc::new(Redactor::new(&conf));
+        let layer = otlp::layer(&format!("http://{addr}/v1/traces"), redactor.clone())
+            .unwrap()
+            .unwrap();
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = info_span!("sync", kind = "full", token = %conf.github_token).entered();
+            tracing::info!(token = %conf.github_token, "github_token: {}", conf.github_token);
+            tracing::error!("同步失败 /preview/1?token=signed");
+        });
+        shutdown().await;
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        // http-proto 没有压缩，字符串原样出现在请求体里
+        let body = String::from_utf8_lossy(&received[0]);
+        assert!(body.contains("同步失败"), "{body}");
+        assert!(body.contains(redact::REDACTED), "{body}");
+        assert!(!body.contains("ghp_abcdef123456"), "{body}");
+        assert!(!body.contains("signed"), "{body}");
+        assert!(otlp::layer::<Registry>("", redactor).unwrap().is_none());
+    }
+}
@@ -6,14 +6,199 @@ use time::OffsetDateTime;
 
 #[server(GetSingleBlog, "/api")]
 pub async fn get_single_blog(cx: Scope, id: u64) -> Result<BlogDisplay, ServerFnError> {
-    let es_client = use_context::<std::sync::Arc<Elasticsearch>>(cx).ok_or(
-        ServerFnError::ServerError("Elasticsearch client not found".to_string()),
-    )?;
-    let post = biz::blog::get_one_blog(&es_client, id)
-        .await
-        .map(BlogDisplay::from)
-        .map_err(|e| ServerFnError::ServerError(e.to_string()))?;
-    Ok(post)
+    biz::metrics::server_fn("get_single_blog", async move {
+        let es_client = use_context::<std::sync::Arc<Elasticsearch>>(cx).ok_or(
+            ServerFnError::ServerError("Elasticsearch client not found".to_string()),
+        )?;
+        let rules = use_context::<std::sync::Arc<biz::Config>>(cx)
+            .map(|c| c.staleness.clone())
+            .unwrap_or_else(biz::staleness::default_rules);
+        let now = OffsetDateTime::now_utc();
+        let (post, degraded) = match biz::blog::get_one_blog(&es_client, id, now).await {
+            Ok(post) => (post, None),
+            Err(e) => from_snapshot(cx, e, |s| s.get(id, now))?,
+        };
+        let locked = post.is_protected() && !is_unlocked(cx, &post);
+        // 导航查不到不影响正文
+        let nav = biz::blog::get_post_nav(&es_client, &post, now)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("获取文章导航失败: {}", e);
+                Default::default()
+            });
+        let mut post = BlogDisplay::new(post, &rules);
+        post.nav = nav.into();
+        post.degraded = degraded;
+        if locked {
+            // 没解锁时正文不能出现在 SSR 输出和 hydration 数据里
+            post.body_html = String::new();
+            post.toc = vec![];
+            post.locked = true;
+        }
+        Ok(post)
+    })
+    .await
+}
+
+#[server(GetHistory, "/api")]
+pub async fn get_history(
+    cx: Scope,
+    id: u64,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<HistoryDisplay, ServerFnError> {
+    biz::metrics::server_fn("get_history", async move {
+        let es_client = use_context::<std::sync::Arc<Elasticsearch>>(cx).ok_or(
+            ServerFnError::ServerError("Elasticsearch client not found".to_string()),
+        )?;
+        // 历史版本里有全文，和正文一样要检查发布时间和密码
+        let post = biz::blog::get_one_blog(&es_client, id, OffsetDateTime::now_utc())
+            .await
+            .map_err(|e| ServerFnError::ServerError(e.to_string()))?;
+        if post.is_protected() && !is_unlocked(cx, &post) {
+            return Err(ServerFnError::ServerError(
+                "这篇文章需要密码才能阅读".to_string(),
+            ));
+        }
+        let revisions = biz::revision::list(&es_client, id, HISTORY_SIZE)
+            .await
+            .map_err(|e| ServerFnError::ServerError(e.to_string()))?;
+        // 默认对比最新的一版和它的上一版
+        let to_idx = match to {
+            Some(t) => revisions.iter().position(|r| r.revision == t),
+            None => (!revisions.is_empty()).then_some(0),
+        };
+        let from_idx = match from {
+            Some(f) => revisions.iter().position(|r| r.revision == f),
+            None => to_idx.map(|i| i + 1).filter(|i| *i < revisions.len()),
+        };
+        let diff = match (from_idx, to_idx) {
+            (Some(f), Some(t)) => Some(DiffDisplay {
+                from: revisions[f].revision,
+                to: revisions[t].revision,
+                lines: biz::revision::diff(&revisions[f], &revisions[t])
+                    .into_iter()
+                    .map(DiffLine::from)
+                    .collect(),
+            }),
+            _ => None,
+        };
+        Ok(HistoryDisplay {
+            number: post.number,
+            title: post.title,
+            revisions: revisions.into_iter().map(RevisionItem::from).collect(),
+            diff,
+        })
+    })
+    .await
+}
+
+#[cfg(feature = "ssr")]
+const HISTORY_SIZE: usize = 100;
+
+#[server(GetPreview, "/api")]
+pub async fn get_preview(cx: Scope, id: u64, token: String) -> Result<BlogDisplay, ServerFnError> {
+    biz::metrics::server_fn("get_preview", async move {
+        let conf = use_context::<std::sync::Arc<biz::Config>>(cx)
+            .ok_or(ServerFnError::ServerError("Config not found".to_string()))?;
+        if !biz::protect::verify_preview(&conf.secret_key(), id, &token, OffsetDateTime::now_utc())
+        {
+            return Err(ServerFnError::ServerError(
+                "预览链接无效或已过期".to_string(),
+            ));
+        }
+        // 直接从 GitHub 取，草稿、定时发布、已关闭的文章都能看
+        let post = biz::github_issues::fetch_post(&conf, id)
+            .await
+            .map_err(|e| ServerFnError::ServerError(e.to_string()))?;
+        Ok(BlogDisplay::new(post, &conf.staleness))
+    })
+    .await
+}
+
+#[server(UnlockPost, "/api")]
+pub async fn unlock_post(cx: Scope, id: u64, password: String) -> Result<bool, ServerFnError> {
+    use http::header::SET_COOKIE;
+    use http::HeaderValue;
+
+    biz::metrics::server_fn("unlock_post", async move {
+        let es_client = use_context::<std::sync::Arc<Elasticsearch>>(cx).ok_or(
+            ServerFnError::ServerError("Elasticsearch client not found".to_string()),
+        )?;
+        let conf = use_context::<std::sync::Arc<biz::Config>>(cx)
+            .ok_or(ServerFnError::ServerError("Config not found".to_string()))?;
+        let post = biz::blog::get_one_blog(&es_client, id, OffsetDateTime::now_utc())
+            .await
+            .map_err(|e| ServerFnError::ServerError(e.to_string()))?;
+        let Some(hash) = post.password_hash.as_deref() else {
+            return Ok(false);
+        };
+        if !biz::protect::unlock(&conf, post.number, hash, &password)
+            .await
+            .map_err(|e| ServerFnError::ServerError(e.to_string()))?
+        {
+            return Ok(false);
+        }
+        let cookie = format!(
+            "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+            biz::protect::cookie_name(post.number),
+            biz::protect::unlock_token(&conf.secret_key(), post.number, hash),
+            biz::protect::COOKIE_MAX_AGE,
+        );
+        if let (Some(res), Ok(v)) = (
+            use_context::<leptos_axum::ResponseOptions>(cx),
+            HeaderValue::from_str(&cookie),
+        ) {
+            res.append_header(SET_COOKIE, v);
+        }
+        Ok(true)
+    })
+    .await
+}
+
+// ES 不可用时从快照里取数据，返回数据和页面顶部的提示；文章不存在之类的错误照常返回
+#[cfg(feature = "ssr")]
+fn from_snapshot<T>(
+    cx: Scope,
+    err: anyhow::Error,
+    f: impl FnOnce(&biz::snapshot::Snapshot) -> Option<T>,
+) -> Result<(T, Option<String>), ServerFnError> {
+    let snapshot = use_context::<std::sync::Arc<biz::snapshot::SnapshotStore>>(cx)
+        .filter(|_| biz::snapshot::is_unavailable(&err))
+        .and_then(|s| s.current());
+    let found = snapshot.and_then(|s| f(&s).map(|v| (v, s.saved_at)));
+    biz::metrics::cache_lookup("snapshot", found.is_some());
+    match found {
+        Some((v, saved_at)) => {
+            warn!("ES 不可用，使用快照: {}", err);
+            let saved = from_now(saved_at).unwrap_or(saved_at.to_string());
+            Ok((
+                v,
+                Some(format!(
+                    "服务暂时不可用，当前显示的是 {saved}保存的内容，评论和相关文章暂时无法加载"
+                )),
+            ))
+        }
+        None => Err(ServerFnError::ServerError(err.to_string())),
+    }
+}
+
+// 请求里带着 unlock_post 签发的 cookie，并且签名和当前密码对得上
+#[cfg(feature = "ssr")]
+fn is_unlocked(cx: Scope, post: &biz::blog::Post) -> bool {
+    let (Some(conf), Some(req), Some(hash)) = (
+        use_context::<std::sync::Arc<biz::Config>>(cx),
+        use_context::<leptos_axum::RequestParts>(cx),
+        post.password_hash.as_deref(),
+    ) else {
+        return false;
+    };
+    req.headers
+        .get_all(http::header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .filter_map(|h| biz::protect::cookie(h, &biz::protect::cookie_name(post.number)))
+        .any(|token| biz::protect::is_unlocked(&conf.secret_key(), post.number, hash, token))
 }
 
 #[server(GetBlogs, "/api")]
@@ -21,21 +206,84 @@ pub async fn get_blogs(
     cx: Scope,
     filter: Option<String>,
 ) -> Result<Vec<BlogAbbrDisplay>, ServerFnError> {
-    let es_client = use_context::<std::sync::Arc<Elasticsearch>>(cx).ok_or(
-        ServerFnError::ServerError("Elasticsearch client not found".to_string()),
-    )?;
-    let posts = biz::blog::get_blogs_with_filter(&es_client, filter)
-        .await
-        .map(|ps| {
-            ps.iter()
-                .map(|p| {
-                    let p = p.clone();
-                    BlogAbbrDisplay::from(p)
-                })
-                .collect()
+    biz::metrics::server_fn("get_blogs", async move {
+        let es_client = use_context::<std::sync::Arc<Elasticsearch>>(cx).ok_or(
+            ServerFnError::ServerError("Elasticsearch client not found".to_string()),
+        )?;
+        let now = OffsetDateTime::now_utc();
+        let posts = match biz::blog::get_blogs_with_filter(&es_client, filter.clone(), now).await {
+            Ok(ps) => ps,
+            Err(e) => from_snapshot(cx, e, |s| Some(s.search(filter.as_deref(), now)))?.0,
+        };
+        Ok(posts.into_iter().map(BlogAbbrDisplay::from).collect())
+    })
+    .await
+}
+
+#[server(GetHome, "/api")]
+pub async fn get_home(cx: Scope) -> Result<HomeDisplay, ServerFnError> {
+    biz::metrics::server_fn("get_home", async move {
+        let es_client = use_context::<std::sync::Arc<Elasticsearch>>(cx).ok_or(
+            ServerFnError::ServerError("Elasticsearch client not found".to_string()),
+        )?;
+        let sort = use_context::<std::sync::Arc<biz::Config>>(cx)
+            .map(|c| c.home_sort)
+            .unwrap_or_default();
+        let now = OffsetDateTime::now_utc();
+        let (home, degraded) = match biz::blog::get_home(&es_client, sort, now).await {
+            Ok(home) => (home, None),
+            Err(e) => from_snapshot(cx, e, |s| Some(s.home(sort, now)))?,
+        };
+        let abbr = |ps: Vec<biz::blog::Post>| ps.into_iter().map(BlogAbbrDisplay::from).collect();
+        Ok(HomeDisplay {
+            pinned: abbr(home.pinned),
+            featured: abbr(home.featured),
+            posts: abbr(home.posts),
+            degraded,
         })
-        .map_err(|e| ServerFnError::ServerError(e.to_string()))?;
-    Ok(posts)
+    })
+    .await
+}
+
+#[server(GetComments, "/api")]
+pub async fn get_comments(cx: Scope, id: u64) -> Result<Vec<CommentDisplay>, ServerFnError> {
+    biz::metrics::server_fn("get_comments", async move {
+        let es_client = use_context::<std::sync::Arc<Elasticsearch>>(cx).ok_or(
+            ServerFnError::ServerError("Elasticsearch client not found".to_string()),
+        )?;
+        let post = match biz::blog::get_one_blog(&es_client, id, OffsetDateTime::now_utc()).await {
+            Ok(post) => post,
+            // ES 不可用时正文来自快照，评论不展示
+            Err(e) if biz::snapshot::is_unavailable(&e) => return Ok(vec![]),
+            Err(e) => return Err(ServerFnError::ServerError(e.to_string())),
+        };
+        if post.is_protected() && !is_unlocked(cx, &post) {
+            return Ok(vec![]);
+        }
+        let comments = biz::blog::get_comments(&es_client, id)
+            .await
+            .map(|cs| cs.into_iter().map(CommentDisplay::from).collect())
+            .map_err(|e| ServerFnError::ServerError(e.to_string()))?;
+        Ok(comments)
+    })
+    .await
+}
+
+#[server(GetRelatedPosts, "/api")]
+pub async fn get_related_posts(cx: Scope, id: u64) -> Result<Vec<PostLink>, ServerFnError> {
+    biz::metrics::server_fn("get_related_posts", async move {
+        let es_client = use_context::<std::sync::Arc<Elasticsearch>>(cx).ok_or(
+            ServerFnError::ServerError("Elasticsearch client not found".to_string()),
+        )?;
+        let posts = match biz::blog::related_posts(&es_client, id, OffsetDateTime::now_utc()).await
+        {
+            Ok(ps) => ps.into_iter().map(PostLink::from).collect(),
+            Err(e) if biz::snapshot::is_unavailable(&e) => vec![],
+            Err(e) => return Err(ServerFnError::ServerError(e.to_string())),
+        };
+        Ok(posts)
+    })
+    .await
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -46,6 +294,105 @@ pub struct BlogAbbrDisplay {
     pub created_from_now: String,
     pub updated_at: OffsetDateTime, //String,//"2018-05-23T16:30:12Z",
     pub updated_from_now: String,
+    pub reading: Reading,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryDisplay {
+    pub number: u64,
+    pub title: String,
+    /// 新的在前
+    pub revisions: Vec<RevisionItem>,
+    pub diff: Option<DiffDisplay>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevisionItem {
+    pub revision: i64,
+    pub revised_at: OffsetDateTime,
+    pub revised_from_now: String,
+    pub title: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffDisplay {
+    pub from: i64,
+    pub to: i64,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiffKind {
+    Equal,
+    Insert,
+    Delete,
+    Skip,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffLine {
+    pub kind: DiffKind,
+    pub text: String,
+}
+
+#[cfg(feature = "ssr")]
+impl From<biz::revision::Revision> for RevisionItem {
+    fn from(r: biz::revision::Revision) -> Self {
+        RevisionItem {
+            revision: r.revision,
+            revised_at: r.revised_at,
+            revised_from_now: from_now(r.revised_at).unwrap_or(r.revised_at.to_string()),
+            title: r.title,
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<biz::revision::DiffLine> for DiffLine {
+    fn from(l: biz::revision::DiffLine) -> Self {
+        use biz::revision::DiffKind as K;
+        DiffLine {
+            kind: match l.kind {
+                K::Equal => DiffKind::Equal,
+                K::Insert => DiffKind::Insert,
+                K::Delete => DiffKind::Delete,
+                K::Skip => DiffKind::Skip,
+            },
+            text: l.text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HomeDisplay {
+    pub pinned: Vec<BlogAbbrDisplay>,
+    pub featured: Vec<BlogAbbrDisplay>,
+    pub posts: Vec<BlogAbbrDisplay>,
+    /// ES 不可用、内容来自快照时的提示
+    pub degraded: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Reading {
+    pub words: u64,
+    pub minutes: u64,
+}
+
+impl Reading {
+    /// 约 8 分钟读完 · 3200 字；旧数据没有统计时不显示
+    pub fn label(&self) -> Option<String> {
+        (self.words > 0).then(|| format!("约 {} 分钟读完 · {} 字", self.minutes, self.words))
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<biz::reading::ReadingStats> for Reading {
+    fn from(r: biz::reading::ReadingStats) -> Self {
+        Reading {
+            words: r.words,
+            minutes: r.minutes,
+        }
+    }
 }
 
 #[cfg(feature = "ssr")]
@@ -58,6 +405,7 @@ impl From<biz::blog::Post> for BlogAbbrDisplay {
             created_from_now: from_now(p.created_at).unwrap_or(p.created_at.to_string()),
             updated_at: p.updated_at,
             updated_from_now: from_now(p.updated_at).unwrap_or(p.updated_at.to_string()),
+            reading: p.reading.into(),
         }
     }
 }
@@ -73,14 +421,127 @@ pub struct BlogDisplay {
     pub created_from_now: String,
     pub updated_at: OffsetDateTime, //String,//"2018-05-23T16:30:12Z",
     pub updated_from_now: String,
-    pub outdated_info: String,
+    pub outdated: Option<Outdated>,
+    pub nav: PostNav,
+    pub reading: Reading,
     pub body_html: String, // "## 如
+    pub toc: Vec<TocItem>,
+    pub html_url: String,
+    pub author: Author,
+    pub reactions: Vec<Reaction>,
+    /// Protected 文章还没输入密码，body_html 和 toc 为空
+    pub locked: bool,
+    /// ES 不可用、内容来自快照时的提示
+    pub degraded: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Author {
+    pub login: String,
+    pub avatar_url: String,
+    pub html_url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostLink {
+    pub number: u64,
+    pub title: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Series {
+    pub name: String,
+    pub parts: Vec<PostLink>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PostNav {
+    pub prev: Option<PostLink>,
+    pub next: Option<PostLink>,
+    pub series: Option<Series>,
 }
 
 #[cfg(feature = "ssr")]
-impl From<biz::blog::Post> for BlogDisplay {
-    fn from(p: biz::blog::Post) -> Self {
-        let outdated_info = outdated(&p);
+impl From<biz::blog::PostLink> for PostLink {
+    fn from(l: biz::blog::PostLink) -> Self {
+        PostLink {
+            number: l.number,
+            title: l.title,
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<biz::blog::PostNav> for PostNav {
+    fn from(n: biz::blog::PostNav) -> Self {
+        PostNav {
+            prev: n.prev.map(PostLink::from),
+            next: n.next.map(PostLink::from),
+            series: n.series.map(|s| Series {
+                name: s.name,
+                parts: s.parts.into_iter().map(PostLink::from).collect(),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Severity {
+    Info,
+    Warning,
+    Danger,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Outdated {
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[cfg(feature = "ssr")]
+impl BlogDisplay {
+    pub fn new(p: biz::blog::Post, rules: &[biz::staleness::StalenessRule]) -> Self {
+        use biz::staleness::{self, Staleness};
+        let outdated = match staleness::evaluate(rules, &p, OffsetDateTime::now_utc()) {
+            Staleness::Fresh => None,
+            Staleness::Stale { severity, message } => Some(Outdated {
+                severity: match severity {
+                    staleness::Severity::Fresh | staleness::Severity::Info => Severity::Info,
+                    staleness::Severity::Warning => Severity::Warning,
+                    staleness::Severity::Danger => Severity::Danger,
+                },
+                message,
+            }),
+        };
+        let body_html = p.html();
+        let reactions = p
+            .reactions
+            .summary()
+            .into_iter()
+            .map(|(emoji, count)| Reaction {
+                emoji: emoji.to_string(),
+                count,
+            })
+            .collect();
+        let author = match &p.user {
+            Some(u) => Author {
+                login: u.login.clone(),
+                avatar_url: u.avatar_url.clone(),
+                html_url: u.profile_url(),
+            },
+            // 没有作者信息的旧数据都是站长写的
+            None => Author {
+                login: "HJin".to_string(),
+                avatar_url: String::new(),
+                html_url: "https://github.com/hjin-me".to_string(),
+            },
+        };
         BlogDisplay {
             id: p.id,
             number: p.number,
@@ -98,8 +559,16 @@ impl From<biz::blog::Post> for BlogDisplay {
             created_from_now: from_now(p.created_at).unwrap_or(p.created_at.to_string()),
             updated_at: p.updated_at,
             updated_from_now: from_now(p.updated_at).unwrap_or(p.updated_at.to_string()),
-            outdated_info,
-            body_html: p.body_html,
+            outdated,
+            nav: PostNav::default(),
+            reading: p.reading.into(),
+            body_html,
+            toc: p.toc.into_iter().map(TocItem::from).collect(),
+            html_url: p.html_url,
+            author,
+            reactions,
+            locked: false,
+            degraded: None,
         }
     }
 }
@@ -110,6 +579,50 @@ pub struct PostLabel {
     pub description: String, // "可以被展现的文章"
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TocItem {
+    pub id: String,
+    pub title: String,
+    pub children: Vec<TocItem>,
+}
+
+#[cfg(feature = "ssr")]
+impl From<biz::toc::TocEntry> for TocItem {
+    fn from(e: biz::toc::TocEntry) -> Self {
+        TocItem {
+            id: e.id,
+            title: e.title,
+            children: e.children.into_iter().map(TocItem::from).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommentDisplay {
+    pub id: u64,
+    pub html_url: String,
+    pub author: String,
+    pub avatar_url: String,
+    pub created_at: OffsetDateTime,
+    pub created_from_now: String,
+    pub body_html: String,
+}
+
+#[cfg(feature = "ssr")]
+impl From<biz::blog::Comment> for CommentDisplay {
+    fn from(c: biz::blog::Comment) -> Self {
+        CommentDisplay {
+            id: c.id,
+            html_url: c.html_url,
+            author: c.user.login,
+            avatar_url: c.user.avatar_url,
+            created_at: c.created_at,
+            created_from_now: from_now(c.created_at).unwrap_or(c.created_at.to_string()),
+            body_html: c.rendered_html,
+        }
+    }
+}
+
 // This filter does not have extra arguments
 #[cfg(feature = "ssr")]
 pub fn from_now(s: OffsetDateTime) -> anyhow::Result<String> {
@@ -142,41 +655,6 @@ pub fn from_now(s: OffsetDateTime) -> anyhow::Result<String> {
     ))
 }
 
-#[cfg(feature = "ssr")]
-fn outdated(post: &biz::blog::Post) -> String {
-    use std::ops::Sub;
-    let mut outdated = false;
-    for o in &post.labels {
-        if o.name == "Outdated" {
-            outdated = true
-        }
-    }
-    let mut outdated_info = "latest";
-    if outdated {
-        outdated_info = "outdated"
-    } else {
-        // modify_expired, post_expired
-        if post
-            .updated_at
-            .sub(OffsetDateTime::now_utc())
-            .whole_days()
-            .abs()
-            > 365
-        {
-            outdated_info = "modify_expired"
-        } else if post
-            .created_at
-            .sub(OffsetDateTime::now_utc())
-            .whole_days()
-            .abs()
-            > 500
-        {
-            outdated_info = "post_expired"
-        }
-    }
-    outdated_info.to_string()
-}
-
 #[cfg(all(test, feature = "ssr"))]
 mod test {
     use super::*;
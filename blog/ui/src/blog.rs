@@ -1,4 +1,8 @@
-use crate::api::blog::{get_blogs, get_single_blog, BlogAbbrDisplay, BlogDisplay};
+use crate::api::blog::{
+    get_comments, get_history, get_home, get_preview, get_related_posts, get_single_blog,
+    BlogAbbrDisplay, BlogDisplay, CommentDisplay, DiffKind, HistoryDisplay, Severity, TocItem,
+    UnlockPost,
+};
 use leptos::*;
 use leptos_meta::*;
 use leptos_router::*;
@@ -9,27 +13,43 @@ use time::OffsetDateTime;
 #[component]
 pub fn SingleBlog(cx: Scope) -> impl IntoView {
     let params = use_params_map(cx);
+    let unlock = create_server_action::<UnlockPost>(cx);
     let post = create_resource(
         cx,
         move || {
-            params.with(|p| {
+            let id = params.with(|p| {
                 p.get("id")
                     .cloned()
                     .map(|i| i.parse::<u64>().unwrap_or_default())
                     .unwrap_or_default()
-            })
+            });
+            // 输入密码后重新加载正文
+            (id, unlock.version().get())
         },
-        move |id| get_single_blog(cx, id),
+        move |(id, _)| get_single_blog(cx, id),
     );
     let post_view = move || {
-        post.with(cx, |post| {
-            let post = post.clone().unwrap();
-            let title = post.title.clone();
-            view! {
-                cx,
-                 <Title text=title />
-                 <Blog post=post/>
+        post.with(cx, |post| match post.clone() {
+            Ok(post) if post.locked => {
+                let title = post.title.clone();
+                view! {
+                    cx,
+                     <Title text=title />
+                     <ProtectedBlog post=post unlock=unlock/>
+                }
+                .into_view(cx)
             }
+            Ok(post) => {
+                let title = post.title.clone();
+                view! {
+                    cx,
+                     <Title text=title />
+                     <Blog post=post/>
+                }
+                .into_view(cx)
+            }
+            // 文章不存在、ES 不可用又没有快照时都走这里
+            Err(e) => view! { cx, <p class="alert alert-danger">{e.to_string()}</p> }.into_view(cx),
         })
     };
 
@@ -47,38 +67,300 @@ pub fn SingleBlog(cx: Scope) -> impl IntoView {
 
 #[allow(non_snake_case)]
 #[component]
-pub fn Blog(cx: Scope, #[prop()] post: BlogDisplay) -> impl IntoView {
-    let format = time::format_description::parse("[year]/[month]/[day]").unwrap();
+pub fn BlogHistory(cx: Scope) -> impl IntoView {
+    let params = use_params_map(cx);
+    let query = use_query_map(cx);
+    let history = create_resource(
+        cx,
+        move || {
+            let id = params.with(|p| {
+                p.get("id")
+                    .and_then(|i| i.parse::<u64>().ok())
+                    .unwrap_or_default()
+            });
+            let (from, to) = query.with(|q| {
+                (
+                    q.get("from").and_then(|v| v.parse::<i64>().ok()),
+                    q.get("to").and_then(|v| v.parse::<i64>().ok()),
+                )
+            });
+            (id, from, to)
+        },
+        move |(id, from, to)| get_history(cx, id, from, to),
+    );
+    let history_view = move || {
+        history.with(cx, |history| match history.clone() {
+            Ok(h) => history_view(cx, h),
+            Err(e) => view! { cx, <p class="alert alert-danger">{e.to_string()}</p> }.into_view(cx),
+        })
+    };
+
+    view! {
+        cx,
+        <main class="page-content" aria-label="Content">
+            <div class="wrapper">
+                <Suspense fallback=move || view! { cx, <p>"Loading..."</p> }>
+                    {history_view}
+                </Suspense>
+            </div>
+        </main>
+    }
+}
 
-    let outdated_view = match post.outdated_info.as_str() {
-        "outdated" => view! {
+fn history_view(cx: Scope, h: HistoryDisplay) -> View {
+    let number = h.number;
+    let (from, to) = h.diff.as_ref().map(|d| (d.from, d.to)).unzip();
+    let revisions = h.revisions.clone();
+    let items = h
+        .revisions
+        .into_iter()
+        .enumerate()
+        .map(|(i, r)| {
+            // 每一版都可以和它的上一版对比
+            let compare = revisions.get(i + 1).map(|prev| {
+                view! {
+                    cx,
+                    <a class="revision-compare" href=format!("/blog/{}/history?from={}&to={}", number, prev.revision, r.revision)>
+                        "查看改动"
+                    </a>
+                }
+            });
+            let current = Some(r.revision) == to;
+            let base = Some(r.revision) == from;
+            view! {
+                cx,
+                <li class:revision-current=current class:revision-base=base>
+                    <time dateTime=datetime(r.revised_at).unwrap_or_default()>{r.revised_from_now}</time>
+                    " "<span class="revision-title">{r.title}</span>" "
+                    {compare}
+                </li>
+            }
+        })
+        .collect::<Vec<_>>();
+    let diff = h.diff.map(|d| {
+        view! {
             cx,
-            <div class="alert alert-danger">
-            "警告：本文已被标记为过期存档，文中所描述的信息已发生改变，请不要使用。"
-        </div>
+            <pre class="diff">
+                {d.lines
+                    .into_iter()
+                    .map(|l| {
+                        let (class, sign) = match l.kind {
+                            DiffKind::Insert => ("diff-insert", "+ "),
+                            DiffKind::Delete => ("diff-delete", "- "),
+                            DiffKind::Equal => ("diff-equal", "  "),
+                            DiffKind::Skip => ("diff-skip", "⋯"),
+                        };
+                        view! { cx, <div class=class>{sign}{l.text}</div> }
+                    })
+                    .collect::<Vec<_>>()}
+            </pre>
+        }
+    });
+    let title = format!("{} 的修改历史", h.title);
+
+    view! {
+        cx,
+        <Title text=title.clone() />
+        <article class="post history">
+            <header class="post-header">
+                <h1 class="post-title">{title}</h1>
+                <p class="post-meta"><a href=format!("/blog/{}", number)>"返回文章"</a></p>
+            </header>
+            {diff}
+            <ol class="revision-list">{items}</ol>
+        </article>
+    }
+    .into_view(cx)
+}
+
+#[allow(non_snake_case)]
+#[component]
+pub fn PreviewBlog(cx: Scope) -> impl IntoView {
+    let params = use_params_map(cx);
+    let query = use_query_map(cx);
+    // 预览页不能被搜索引擎收录
+    #[cfg(feature = "ssr")]
+    if let Some(res) = use_context::<leptos_axum::ResponseOptions>(cx) {
+        res.insert_header(
+            http::HeaderName::from_static("x-robots-tag"),
+            http::HeaderValue::from_static("noindex, nofollow"),
+        );
+    }
+    let post = create_resource(
+        cx,
+        move || {
+            let id = params.with(|p| {
+                p.get("id")
+                    .and_then(|i| i.parse::<u64>().ok())
+                    .unwrap_or_default()
+            });
+            let token = query.with(|q| q.get("token").cloned().unwrap_or_default());
+            (id, token)
         },
-        "modify_expired" => view! {
+        move |(id, token)| get_preview(cx, id, token),
+    );
+    let post_view = move || {
+        post.with(cx, |post| match post.clone() {
+            Ok(post) => {
+                let title = format!("[预览] {}", post.title);
+                view! {
+                    cx,
+                     <Title text=title />
+                     <Blog post=post/>
+                }
+                .into_view(cx)
+            }
+            Err(e) => view! { cx, <p class="alert alert-danger">{e.to_string()}</p> }.into_view(cx),
+        })
+    };
+
+    view! {
+        cx,
+        <Meta name="robots" content="noindex, nofollow"/>
+        <main class="page-content" aria-label="Content">
+            <div class="wrapper">
+                <div class="preview-banner" role="status">"预览模式：这篇文章可能还没有发布，页面内容仅供作者检查"</div>
+                <Suspense fallback=move || view! { cx, <p>"Loading posts..."</p> }>
+                    {post_view}
+                </Suspense>
+            </div>
+        </main>
+    }
+}
+
+#[allow(non_snake_case)]
+#[component]
+pub fn ProtectedBlog(
+    cx: Scope,
+    post: BlogDisplay,
+    unlock: Action<UnlockPost, Result<bool, ServerFnError>>,
+) -> impl IntoView {
+    let error = move || match unlock.value().get() {
+        Some(Ok(false)) => Some("密码错误".to_string()),
+        Some(Err(ServerFnError::ServerError(e))) => Some(e),
+        Some(Err(e)) => Some(e.to_string()),
+        _ => None,
+    };
+    view! {
+        cx,
+        <article class="post">
+            <header class="post-header">
+                <h1 class="post-title">{post.title}</h1>
+            </header>
+            <div class="post-content protected">
+                <p>"这篇文章需要密码才能阅读。"</p>
+                <ActionForm action=unlock>
+                    <input type="hidden" name="id" value=post.number/>
+                    <input type="password" name="password" placeholder="密码" autocomplete="off" required/>
+                    <button type="submit">"解锁"</button>
+                </ActionForm>
+                {move || error().map(|e| view! { cx, <p class="alert alert-danger">{e}</p> })}
+            </div>
+        </article>
+    }
+}
+
+#[allow(non_snake_case)]
+#[component]
+pub fn Blog(cx: Scope, #[prop()] post: BlogDisplay) -> impl IntoView {
+    let outdated_view = post.outdated.map(|o| {
+        let class = match o.severity {
+            Severity::Info => "alert alert-info",
+            Severity::Warning => "alert alert-warning",
+            Severity::Danger => "alert alert-danger",
+        };
+        view! {
             cx,
-            <div class="alert alert-warning">
-            "提醒：本文最后更新于 " {post.updated_at.format(&format)}
-            " ，文中所描述的信息可能已发生改变，请谨慎使用。"
-        </div>
-        },
-        "post_expired" => view! {
+            <div class=class>{o.message}</div>
+        }
+    });
+
+    let toc = post.toc;
+    let number = post.number;
+    let series_view = post.nav.series.map(|series| {
+        view! {
             cx,
-            <div class="alert alert-warning">
-            "提醒：本文发布于 " {post.created_at.format(&format)}
-            " ，文中所描述的信息可能已发生改变，请谨慎使用。"
-        </div>
-        },
-        _ => view! {
+            <aside class="series" aria-label="系列文章">
+                <div class="series-title">"系列：" {series.name}</div>
+                <ol class="series-parts">
+                    {series
+                        .parts
+                        .into_iter()
+                        .map(|p| {
+                            if p.number == number {
+                                view! { cx, <li class="series-current" aria-current="page">{p.title}</li> }
+                            } else {
+                                view! { cx, <li><a href=format!("/blog/{}", p.number)>{p.title}</a></li> }
+                            }
+                        })
+                        .collect::<Vec<_>>()}
+                </ol>
+            </aside>
+        }
+    });
+    let prev_view = post.nav.prev.map(|p| {
+        view! {
             cx,
-            <div/>
-        },
-    };
+            <a class="post-nav-prev" href=format!("/blog/{}", p.number) rel="prev">
+                <span class="post-nav-label">"上一篇"</span>
+                <span class="post-nav-title">{p.title}</span>
+            </a>
+        }
+    });
+    let next_view = post.nav.next.map(|p| {
+        view! {
+            cx,
+            <a class="post-nav-next" href=format!("/blog/{}", p.number) rel="next">
+                <span class="post-nav-label">"下一篇"</span>
+                <span class="post-nav-title">{p.title}</span>
+            </a>
+        }
+    });
+    let html_url = post.html_url;
+    let author = post.author;
+    let avatar = (!author.avatar_url.is_empty()).then(|| {
+        view! {
+            cx,
+            <img class="author-avatar u-photo" src=author.avatar_url.clone() alt="" width="20" height="20" itemProp="image"/>
+        }
+    });
+    let reactions = post.reactions;
+    let reactions_view = (!reactions.is_empty()).then(|| {
+        view! {
+            cx,
+            <a class="reactions" href=html_url.clone() target="_blank" rel="noopener" title="在 GitHub 上回应">
+                {reactions
+                    .into_iter()
+                    .map(|r| view! { cx, <span class="reaction">{r.emoji}" "{r.count}</span> })
+                    .collect::<Vec<_>>()}
+            </a>
+        }
+    });
+    let toc_mobile = (!toc.is_empty()).then(|| {
+        view! {
+            cx,
+            <details class="toc toc-mobile">
+                <summary>"目录"</summary>
+                {toc_list(cx, toc.clone())}
+            </details>
+        }
+    });
+    let toc_sidebar = (!toc.is_empty()).then(|| {
+        view! {
+            cx,
+            <nav class="toc toc-sidebar" aria-label="目录">
+                <div class="toc-sticky">
+                    <div class="toc-title">"目录"</div>
+                    {toc_list(cx, toc.clone())}
+                </div>
+            </nav>
+        }
+    });
 
     view! {
         cx,
+        {post.degraded.map(|d| view! { cx, <DegradedBanner message=d/> })}
+        <div class="post-layout">
         <article
                 class="post h-entry"
                 itemScope
@@ -88,51 +370,220 @@ pub fn Blog(cx: Scope, #[prop()] post: BlogDisplay) -> impl IntoView {
                 <h1 class="post-title p-name" itemProp="name headline">{post.title}</h1>
                 <p class="post-meta">
                     "最后更新于 "
-                    <time
-                        class="dt-published"
-                        dateTime=datetime(post.updated_at).unwrap()
-                        itemProp="dateModified"
-                    >
-                        {post.updated_from_now}
-                    </time>
+                    <a class="post-history" href=format!("/blog/{}/history", number) title="查看修改历史">
+                        <time
+                            class="dt-published"
+                            dateTime=datetime(post.updated_at).unwrap()
+                            itemProp="dateModified"
+                        >
+                            {post.updated_from_now}
+                        </time>
+                    </a>
                     " • "
                     <span itemProp="author" itemScope itemType="https://schema.org/Person">
-                    <span class="p-author h-card" itemProp="name">
-                      " HJin "
-                    </span>
+                    <a class="p-author h-card" href=author.html_url itemProp="url" target="_blank" rel="noopener">
+                      {avatar}
+                      <span itemProp="name">{author.login}</span>
+                    </a>
                   </span>
+                  {post.reading.label().map(|r| view! { cx, " • "<span class="reading-time">{r}</span> })}
                 </p>
             </header>
+            {toc_mobile}
+            {series_view}
             <div class="post-content e-content markdown-body" id="write" itemProp="articleBody">
                 {outdated_view}
                 <div inner_html=post.body_html></div>
             </div>
+            {reactions_view}
+            <nav class="post-nav" aria-label="文章导航">
+                {prev_view}
+                {next_view}
+            </nav>
+            <RelatedPosts number=number/>
+            <Comments number=number html_url=html_url/>
         </article>
+        {toc_sidebar}
+        </div>
+    }
+}
+
+#[allow(non_snake_case)]
+#[component]
+pub fn RelatedPosts(cx: Scope, number: u64) -> impl IntoView {
+    let posts = create_resource(cx, move || number, move |id| get_related_posts(cx, id));
+    let posts_view = move || {
+        posts.with(cx, |posts| {
+            let posts = posts.clone().unwrap_or_default();
+            (!posts.is_empty()).then(|| {
+                view! {
+                    cx,
+                    <section class="related" aria-label="相关文章">
+                        <h2 class="related-title">"相关文章"</h2>
+                        <ul class="related-list">
+                            {posts
+                                .into_iter()
+                                .map(|p| view! { cx, <li><a href=format!("/blog/{}", p.number)>{p.title}</a></li> })
+                                .collect::<Vec<_>>()}
+                        </ul>
+                    </section>
+                }
+            })
+        })
+    };
+
+    view! {
+        cx,
+        <Suspense fallback=|| ()>{posts_view}</Suspense>
+    }
+}
+
+#[allow(non_snake_case)]
+#[component]
+pub fn Comments(cx: Scope, number: u64, html_url: String) -> impl IntoView {
+    let comments = create_resource(cx, move || number, move |id| get_comments(cx, id));
+    let comments_view = move || {
+        comments.with(cx, |comments| {
+            // 评论加载失败不影响正文
+            let comments = comments.clone().unwrap_or_default();
+            comments
+                .into_iter()
+                .map(|c| view! { cx, <Comment comment=c/> })
+                .collect::<Vec<_>>()
+        })
+    };
+    let reply = (!html_url.is_empty()).then(|| {
+        view! {
+            cx,
+            <a class="comment-reply" href=html_url target="_blank" rel="noopener">"在 GitHub 上回复"</a>
+        }
+    });
+
+    view! {
+        cx,
+        <section class="comments" id="comments">
+            <h2 class="comments-title">"评论"</h2>
+            <Suspense fallback=move || view! { cx, <p>"Loading..."</p> }>
+                <ol class="comment-list">{comments_view}</ol>
+            </Suspense>
+            {reply}
+        </section>
+    }
+}
+
+#[allow(non_snake_case)]
+#[component]
+pub fn Comment(cx: Scope, comment: CommentDisplay) -> impl IntoView {
+    view! {
+        cx,
+        <li class="comment" id=format!("comment-{}", comment.id)>
+            <div class="comment-meta">
+                <img class="comment-avatar" src=comment.avatar_url alt=comment.author.clone() width="32" height="32" loading="lazy"/>
+                <a class="comment-author" href=format!("https://github.com/{}", comment.author) target="_blank" rel="noopener">
+                    {comment.author.clone()}
+                </a>
+                <a class="comment-time" href=comment.html_url target="_blank" rel="noopener">
+                    <time dateTime=datetime(comment.created_at).unwrap_or_default()>{comment.created_from_now}</time>
+                </a>
+            </div>
+            <div class="comment-body markdown-body" inner_html=comment.body_html></div>
+        </li>
     }
 }
 
+fn toc_list(cx: Scope, items: Vec<TocItem>) -> View {
+    view! {
+        cx,
+        <ul>
+            {items
+                .into_iter()
+                .map(|item| {
+                    let children = (!item.children.is_empty()).then(|| toc_list(cx, item.children));
+                    view! {
+                        cx,
+                        <li>
+                            <a href=format!("#{}", item.id)>{item.title}</a>
+                            {children}
+                        </li>
+                    }
+                })
+                .collect::<Vec<_>>()}
+        </ul>
+    }
+    .into_view(cx)
+}
+
 pub fn datetime(s: OffsetDateTime) -> anyhow::Result<String> {
     let format = format_description!("[year]-[month]-[day]T[hour padding:none]:[minute]:[second].[subsecond][offset_hour sign:mandatory]:[offset_minute]");
     Ok(s.format(&format)?)
 }
+/// ES 不可用、页面内容来自快照时显示在顶部
+#[allow(non_snake_case)]
+#[component]
+pub fn DegradedBanner(cx: Scope, message: String) -> impl IntoView {
+    view! {
+        cx,
+        <div class="alert alert-warning degraded" role="status">{message}</div>
+    }
+}
+
 // Blogs
 
 #[allow(non_snake_case)]
 #[component]
 pub fn BlogList(cx: Scope) -> impl IntoView {
-    let posts = create_resource(cx, || (), move |_| get_blogs(cx, None));
+    let home = create_resource(cx, || (), move |_| get_home(cx));
+    let featured_view = move || {
+        home.with(cx, |home| {
+            let featured = home.clone().map(|h| h.featured).unwrap_or_default();
+            (!featured.is_empty()).then(|| {
+                view! {
+                    cx,
+                    <section class="featured" aria-label="精选文章">
+                        <h2 class="featured-title">"精选"</h2>
+                        <ul class="featured-list">
+                            {featured
+                                .into_iter()
+                                .map(|post| {
+                                    view! {
+                                        cx,
+                                        <li class="featured-item">
+                                            <a href=format!("/blog/{}", post.number)>{post.title}</a>
+                                            <span class="post-meta">{post.created_from_now}</span>
+                                        </li>
+                                    }
+                                })
+                                .collect::<Vec<_>>()}
+                        </ul>
+                    </section>
+                }
+            })
+        })
+    };
+    let degraded_view = move || {
+        home.with(cx, |home| {
+            home.clone()
+                .ok()
+                .and_then(|h| h.degraded)
+                .map(|d| view! { cx, <DegradedBanner message=d/> })
+        })
+    };
     let posts_view = move || {
-        posts.with(cx, |posts| {
-            let posts = posts.clone().unwrap();
-            posts
-                .iter()
-                .map(move |post| {
-                    view! {
-                        cx,
-                        <BlogAbbr post=post.clone()/>
-                    }
-                })
-                .collect::<Vec<_>>()
+        home.with(cx, |home| match home.clone() {
+            Ok(home) => {
+                let pinned = home
+                    .pinned
+                    .into_iter()
+                    .map(|post| view! { cx, <BlogAbbr post=post pinned=true/> });
+                let posts = home
+                    .posts
+                    .into_iter()
+                    .map(|post| view! { cx, <BlogAbbr post=post/> });
+                pinned.chain(posts).collect::<Vec<_>>().into_view(cx)
+            }
+            Err(e) => {
+                view! { cx, <li class="alert alert-danger">{e.to_string()}</li> }.into_view(cx)
+            }
         })
     };
 
@@ -143,6 +594,8 @@ pub fn BlogList(cx: Scope) -> impl IntoView {
                 <div class="home">
                     <Title text="首页" />
                     <Suspense fallback=move || view! { cx, <p>"Loading..."</p> }>
+                        {degraded_view}
+                        {featured_view}
                         <ul class="post-list">
                             {posts_view}
                         </ul>
@@ -155,11 +608,19 @@ pub fn BlogList(cx: Scope) -> impl IntoView {
 
 #[allow(non_snake_case)]
 #[component]
-pub fn BlogAbbr(cx: Scope, #[prop()] post: BlogAbbrDisplay) -> impl IntoView {
+pub fn BlogAbbr(
+    cx: Scope,
+    #[prop()] post: BlogAbbrDisplay,
+    #[prop(optional)] pinned: bool,
+) -> impl IntoView {
     view! {
         cx,
-        <li>
-            <span class="post-meta">{ post.created_from_now }</span>
+        <li class:post-pinned=pinned>
+            {pinned.then(|| view! { cx, <span class="pinned-badge">"置顶"</span> })}
+            <span class="post-meta">
+                { post.created_from_now }
+                {post.reading.label().map(|r| format!(" • {}", r))}
+            </span>
             <h3><a href=format!("/blog/{}", post.number) class="post-link">{ post.title }</a></h3>
         </li>
     }
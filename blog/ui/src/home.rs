@@ -5,18 +5,21 @@ use leptos_router::*;
 use crate::blog::*;
 use crate::search::*;
 
+/// 站点标题，页面标题和导出的 feed 都用它
+pub const SITE_TITLE: &str = "黄进的个人网站 - HuangJ.in";
+
 #[allow(non_snake_case)]
 #[component]
 pub fn BlogApp(cx: Scope) -> impl IntoView {
     provide_meta_context(cx);
-    let formatter = |text| format!("{text} — 黄进的个人网站 - HuangJ.in");
+    let formatter = |text| format!("{text} — {SITE_TITLE}");
 
     view! {
             cx,
         <Html lang="zh-hans"/>
         <Title
       // reactively sets document.title when `name` changes
-      text="黄进的个人网站 - HuangJ.in"
+      text=SITE_TITLE
       // applies the `formatter` function to the `text` value
       formatter=formatter
     />
@@ -50,6 +53,14 @@ pub fn BlogApp(cx: Scope) -> impl IntoView {
                         cx,
                         <SingleBlog/>
                     } ssr=SsrMode::InOrder/> //Route
+                    <Route path="blog/:id/history" view=|cx| view! {
+                        cx,
+                        <BlogHistory/>
+                    } ssr=SsrMode::InOrder/> //Route
+                    <Route path="preview/:id" view=|cx| view! {
+                        cx,
+                        <PreviewBlog/>
+                    } ssr=SsrMode::InOrder/> //Route
                 </Routes>
             </div>
         </div>
@@ -16,9 +16,11 @@ pub fn SearchPage(cx: Scope) -> impl IntoView {
     let query_key = query.get().get("query").cloned().unwrap_or_default();
 
     let posts_view = move || {
-        posts.with(cx, |posts| {
-            let posts = posts.clone().unwrap();
-            posts
+        posts.with(cx, |posts| match posts {
+            Ok(posts) if posts.is_empty() => {
+                view! { cx, <li>"没有找到相关的文章"</li> }.into_view(cx)
+            }
+            Ok(posts) => posts
                 .iter()
                 .map(move |post| {
                     view! {
@@ -27,6 +29,10 @@ pub fn SearchPage(cx: Scope) -> impl IntoView {
                     }
                 })
                 .collect::<Vec<_>>()
+                .into_view(cx),
+            Err(e) => {
+                view! { cx, <li class="alert alert-danger">{e.to_string()}</li> }.into_view(cx)
+            }
         })
     };
     let title = format!("搜索: {}", &query_key);